@@ -0,0 +1,192 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use eframe::egui;
+
+use crate::Team;
+use crate::endgame::EndgameTables;
+use crate::position::Position;
+use crate::san::format_line;
+use crate::search::{MATE, SearchInfo, SearchLimits, SearchOptions, is_mate_score, search};
+use crate::syzygy::{TablebaseResult, Tablebases, piece_count};
+use crate::tt::TranspositionTable;
+use crate::zobrist;
+
+const ANALYSIS_HASH_MB: usize = 64;
+const MAX_LINES: usize = 10;
+const MAX_TABLEBASE_PIECES: usize = 7;
+
+// Runs an infinite search on a background thread for whatever position the board shows,
+// restarting whenever the position or the number of lines changes
+pub struct Analysis {
+    enabled: bool,
+    lines: usize,
+    analysed: Option<(u64, usize)>, // Zobrist key and line count of the running search
+    position: Position,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    latest: Arc<Mutex<Option<SearchInfo>>>,
+    tt: Arc<TranspositionTable>,
+    syzygy_path: String,
+    tablebases: Option<Arc<Tablebases>>,
+    tablebase_error: Option<String>,
+    endgame_tables: Option<Arc<EndgameTables>>,
+}
+
+impl Default for Analysis {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            lines: 3,
+            analysed: None,
+            position: Position::default(),
+            stop: Arc::new(AtomicBool::new(false)),
+            thread: None,
+            latest: Arc::new(Mutex::new(None)),
+            tt: Arc::new(TranspositionTable::new(ANALYSIS_HASH_MB)),
+            syzygy_path: String::new(),
+            tablebases: None,
+            tablebase_error: None,
+            endgame_tables: None,
+        }
+    }
+}
+
+impl Drop for Analysis {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// Scores are shown from White's side like most GUIs do, e.g. "+0.35" or "#-3"
+pub fn format_score(score: i32, current_team: Team) -> String {
+    let score = if current_team == Team::White { score } else { -score };
+    if is_mate_score(score) {
+        let moves = (MATE - score.abs() + 1) / 2;
+        format!("#{}", if score > 0 { moves } else { -moves })
+    } else {
+        format!("{:+.2}", score as f64 / 100.0)
+    }
+}
+
+impl Analysis {
+    fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.thread.take() {
+            handle.join().unwrap();
+        }
+        self.analysed = None;
+    }
+
+    fn start(&mut self, ctx: &egui::Context, position: &Position) {
+        self.stop();
+        *self.latest.lock().unwrap() = None;
+        self.analysed = Some((zobrist::hash(position), self.lines));
+        self.position = *position;
+
+        self.stop = Arc::new(AtomicBool::new(false));
+        let stop = self.stop.clone();
+        let latest = self.latest.clone();
+        let tt = self.tt.clone();
+        let ctx = ctx.clone();
+        let position = *position;
+        let options = SearchOptions {
+            threads: std::thread::available_parallelism().map_or(1, |count| count.get()),
+            multi_pv: self.lines,
+            tablebases: self.tablebases.clone(),
+            endgame_tables: self.endgame_tables.clone(),
+            nnue: None,
+        };
+        self.thread = Some(std::thread::spawn(move || {
+            let limits = SearchLimits { infinite: true, ..Default::default() };
+            search(&position, &limits, &options, &tt, stop, |info| {
+                *latest.lock().unwrap() = Some(info.clone());
+                ctx.request_repaint();
+            });
+        }));
+    }
+
+    // The endgame trainer's tables, which the search uses too once they're loaded
+    pub fn set_endgame_tables(&mut self, endgame_tables: Option<Arc<EndgameTables>>) {
+        let same = match (&self.endgame_tables, &endgame_tables) {
+            (Some(current), Some(new)) => Arc::ptr_eq(current, new),
+            (current, new) => current.is_none() && new.is_none(),
+        };
+        if !same {
+            self.endgame_tables = endgame_tables;
+            self.analysed = None;
+        }
+    }
+
+    fn show_tablebases(&mut self, ui: &mut egui::Ui, position: &Position) {
+        ui.horizontal(|ui| {
+            ui.label("Syzygy:");
+            ui.text_edit_singleline(&mut self.syzygy_path);
+            if ui.button("Load").clicked() {
+                match Tablebases::open(self.syzygy_path.trim()) {
+                    Ok(tablebases) => {
+                        self.tablebases = Some(Arc::new(tablebases));
+                        self.tablebase_error = None;
+                    }
+                    Err(error) => {
+                        self.tablebases = None;
+                        self.tablebase_error = Some(error);
+                    }
+                }
+                self.analysed = None; // Search again with or without the tables
+            }
+        });
+        if let Some(error) = &self.tablebase_error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+
+        let Some(tablebases) = &self.tablebases else {
+            return;
+        };
+        if piece_count(position) > MAX_TABLEBASE_PIECES {
+            return;
+        }
+        let text = match tablebases.probe(position) {
+            Some(TablebaseResult::Win(plies)) => format!("Tablebase win in {} for {:?}", plies, position.current_team),
+            Some(TablebaseResult::Loss(plies)) => format!("Tablebase loss in {} for {:?}", plies, position.current_team),
+            Some(TablebaseResult::Draw) => "Tablebase draw".to_string(),
+            None => return, // Tables for this material aren't loaded
+        };
+        ui.label(egui::RichText::new(text).strong())
+            .on_hover_text("Half-moves until the next capture or pawn move on the winning path (DTZ)");
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, position: &Position) {
+        ui.heading("Analysis");
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.enabled, "Analyse");
+            ui.label("Lines:");
+            ui.add(egui::DragValue::new(&mut self.lines).range(1..=MAX_LINES));
+        });
+        self.show_tablebases(ui, position);
+
+        if !self.enabled || position.legal_moves().is_empty() {
+            if self.thread.is_some() {
+                self.stop();
+            }
+            return;
+        }
+        if self.analysed != Some((zobrist::hash(position), self.lines)) {
+            self.start(ui.ctx(), position);
+        }
+
+        let Some(info) = self.latest.lock().unwrap().clone() else {
+            ui.label("Thinking...");
+            return;
+        };
+        ui.label(format!("Depth {}  {} nodes  {} kN/s", info.depth, info.nodes, info.nodes / info.time_ms.max(1)));
+        ui.separator();
+        for line in &info.lines {
+            ui.horizontal_wrapped(|ui| {
+                ui.label(egui::RichText::new(format_score(line.score, self.position.current_team)).strong().monospace());
+                ui.label(format_line(&self.position, &line.pv));
+            });
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use std::time::{Duration, Instant};
+
+use eframe::egui;
+
+use crate::theme::PieceSet;
+use crate::{Piece, PieceType, Team};
+
+// Bigger changes than castling plus a capture are jumps, such as a new game, and just appear
+const MAX_CHANGES: usize = 4;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Fade {
+    None,
+    Out, // Captured off a square that's left empty, as with en passant
+    In,  // Back on the board after stepping back over its capture
+}
+
+// One piece drawn over the board while it moves
+#[derive(Clone, Copy)]
+pub struct Sprite {
+    pub piece: Piece,
+    pub from: egui::Pos2, // Centre of where it starts
+    pub to: [isize; 2],
+    pub fade: Fade,
+}
+
+pub struct Animation {
+    started: Instant,
+    duration: Duration,
+    sprites: Vec<Sprite>,
+}
+
+fn same_piece(a: Piece, b: Piece) -> bool {
+    a.team == b.team && a.piece_type == b.piece_type
+}
+
+fn squares() -> impl Iterator<Item = [isize; 2]> {
+    (0..8).flat_map(|x| (0..8).map(move |y| [x, y]))
+}
+
+fn at(board: &[[Piece; 8]; 8], square: [isize; 2]) -> Piece {
+    board[square[0] as usize][square[1] as usize]
+}
+
+// How the pieces got from one board to the other: each piece that turns up on a square is
+// matched with the nearest one of its kind that left one (or of its side, for promotions),
+// and whatever's left over was captured or uncaptured. Returns (piece, from, to, fade)
+pub fn board_changes(old: &[[Piece; 8]; 8], new: &[[Piece; 8]; 8]) -> Vec<(Piece, [isize; 2], [isize; 2], Fade)> {
+    let changed = |square| !same_piece(at(old, square), at(new, square));
+    let mut left: Vec<[isize; 2]> = squares().filter(|square| changed(*square) && at(old, *square).team != Team::None).collect();
+    let arrived: Vec<[isize; 2]> = squares().filter(|square| changed(*square) && at(new, *square).team != Team::None).collect();
+
+    let mut changes = vec![];
+    for to in arrived {
+        let piece = at(new, to);
+        let distance = |from: &[isize; 2]| (from[0] - to[0]).abs().max((from[1] - to[1]).abs());
+        let nearest = |matches: &dyn Fn(Piece) -> bool| {
+            left.iter().enumerate().filter(|(_, from)| matches(at(old, **from))).min_by_key(|(_, from)| distance(from)).map(|(index, _)| index)
+        };
+        let promoted = |other: Piece| other.team == piece.team && (other.piece_type == PieceType::Pawn || piece.piece_type == PieceType::Pawn);
+        match nearest(&|other| same_piece(other, piece)).or_else(|| nearest(&promoted)) {
+            Some(index) => changes.push((piece, left.remove(index), to, Fade::None)),
+            None => changes.push((piece, to, to, Fade::In)),
+        }
+    }
+    // A captured piece only shows fading away when nothing took its place
+    for from in left {
+        if at(new, from).team == Team::None {
+            changes.push((at(old, from), from, from, Fade::Out));
+        }
+    }
+    changes
+}
+
+fn ease_out(t: f32) -> f32 {
+    1.0 - (1.0 - t).powi(3)
+}
+
+impl Animation {
+    pub fn new(sprites: Vec<Sprite>, duration: Duration) -> Option<Animation> {
+        if sprites.is_empty() || duration.is_zero() {
+            return None;
+        }
+        Some(Animation { started: Instant::now(), duration, sprites })
+    }
+
+    // Slides between two boards, given where each square was drawn
+    pub fn between(old: &[[Piece; 8]; 8], new: &[[Piece; 8]; 8], rects: &[[egui::Rect; 8]; 8], duration: Duration) -> Option<Animation> {
+        let changes = board_changes(old, new);
+        if changes.len() > MAX_CHANGES || rects[0][0] == egui::Rect::NOTHING {
+            return None;
+        }
+        let sprites = changes
+            .into_iter()
+            .map(|(piece, from, to, fade)| Sprite { piece, from: rects[from[0] as usize][from[1] as usize].center(), to, fade })
+            .collect();
+        Animation::new(sprites, duration)
+    }
+
+    // The board leaves these squares empty, since the piece on them is still on its way
+    pub fn hides(&self, square: [isize; 2]) -> bool {
+        self.sprites.iter().any(|sprite| sprite.to == square && sprite.fade != Fade::Out)
+    }
+
+    // For a piece that was dropped into place rather than needing to slide there
+    pub fn skip(&mut self, square: [isize; 2]) {
+        self.sprites.retain(|sprite| sprite.to != square || sprite.fade == Fade::Out);
+    }
+
+    // Draws the moving pieces on top of the board, returning false once they've arrived
+    pub fn show(&self, ctx: &egui::Context, rects: &[[egui::Rect; 8]; 8], pieces: &PieceSet) -> bool {
+        let t = self.started.elapsed().as_secs_f32() / self.duration.as_secs_f32();
+        if t >= 1.0 {
+            ctx.request_repaint();
+            return false;
+        }
+        egui::Area::new(egui::Id::new("moving pieces")).order(egui::Order::Foreground).fixed_pos(egui::Pos2::ZERO).interactable(false).show(ctx, |ui| {
+            for sprite in &self.sprites {
+                let target = rects[sprite.to[0] as usize][sprite.to[1] as usize];
+                let centre = sprite.from.lerp(target.center(), ease_out(t));
+                let alpha = match sprite.fade {
+                    Fade::None => 1.0,
+                    Fade::Out => 1.0 - t,
+                    Fade::In => t,
+                };
+                pieces.picture(sprite.piece, target.size())
+                    .tint(egui::Color32::WHITE.gamma_multiply(alpha))
+                    .paint_at(ui, egui::Rect::from_center_size(centre, target.size()));
+            }
+        });
+        ctx.request_repaint();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::Position;
+
+    fn changes_after(fen: &str, uci: &str) -> Vec<(PieceType, [isize; 2], [isize; 2], Fade)> {
+        let position = Position::from_fen(fen).unwrap();
+        let next = position.make_move(position.parse_uci_move(uci).unwrap());
+        let mut changes: Vec<_> = board_changes(&position.board, &next.board)
+            .into_iter()
+            .map(|(piece, from, to, fade)| (piece.piece_type, from, to, fade))
+            .collect();
+        changes.sort_by_key(|change| change.1);
+        changes
+    }
+
+    #[test]
+    fn test_moves_are_worked_out_from_the_boards() {
+        // Castling moves the rook too
+        let castling = changes_after("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1g1");
+        assert_eq!(castling, [(PieceType::King, [4, 0], [6, 0], Fade::None), (PieceType::Rook, [7, 0], [5, 0], Fade::None)]);
+
+        // En passant takes the pawn off a square nothing moves onto
+        let en_passant = changes_after("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6");
+        assert_eq!(en_passant, [(PieceType::Pawn, [3, 4], [3, 4], Fade::Out), (PieceType::Pawn, [4, 4], [3, 5], Fade::None)]);
+
+        // A capturing promotion slides the pawn over as a queen, and the rook just goes
+        let promotion = changes_after("1r2k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7b8q");
+        assert_eq!(promotion, [(PieceType::Queen, [0, 6], [1, 7], Fade::None)]);
+
+        // Going back over a capture brings the piece back
+        let position = Position::from_fen("4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1").unwrap();
+        let next = position.make_move(position.parse_uci_move("e4d5").unwrap());
+        let back = board_changes(&next.board, &position.board);
+        assert!(back.iter().any(|(piece, _, to, fade)| piece.team == Team::Black && *to == [3, 4] && *fade == Fade::In));
+        assert!(back.iter().any(|(piece, from, to, _)| piece.team == Team::White && *from == [3, 4] && *to == [4, 3]));
+    }
+}
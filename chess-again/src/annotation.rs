@@ -0,0 +1,171 @@
+use eframe::egui;
+use egui::Color32;
+
+use crate::position::{parse_square, square_name};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Colour {
+    Green,
+    Red,
+    Yellow,
+    Blue,
+}
+
+impl Colour {
+    // Plain right-drags are green; Shift or Ctrl make red, Alt blue, and both yellow
+    pub fn from_modifiers(modifiers: egui::Modifiers) -> Colour {
+        match (modifiers.shift || modifiers.command, modifiers.alt) {
+            (false, false) => Colour::Green,
+            (true, false) => Colour::Red,
+            (false, true) => Colour::Blue,
+            (true, true) => Colour::Yellow,
+        }
+    }
+
+    fn letter(self) -> char {
+        match self {
+            Colour::Green => 'G',
+            Colour::Red => 'R',
+            Colour::Yellow => 'Y',
+            Colour::Blue => 'B',
+        }
+    }
+
+    fn from_letter(letter: char) -> Option<Colour> {
+        match letter {
+            'G' => Some(Colour::Green),
+            'R' => Some(Colour::Red),
+            'Y' => Some(Colour::Yellow),
+            'B' => Some(Colour::Blue),
+            _ => None,
+        }
+    }
+
+    fn paint_colour(self) -> Color32 {
+        match self {
+            Colour::Green => Color32::from_rgba_unmultiplied(21, 120, 27, 170),
+            Colour::Red => Color32::from_rgba_unmultiplied(136, 32, 32, 170),
+            Colour::Yellow => Color32::from_rgba_unmultiplied(230, 143, 0, 170),
+            Colour::Blue => Color32::from_rgba_unmultiplied(0, 48, 136, 170),
+        }
+    }
+}
+
+// An arrow, or a circled square when both ends are the same
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Annotation {
+    pub colour: Colour,
+    pub from: [isize; 2],
+    pub to: [isize; 2],
+}
+
+impl Annotation {
+    pub fn is_circle(&self) -> bool {
+        self.from == self.to
+    }
+}
+
+// Takes the [%csl Rd4] and [%cal Gd2d4,Re7e5] commands out of a PGN comment, returning
+// the rest of the text and what they drew
+pub fn split_comment(text: &str) -> (String, Vec<Annotation>) {
+    let mut rest = String::new();
+    let mut annotations = vec![];
+    let mut remaining = text;
+    while let Some(start) = remaining.find("[%") {
+        let Some(length) = remaining[start..].find(']') else {
+            break;
+        };
+        let command = &remaining[start + 2..start + length];
+        rest.push_str(&remaining[..start]);
+        match command.split_once(' ') {
+            Some(("csl" | "cal", items)) => annotations.extend(items.split(',').filter_map(|item| parse_item(item.trim()))),
+            // Clock times, evaluations and the like are kept as they are
+            _ => rest.push_str(&remaining[start..=start + length]),
+        }
+        remaining = &remaining[start + length + 1..];
+    }
+    rest.push_str(remaining);
+    (rest.split_whitespace().collect::<Vec<_>>().join(" "), annotations)
+}
+
+// "Rd4" or "Gd2d4"
+fn parse_item(item: &str) -> Option<Annotation> {
+    let colour = Colour::from_letter(item.chars().next()?)?;
+    let squares = item.get(1..)?;
+    let from = parse_square(squares.get(..2)?)?;
+    let to = match squares.get(2..) {
+        Some("") => from,
+        Some(square) => parse_square(square)?,
+        None => return None,
+    };
+    Some(Annotation { colour, from, to })
+}
+
+// The commands to write into a comment, or nothing when there are no annotations
+pub fn format_commands(annotations: &[Annotation]) -> String {
+    let item = |annotation: &Annotation| {
+        let to = if annotation.is_circle() { String::new() } else { square_name(annotation.to) };
+        format!("{}{}{}", annotation.colour.letter(), square_name(annotation.from), to)
+    };
+    let (circles, arrows): (Vec<&Annotation>, Vec<&Annotation>) = annotations.iter().partition(|annotation| annotation.is_circle());
+    let mut commands = vec![];
+    for (name, list) in [("csl", circles), ("cal", arrows)] {
+        if !list.is_empty() {
+            let items: Vec<String> = list.into_iter().map(item).collect();
+            commands.push(format!("[%{} {}]", name, items.join(",")));
+        }
+    }
+    commands.join(" ")
+}
+
+// Adds the annotation, or takes it off if it's already there in that colour
+pub fn toggle(annotations: &mut Vec<Annotation>, annotation: Annotation) {
+    let existing = annotations.iter().position(|other| other.from == annotation.from && other.to == annotation.to);
+    match existing {
+        Some(index) if annotations[index].colour == annotation.colour => {
+            annotations.remove(index);
+        }
+        Some(index) => annotations[index] = annotation,
+        None => annotations.push(annotation),
+    }
+}
+
+pub fn paint(painter: &egui::Painter, annotation: Annotation, rects: &[[egui::Rect; 8]; 8]) {
+    let from = rects[annotation.from[0] as usize][annotation.from[1] as usize];
+    let to = rects[annotation.to[0] as usize][annotation.to[1] as usize];
+    let colour = annotation.colour.paint_colour();
+    let size = from.width();
+    if annotation.is_circle() {
+        painter.circle_stroke(from.center(), size * 0.44, egui::Stroke::new(size * 0.07, colour));
+        return;
+    }
+    // The shaft stops where the head starts, so the overlapping alpha doesn't show
+    let direction = (to.center() - from.center()).normalized();
+    let head = size * 0.4;
+    let tip = to.center() - direction * size * 0.1;
+    let base = tip - direction * head;
+    painter.line_segment([from.center() + direction * size * 0.25, base], egui::Stroke::new(size * 0.16, colour));
+    let side = direction.rot90() * head * 0.6;
+    painter.add(egui::Shape::convex_polygon(vec![tip, base + side, base - side], colour, egui::Stroke::NONE));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_comment_commands_round_trip() {
+        let (text, annotations) = split_comment("Good move [%csl Rd4,Ge5] [%clk 0:05:00] and [%cal Gd2d4, Bg1f3]");
+        assert_eq!(text, "Good move [%clk 0:05:00] and");
+        assert_eq!(annotations.len(), 4);
+        assert_eq!(annotations[0], Annotation { colour: Colour::Red, from: [3, 3], to: [3, 3] });
+        assert_eq!(annotations[3], Annotation { colour: Colour::Blue, from: [6, 0], to: [5, 2] });
+        assert_eq!(format_commands(&annotations), "[%csl Rd4,Ge5] [%cal Gd2d4,Bg1f3]");
+        assert_eq!(format_commands(&[]), "");
+
+        let mut annotations = annotations;
+        toggle(&mut annotations, Annotation { colour: Colour::Red, from: [3, 3], to: [3, 3] });
+        toggle(&mut annotations, Annotation { colour: Colour::Yellow, from: [4, 4], to: [4, 4] });
+        assert_eq!(format_commands(&annotations), "[%csl Ye5] [%cal Gd2d4,Bg1f3]");
+    }
+}
@@ -0,0 +1,1058 @@
+// Plays UCI engines against each other: `match` for two engines with an SPRT stop, and
+// `tournament` for round robins and gauntlets.
+// Engines are given cutechess style, e.g.
+//   match --engine cmd=self name=new --engine cmd=./old option.Hash=64 --tc 10+0.1 --games 200
+// where `cmd=self` runs this executable in UCI mode, `arg=` adds a command line argument
+// (repeatable, e.g. arg=--eval arg=params.txt) and `option.Name=value` is sent with setoption.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use rand::SeedableRng;
+use rand::seq::IndexedRandom;
+
+use crate::Team;
+use crate::pgn::{format_header, parse_pgn, wrap_movetext};
+use crate::position::{ChessMove, Position, START_FEN};
+use crate::san::{move_to_san, parse_san};
+use crate::zobrist;
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+const RANDOM_OPENING_PLIES: usize = 8;
+
+#[derive(Clone)]
+struct EngineSpec {
+    name: String,
+    command: String,
+    args: Vec<String>,
+    options: Vec<(String, String)>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct TimeControl {
+    base_ms: u64,
+    increment_ms: u64,
+}
+
+// Where a game starts: a position and any moves already played from it
+#[derive(Clone)]
+struct Opening {
+    fen: String,
+    moves: Vec<ChessMove>,
+}
+
+struct GameRecord {
+    white: String,
+    black: String,
+    opening: Opening,
+    moves: Vec<ChessMove>,       // After the opening
+    scores: Vec<Option<String>>, // What the engine said about each of its moves, as in "cp 35"
+    result: f64,                 // For White: 1, 0.5 or 0
+    reason: String,
+}
+
+// W/D/L from the first engine's side
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+struct Score {
+    wins: u32,
+    draws: u32,
+    losses: u32,
+}
+
+impl Score {
+    fn add(&mut self, result: f64) {
+        if result == 1.0 {
+            self.wins += 1;
+        } else if result == 0.0 {
+            self.losses += 1;
+        } else {
+            self.draws += 1;
+        }
+    }
+
+    fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    fn points(&self) -> f64 {
+        self.wins as f64 + self.draws as f64 / 2.0
+    }
+
+    fn mean_and_variance(&self) -> (f64, f64) {
+        let games = self.games() as f64;
+        let mean = self.points() / games;
+        let variance = (self.wins as f64 * (1.0 - mean).powi(2) + self.draws as f64 * (0.5 - mean).powi(2) + self.losses as f64 * mean.powi(2)) / games;
+        (mean, variance)
+    }
+
+    // The Elo difference and the half width of its 95% confidence interval
+    fn elo(&self) -> Option<(f64, f64)> {
+        if self.games() == 0 {
+            return None;
+        }
+        let (mean, variance) = self.mean_and_variance();
+        let error = 1.96 * (variance / self.games() as f64).sqrt();
+        let (low, high) = (elo_from_score(mean - error), elo_from_score(mean + error));
+        Some((elo_from_score(mean), (high - low) / 2.0))
+    }
+
+    // Log-likelihood ratio of "the first engine is elo1 stronger" against "it is elo0
+    // stronger", using the normal approximation to the trinomial GSPRT
+    fn llr(&self, elo0: f64, elo1: f64) -> f64 {
+        if self.games() == 0 {
+            return 0.0;
+        }
+        let (mean, variance) = self.mean_and_variance();
+        if variance <= 0.0 {
+            return 0.0; // All the same result so far, too early to say anything
+        }
+        let (score0, score1) = (score_from_elo(elo0), score_from_elo(elo1));
+        self.games() as f64 * (score1 - score0) * (2.0 * mean - score0 - score1) / (2.0 * variance)
+    }
+}
+
+fn elo_from_score(score: f64) -> f64 {
+    let score = score.clamp(1e-6, 1.0 - 1e-6);
+    400.0 * (score / (1.0 - score)).log10()
+}
+
+fn score_from_elo(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Sprt {
+    elo0: f64,
+    elo1: f64,
+    alpha: f64,
+    beta: f64,
+}
+
+#[derive(PartialEq, Debug)]
+enum SprtDecision {
+    AcceptH0,
+    AcceptH1,
+    Continue,
+}
+
+impl Sprt {
+    fn bounds(&self) -> (f64, f64) {
+        ((self.beta / (1.0 - self.alpha)).ln(), ((1.0 - self.beta) / self.alpha).ln())
+    }
+
+    fn decide(&self, score: &Score) -> SprtDecision {
+        let llr = score.llr(self.elo0, self.elo1);
+        let (lower, upper) = self.bounds();
+        if llr >= upper {
+            SprtDecision::AcceptH1
+        } else if llr <= lower {
+            SprtDecision::AcceptH0
+        } else {
+            SprtDecision::Continue
+        }
+    }
+}
+
+// "60" or "10+0.1", in seconds
+fn parse_time_control(text: &str) -> Option<TimeControl> {
+    let (base, increment) = text.split_once('+').unwrap_or((text, "0"));
+    let seconds = |text: &str| text.parse::<f64>().ok().filter(|value| *value >= 0.0).map(|value| (value * 1000.0).round() as u64);
+    Some(TimeControl { base_ms: seconds(base)?, increment_ms: seconds(increment)? })
+}
+
+// The words after --engine, up to the next flag
+fn parse_engine(words: &[String]) -> Result<EngineSpec, String> {
+    let mut spec = EngineSpec { name: String::new(), command: String::new(), args: vec![], options: vec![] };
+    for word in words {
+        let Some((key, value)) = word.split_once('=') else {
+            return Err(format!("Expected key=value in engine settings, got \"{}\"", word));
+        };
+        match key {
+            "cmd" => spec.command = value.to_string(),
+            "name" => spec.name = value.to_string(),
+            "arg" => spec.args.push(value.to_string()),
+            _ if let Some(option) = key.strip_prefix("option.") => spec.options.push((option.to_string(), value.to_string())),
+            _ => return Err(format!("Unknown engine setting \"{}\"", key)),
+        }
+    }
+    if spec.command.is_empty() {
+        return Err("Every engine needs cmd=<path> (or cmd=self)".to_string());
+    }
+    if spec.name.is_empty() {
+        spec.name = std::path::Path::new(&spec.command).file_name().map_or(spec.command.clone(), |name| name.to_string_lossy().to_string());
+    }
+    Ok(spec)
+}
+
+// Lines of FEN/EPD, or PGN games whose moves are played out first
+fn load_openings(path: &str) -> Result<Vec<Opening>, String> {
+    let text = std::fs::read_to_string(path).map_err(|error| format!("Can't read {}: {}", path, error))?;
+    let mut openings = vec![];
+    if path.ends_with(".pgn") {
+        for game in parse_pgn(&text) {
+            let fen = game.header("FEN").unwrap_or(START_FEN).to_string();
+            let mut position = Position::from_fen(&fen)?;
+            let mut moves = vec![];
+            for san in &game.moves {
+                let Some(chess_move) = parse_san(&position, san) else {
+                    break;
+                };
+                position = position.make_move(chess_move);
+                moves.push(chess_move);
+            }
+            openings.push(Opening { fen, moves });
+        }
+    } else {
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let fields: Vec<&str> = line.split_whitespace().take(4).collect();
+            let fen = fields.join(" ");
+            Position::from_fen(&fen)?;
+            openings.push(Opening { fen, moves: vec![] });
+        }
+    }
+    if openings.is_empty() {
+        return Err(format!("No openings in {}", path));
+    }
+    Ok(openings)
+}
+
+fn random_opening(rng: &mut impl rand::Rng) -> Opening {
+    loop {
+        let mut position = Position::default();
+        let mut moves = vec![];
+        for _ in 0..RANDOM_OPENING_PLIES {
+            let Some(chess_move) = position.legal_moves().choose(rng).copied() else {
+                break;
+            };
+            position = position.make_move(chess_move);
+            moves.push(chess_move);
+        }
+        if !position.legal_moves().is_empty() {
+            return Opening { fen: START_FEN.to_string(), moves };
+        }
+    }
+}
+
+struct UciEngine {
+    process: Child,
+    input: ChildStdin,
+    output: Receiver<String>,
+}
+
+impl UciEngine {
+    fn start(spec: &EngineSpec) -> Result<UciEngine, String> {
+        let mut command = if spec.command == "self" {
+            let mut command = Command::new(std::env::current_exe().map_err(|error| error.to_string())?);
+            command.args(&spec.args).arg("uci");
+            command
+        } else {
+            let mut command = Command::new(&spec.command);
+            command.args(&spec.args);
+            command
+        };
+        let mut process = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|error| format!("Can't start {}: {}", spec.command, error))?;
+        let input = process.stdin.take().unwrap();
+        let stdout = process.stdout.take().unwrap();
+
+        // A thread per engine turns its output into lines we can wait on with a timeout
+        let (sender, output) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut engine = UciEngine { process, input, output };
+        engine.send("uci")?;
+        engine.wait_for("uciok", STARTUP_TIMEOUT)?;
+        for (name, value) in &spec.options {
+            engine.send(&format!("setoption name {} value {}", name, value))?;
+        }
+        engine.ready()?;
+        Ok(engine)
+    }
+
+    fn send(&mut self, command: &str) -> Result<(), String> {
+        writeln!(self.input, "{}", command).and_then(|_| self.input.flush()).map_err(|error| format!("Engine went away: {}", error))
+    }
+
+    // Reads lines until one starts with `prefix`, and returns it along with the last score seen
+    fn wait_for(&mut self, prefix: &str, timeout: Duration) -> Result<(String, Option<String>), String> {
+        let deadline = Instant::now() + timeout;
+        let mut score = None;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.output.recv_timeout(left) {
+                Ok(line) if line.starts_with(prefix) => return Ok((line, score)),
+                Ok(line) => {
+                    if let Some((_, rest)) = line.split_once(" score ") {
+                        score = Some(rest.split_whitespace().take(2).collect::<Vec<_>>().join(" "));
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => return Err("timeout".to_string()),
+                Err(RecvTimeoutError::Disconnected) => return Err("disconnected".to_string()),
+            }
+        }
+    }
+
+    fn ready(&mut self) -> Result<(), String> {
+        self.send("isready")?;
+        self.wait_for("readyok", STARTUP_TIMEOUT).map(|_| ())
+    }
+
+    fn new_game(&mut self) -> Result<(), String> {
+        self.send("ucinewgame")?;
+        self.ready()
+    }
+}
+
+impl Drop for UciEngine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let deadline = Instant::now() + Duration::from_millis(500);
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = self.process.try_wait() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+// Checkmate, stalemate and the draw rules; None while the game goes on
+fn game_over(position: &Position, repetitions: &HashMap<u64, u32>) -> Option<(f64, &'static str)> {
+    if position.legal_moves().is_empty() {
+        if !position.in_check() {
+            return Some((0.5, "stalemate"));
+        }
+        return Some((if position.current_team == Team::White { 0.0 } else { 1.0 }, "checkmate"));
+    }
+    if position.halfmove_clock >= 100 {
+        return Some((0.5, "fifty move rule"));
+    }
+    if position.insufficient_material() {
+        return Some((0.5, "insufficient material"));
+    }
+    if repetitions.get(&zobrist::hash(position)).is_some_and(|count| *count >= 3) {
+        return Some((0.5, "threefold repetition"));
+    }
+    None
+}
+
+// `engines` are White and Black. An engine that crashes, runs out of time or plays an illegal
+// move loses.
+fn play_game(mut engines: [&mut UciEngine; 2], names: [&str; 2], opening: &Opening, time_control: TimeControl, margin_ms: u64) -> GameRecord {
+    let mut record = GameRecord {
+        white: names[0].to_string(),
+        black: names[1].to_string(),
+        opening: opening.clone(),
+        moves: vec![],
+        scores: vec![],
+        result: 0.5,
+        reason: String::new(),
+    };
+    for engine in engines.iter_mut() {
+        if let Err(error) = engine.new_game() {
+            record.reason = format!("engine failed to start a game ({})", error);
+            return record;
+        }
+    }
+
+    let mut position = Position::from_fen(&opening.fen).unwrap_or_default();
+    let mut repetitions: HashMap<u64, u32> = HashMap::new();
+    let mut uci_moves: Vec<String> = vec![];
+    for chess_move in &opening.moves {
+        *repetitions.entry(zobrist::hash(&position)).or_insert(0) += 1;
+        position = position.make_move(*chess_move);
+        uci_moves.push(chess_move.to_uci());
+    }
+    let mut clocks = [time_control.base_ms; 2];
+
+    loop {
+        *repetitions.entry(zobrist::hash(&position)).or_insert(0) += 1;
+        if let Some((result, reason)) = game_over(&position, &repetitions) {
+            record.result = result;
+            record.reason = reason.to_string();
+            return record;
+        }
+
+        let side = if position.current_team == Team::White { 0 } else { 1 };
+        let loses = if side == 0 { 0.0 } else { 1.0 };
+        let engine = &mut engines[side];
+        let moves = if uci_moves.is_empty() { String::new() } else { format!(" moves {}", uci_moves.join(" ")) };
+        let go = format!(
+            "go wtime {} btime {} winc {} binc {}",
+            clocks[0], clocks[1], time_control.increment_ms, time_control.increment_ms
+        );
+        let started = Instant::now();
+        let reply = engine
+            .send(&format!("position fen {}{}", opening.fen, moves))
+            .and_then(|_| engine.send(&go))
+            .and_then(|_| engine.wait_for("bestmove", Duration::from_millis(clocks[side] + margin_ms)));
+        let used = started.elapsed().as_millis() as u64;
+
+        let (line, score) = match reply {
+            Ok(reply) => reply,
+            Err(error) => {
+                record.result = loses;
+                record.reason = if error == "timeout" { format!("{} loses on time", names[side]) } else { format!("{} {}", names[side], error) };
+                return record;
+            }
+        };
+        if used > clocks[side] + margin_ms {
+            record.result = loses;
+            record.reason = format!("{} loses on time", names[side]);
+            return record;
+        }
+        clocks[side] = clocks[side].saturating_sub(used) + time_control.increment_ms;
+
+        let text = line.split_whitespace().nth(1).unwrap_or("");
+        let Some(chess_move) = position.parse_uci_move(text) else {
+            record.result = loses;
+            record.reason = format!("{} plays an illegal move {}", names[side], text);
+            return record;
+        };
+        position = position.make_move(chess_move);
+        uci_moves.push(text.to_string());
+        record.moves.push(chess_move);
+        record.scores.push(score);
+    }
+}
+
+fn result_text(result: f64) -> &'static str {
+    match result {
+        1.0 => "1-0",
+        0.0 => "0-1",
+        _ => "1/2-1/2",
+    }
+}
+
+// "cp 35" becomes +0.35 and "mate -3" becomes -M3, from the side that moved
+fn score_comment(score: &str) -> Option<String> {
+    let (kind, value) = score.split_once(' ')?;
+    let value: i32 = value.parse().ok()?;
+    let sign = if value < 0 { "-" } else { "+" };
+    match kind {
+        "cp" => Some(format!("{}{:.2}", sign, value.abs() as f64 / 100.0)),
+        "mate" => Some(format!("{}M{}", sign, value.abs())),
+        _ => None,
+    }
+}
+
+// Days since 1970 to a calendar date, for the PGN Date tag
+fn civil_date(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    (era * 400 + year_of_era + (month <= 2) as i64, month, day)
+}
+
+fn pgn_game(record: &GameRecord, event: &str, round: &str, time_control: TimeControl) -> String {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs() / 86_400) as i64;
+    let (year, month, day) = civil_date(days);
+    let mut text = String::new();
+    let mut headers = vec![
+        ("Event", event.to_string()),
+        ("Site", "?".to_string()),
+        ("Date", format!("{:04}.{:02}.{:02}", year, month, day)),
+        ("Round", round.to_string()),
+        ("White", record.white.clone()),
+        ("Black", record.black.clone()),
+        ("Result", result_text(record.result).to_string()),
+    ];
+    if record.opening.fen != START_FEN {
+        headers.push(("SetUp", "1".to_string()));
+        headers.push(("FEN", record.opening.fen.clone()));
+    }
+    headers.push(("TimeControl", format!("{}+{}", time_control.base_ms as f64 / 1000.0, time_control.increment_ms as f64 / 1000.0)));
+    for (name, value) in headers {
+        text.push_str(&format_header(name, &value));
+    }
+    text.push('\n');
+
+    // The opening moves go in without comments, then each engine move with its score
+    let mut tokens: Vec<String> = vec![];
+    let mut position = Position::from_fen(&record.opening.fen).unwrap_or_default();
+    let scores = std::iter::repeat_n(&None, record.opening.moves.len()).chain(&record.scores);
+    for (i, (chess_move, score)) in record.opening.moves.iter().chain(&record.moves).zip(scores).enumerate() {
+        if position.current_team == Team::White {
+            tokens.push(format!("{}.", position.fullmove_number));
+        } else if i == 0 {
+            tokens.push(format!("{}...", position.fullmove_number));
+        }
+        tokens.push(move_to_san(&position, *chess_move));
+        if let Some(comment) = score.as_deref().and_then(score_comment) {
+            tokens.push(format!("{{{}}}", comment));
+        }
+        position = position.make_move(*chess_move);
+    }
+    tokens.push(format!("{{{}}}", record.reason));
+    tokens.push(result_text(record.result).to_string());
+
+    text.push_str(&wrap_movetext(&tokens));
+    text.push_str("\n\n");
+    text
+}
+
+fn append(path: &str, text: &str) -> Result<(), String> {
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(text.as_bytes()))
+        .map_err(|error| format!("Can't write {}: {}", path, error))
+}
+
+// One game of an event, with the engines as indices into the engine list
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Pairing {
+    number: usize,
+    round: usize,
+    white: usize,
+    black: usize,
+    opening: usize,
+}
+
+struct ArenaSettings {
+    engines: Vec<EngineSpec>,
+    games: usize,
+    rounds: usize,
+    gauntlet: bool,
+    time_control: TimeControl,
+    margin_ms: u64,
+    concurrency: usize,
+    openings: Option<Vec<Opening>>,
+    sprt: Option<Sprt>,
+    pgn: Option<String>,
+}
+
+fn parse_args(args: &[String]) -> Result<ArenaSettings, String> {
+    let mut settings = ArenaSettings {
+        engines: vec![],
+        games: 100,
+        rounds: 1,
+        gauntlet: false,
+        time_control: TimeControl { base_ms: 10_000, increment_ms: 100 },
+        margin_ms: 100,
+        concurrency: 1,
+        openings: None,
+        sprt: None,
+        pgn: None,
+    };
+    let mut i = 0;
+    while i < args.len() {
+        // Everything up to the next flag belongs to this one
+        let end = args[i + 1..].iter().position(|arg| arg.starts_with("--")).map_or(args.len(), |offset| i + 1 + offset);
+        let values = &args[i + 1..end];
+        let number = |index: usize| values.get(index).and_then(|value| value.parse::<f64>().ok()).ok_or(format!("{} needs a number", args[i]));
+        match args[i].as_str() {
+            "--engine" => settings.engines.push(parse_engine(values)?),
+            "--games" => settings.games = number(0)? as usize,
+            "--rounds" => settings.rounds = (number(0)? as usize).max(1),
+            "--gauntlet" => settings.gauntlet = true,
+            "--tc" => settings.time_control = values.first().and_then(|value| parse_time_control(value)).ok_or("--tc needs seconds or seconds+increment")?,
+            "--timemargin" => settings.margin_ms = number(0)? as u64,
+            "--concurrency" => settings.concurrency = (number(0)? as usize).max(1),
+            "--openings" => settings.openings = Some(load_openings(values.first().ok_or("--openings needs a file")?)?),
+            "--pgn" => settings.pgn = Some(values.first().ok_or("--pgn needs a file")?.clone()),
+            "--sprt" => {
+                settings.sprt = Some(Sprt {
+                    elo0: number(0)?,
+                    elo1: number(1)?,
+                    alpha: values.get(2).and_then(|value| value.parse().ok()).unwrap_or(0.05),
+                    beta: values.get(3).and_then(|value| value.parse().ok()).unwrap_or(0.05),
+                });
+            }
+            other => return Err(format!("Unknown option {}", other)),
+        }
+        i = end;
+    }
+    let mut names: Vec<&str> = settings.engines.iter().map(|spec| spec.name.as_str()).collect();
+    names.sort();
+    if names.windows(2).any(|pair| pair[0] == pair[1]) {
+        return Err("Engines need different names, set them with name=".to_string());
+    }
+    Ok(settings)
+}
+
+// Plays the games on `settings.concurrency` threads, each starting the engines it needs as it
+// goes. `finished` sees every game as it ends and can stop the rest by returning true.
+fn run_games(settings: &ArenaSettings, schedule: &[Pairing], openings: &[Opening], mut finished: impl FnMut(Pairing, GameRecord) -> bool) {
+    let next_game = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel::<(Pairing, GameRecord)>();
+    std::thread::scope(|scope| {
+        for _ in 0..settings.concurrency.min(schedule.len()) {
+            let (sender, next_game, stop) = (sender.clone(), &next_game, &stop);
+            scope.spawn(move || {
+                let mut engines: Vec<Option<UciEngine>> = settings.engines.iter().map(|_| None).collect();
+                loop {
+                    let slot = next_game.fetch_add(1, Ordering::Relaxed);
+                    if slot >= schedule.len() || stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    let pairing = schedule[slot];
+                    for index in [pairing.white, pairing.black] {
+                        if engines[index].is_none() {
+                            match UciEngine::start(&settings.engines[index]) {
+                                Ok(engine) => engines[index] = Some(engine),
+                                Err(error) => {
+                                    println!("{}", error);
+                                    stop.store(true, Ordering::Relaxed);
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                    let Ok([Some(white), Some(black)]) = engines.get_disjoint_mut([pairing.white, pairing.black]) else {
+                        break;
+                    };
+                    let names = [settings.engines[pairing.white].name.as_str(), settings.engines[pairing.black].name.as_str()];
+                    let opening = &openings[pairing.opening % openings.len()];
+                    let record = play_game([white, black], names, opening, settings.time_control, settings.margin_ms);
+                    if sender.send((pairing, record)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        for (pairing, record) in receiver {
+            if !stop.load(Ordering::Relaxed) && finished(pairing, record) {
+                stop.store(true, Ordering::Relaxed);
+            }
+        }
+    });
+}
+
+fn print_score(names: [&str; 2], score: &Score, sprt: Option<Sprt>) {
+    println!(
+        "Score of {} vs {}: {} - {} - {}  [{:.3}] {}",
+        names[0],
+        names[1],
+        score.wins,
+        score.losses,
+        score.draws,
+        score.points() / score.games().max(1) as f64,
+        score.games()
+    );
+    if let Some((elo, error)) = score.elo() {
+        println!("Elo difference: {:.1} +/- {:.1}", elo, error);
+    }
+    if let Some(sprt) = sprt {
+        let (lower, upper) = sprt.bounds();
+        println!("SPRT [{}, {}]: LLR {:.2} ({:.2}, {:.2})", sprt.elo0, sprt.elo1, score.llr(sprt.elo0, sprt.elo1), lower, upper);
+    }
+}
+
+const USAGE: &str = "options: --engine cmd=... [name=...] [arg=...] [option.Name=value] (at least twice) [--tc S+I] [--openings FILE] [--concurrency N] [--pgn FILE] [--timemargin MS]";
+
+// match --engine ... --engine ... [--games N] [--sprt ELO0 ELO1 [ALPHA BETA]] and the shared options
+pub fn run_match(args: &[String]) {
+    let settings = match parse_args(args) {
+        Ok(settings) if settings.engines.len() == 2 => settings,
+        Ok(_) => {
+            println!("usage: match [--games N] [--sprt ELO0 ELO1 [ALPHA BETA]] with two engines\n{}", USAGE);
+            return;
+        }
+        Err(error) => {
+            println!("{}", error);
+            return;
+        }
+    };
+    let names = [settings.engines[0].name.as_str(), settings.engines[1].name.as_str()];
+
+    // Each opening is played twice, with the colours swapped
+    let mut rng = rand::rng();
+    let openings: Vec<Opening> = match &settings.openings {
+        Some(openings) => openings.clone(),
+        None => (0..settings.games.div_ceil(2)).map(|_| random_opening(&mut rng)).collect(),
+    };
+    let schedule: Vec<Pairing> = (0..settings.games)
+        .map(|number| Pairing { number, round: number / 2 + 1, white: number % 2, black: 1 - number % 2, opening: number / 2 })
+        .collect();
+
+    let mut score = Score::default();
+    run_games(&settings, &schedule, &openings, |pairing, record| {
+        score.add(if pairing.white == 0 { record.result } else { 1.0 - record.result });
+        println!("Game {} ({} vs {}): {} {{{}}}", pairing.number + 1, record.white, record.black, result_text(record.result), record.reason);
+        if let Some(path) = &settings.pgn
+            && let Err(error) = append(path, &pgn_game(&record, "Match", &pairing.round.to_string(), settings.time_control))
+        {
+            println!("{}", error);
+        }
+        print_score(names, &score, settings.sprt);
+
+        let Some(sprt) = settings.sprt else {
+            return false;
+        };
+        match sprt.decide(&score) {
+            SprtDecision::AcceptH1 => println!("SPRT: H1 accepted, {} is stronger", names[0]),
+            SprtDecision::AcceptH0 => println!("SPRT: H0 accepted, {} is not stronger", names[0]),
+            SprtDecision::Continue => return false,
+        }
+        true
+    });
+}
+
+// Every pair meets `rounds` times (only the first engine's pairs in a gauntlet), and each
+// meeting is two games on the same opening with the colours swapped
+fn tournament_schedule(engines: usize, rounds: usize, gauntlet: bool) -> Vec<Pairing> {
+    let mut pairs = vec![];
+    for first in 0..engines {
+        for second in first + 1..engines {
+            if !gauntlet || first == 0 {
+                pairs.push((first, second));
+            }
+        }
+    }
+    let mut schedule = vec![];
+    for round in 1..=rounds {
+        for (first, second) in &pairs {
+            let opening = schedule.len() / 2;
+            for (white, black) in [(*first, *second), (*second, *first)] {
+                schedule.push(Pairing { number: schedule.len(), round, white, black, opening });
+            }
+        }
+    }
+    schedule
+}
+
+// Bradley-Terry strengths by minorisation-maximisation, as Elo around an average of zero. A
+// draw counts as half a win each way, and every pair that met gets one extra virtual draw so
+// an engine that won or lost everything still gets a finite rating.
+fn ratings(engines: usize, results: &[(Pairing, f64)]) -> Vec<f64> {
+    let mut points = vec![vec![0.0; engines]; engines];
+    let mut games = vec![vec![0.0; engines]; engines];
+    for (pairing, result) in results {
+        points[pairing.white][pairing.black] += result;
+        points[pairing.black][pairing.white] += 1.0 - result;
+        games[pairing.white][pairing.black] += 1.0;
+        games[pairing.black][pairing.white] += 1.0;
+    }
+    for i in 0..engines {
+        for j in 0..engines {
+            if games[i][j] > 0.0 {
+                points[i][j] += 0.5;
+                games[i][j] += 1.0;
+            }
+        }
+    }
+
+    let mut strength = vec![1.0; engines];
+    for _ in 0..1000 {
+        let mut next = strength.clone();
+        for i in 0..engines {
+            let won: f64 = points[i].iter().sum();
+            let expected: f64 = (0..engines).filter(|j| games[i][*j] > 0.0).map(|j| games[i][j] / (strength[i] + strength[j])).sum();
+            if expected > 0.0 {
+                next[i] = won / expected;
+            }
+        }
+        let mean_log = next.iter().map(|value: &f64| value.ln()).sum::<f64>() / engines as f64;
+        for value in next.iter_mut() {
+            *value /= mean_log.exp();
+        }
+        let change = next.iter().zip(&strength).map(|(a, b)| (a - b).abs()).fold(0.0, f64::max);
+        strength = next;
+        if change < 1e-9 {
+            break;
+        }
+    }
+    strength.iter().map(|value| 400.0 * value.log10()).collect()
+}
+
+// The standings, best first, with each pairing's points in a crosstable
+fn standings(names: &[String], results: &[(Pairing, f64)]) -> String {
+    let engines = names.len();
+    let elo = ratings(engines, results);
+    let mut cross = vec![vec![(0.0, 0); engines]; engines];
+    for (pairing, result) in results {
+        cross[pairing.white][pairing.black].0 += result;
+        cross[pairing.white][pairing.black].1 += 1;
+        cross[pairing.black][pairing.white].0 += 1.0 - result;
+        cross[pairing.black][pairing.white].1 += 1;
+    }
+    let totals: Vec<(f64, u32)> = cross
+        .iter()
+        .map(|row| row.iter().fold((0.0, 0), |(points, games), (p, g)| (points + p, games + g)))
+        .collect();
+    let mut order: Vec<usize> = (0..engines).collect();
+    order.sort_by(|a, b| elo[*b].total_cmp(&elo[*a]));
+
+    let width = names.iter().map(|name| name.len()).max().unwrap_or(4).max(4);
+    let mut text = format!("{:>4}  {:<width$} {:>6} {:>9}", "Rank", "Name", "Elo", "Points");
+    for &column in &order {
+        text.push_str(&format!(" {:>9}", names[column].chars().take(9).collect::<String>()));
+    }
+    text.push('\n');
+    for (rank, &row) in order.iter().enumerate() {
+        let (points, games) = totals[row];
+        text.push_str(&format!("{:>4}  {:<width$} {:>+6.0} {:>9}", rank + 1, names[row], elo[row], format!("{}/{}", points, games)));
+        for &column in &order {
+            let (points, games) = cross[row][column];
+            let cell = if row == column || games == 0 { "-".to_string() } else { format!("{}/{}", points, games) };
+            text.push_str(&format!(" {:>9}", cell));
+        }
+        text.push('\n');
+    }
+    text
+}
+
+// What a tournament has done so far, kept next to the PGN so it can carry on after being
+// stopped: a line describing the event, the seed for random openings, then "game <number>
+// <result>" for each finished game
+struct TournamentState {
+    path: String,
+    seed: u64,
+    results: Vec<(usize, f64)>,
+}
+
+impl TournamentState {
+    fn open(path: &str, description: &str) -> Result<TournamentState, String> {
+        let Ok(text) = std::fs::read_to_string(path) else {
+            let state = TournamentState { path: path.to_string(), seed: rand::random(), results: vec![] };
+            std::fs::write(path, format!("{}\nseed {}\n", description, state.seed)).map_err(|error| format!("Can't write {}: {}", path, error))?;
+            return Ok(state);
+        };
+        let mut lines = text.lines();
+        if lines.next() != Some(description) {
+            return Err(format!("{} belongs to a different tournament; delete it to start again", path));
+        }
+        let mut state = TournamentState { path: path.to_string(), seed: 0, results: vec![] };
+        for line in lines {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words[..] {
+                ["seed", seed] => state.seed = seed.parse().map_err(|_| format!("Bad seed in {}", path))?,
+                ["game", number, result] => {
+                    if let (Ok(number), Ok(result)) = (number.parse(), result.parse()) {
+                        state.results.push((number, result));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(state)
+    }
+
+    fn record(&mut self, number: usize, result: f64) -> Result<(), String> {
+        self.results.push((number, result));
+        append(&self.path, &format!("game {} {}\n", number, result))
+    }
+}
+
+// tournament --engine ... (two or more) [--rounds N] [--gauntlet] and the shared options
+pub fn run_tournament(args: &[String]) {
+    let settings = match parse_args(args) {
+        Ok(settings) if settings.engines.len() >= 2 => settings,
+        Ok(_) => {
+            println!("usage: tournament [--rounds N] [--gauntlet] with two or more engines\n{}", USAGE);
+            return;
+        }
+        Err(error) => {
+            println!("{}", error);
+            return;
+        }
+    };
+    let names: Vec<String> = settings.engines.iter().map(|spec| spec.name.clone()).collect();
+    let pgn = settings.pgn.clone().unwrap_or("tournament.pgn".to_string());
+    let format = if settings.gauntlet { "gauntlet" } else { "round-robin" };
+    let description = format!("tournament {} rounds {} engines {}", format, settings.rounds, names.join(" "));
+    let mut state = match TournamentState::open(&format!("{}.state", pgn), &description) {
+        Ok(state) => state,
+        Err(error) => {
+            println!("{}", error);
+            return;
+        }
+    };
+
+    let schedule = tournament_schedule(names.len(), settings.rounds, settings.gauntlet);
+    let openings: Vec<Opening> = match &settings.openings {
+        Some(openings) => openings.clone(),
+        None => {
+            // Seeded, so a resumed tournament plays the same openings
+            let mut rng = rand::rngs::StdRng::seed_from_u64(state.seed);
+            (0..schedule.len().div_ceil(2)).map(|_| random_opening(&mut rng)).collect()
+        }
+    };
+    let mut results: Vec<(Pairing, f64)> = state.results.iter().filter_map(|(number, result)| schedule.get(*number).map(|pairing| (*pairing, *result))).collect();
+    let remaining: Vec<Pairing> = schedule.iter().filter(|pairing| !results.iter().any(|(done, _)| done.number == pairing.number)).copied().collect();
+    if !results.is_empty() {
+        println!("Resuming with {} of {} games played", results.len(), schedule.len());
+    }
+
+    let event = format!("{} {}", format, names.join(" "));
+    run_games(&settings, &remaining, &openings, |pairing, record| {
+        println!("Game {} ({} vs {}): {} {{{}}}", pairing.number + 1, record.white, record.black, result_text(record.result), record.reason);
+        let round = format!("{}.{}", pairing.round, pairing.number + 1);
+        if let Err(error) = append(&pgn, &pgn_game(&record, &event, &round, settings.time_control)).and_then(|_| state.record(pairing.number, record.result)) {
+            println!("{}", error);
+            return true;
+        }
+        results.push((pairing, record.result));
+        if results.len().is_multiple_of(names.len()) || results.len() == schedule.len() {
+            print!("{}", standings(&names, &results));
+        }
+        false
+    });
+    if results.len() == schedule.len() {
+        println!("Tournament finished, games are in {}", pgn);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_settings() {
+        assert_eq!(parse_time_control("10+0.1"), Some(TimeControl { base_ms: 10_000, increment_ms: 100 }));
+        assert_eq!(parse_time_control("60"), Some(TimeControl { base_ms: 60_000, increment_ms: 0 }));
+        assert_eq!(parse_time_control("fast"), None);
+
+        let settings = parse_args(&strings(&[
+            "--engine", "cmd=self", "name=new", "arg=--eval", "arg=params.txt", "option.Hash=64",
+            "--engine", "cmd=/usr/bin/old-engine",
+            "--games", "20", "--sprt", "0", "5",
+        ]))
+        .unwrap();
+        assert_eq!(settings.engines[0].name, "new");
+        assert_eq!(settings.engines[0].args, ["--eval", "params.txt"]);
+        assert_eq!(settings.engines[0].options, [("Hash".to_string(), "64".to_string())]);
+        assert_eq!(settings.engines[1].name, "old-engine");
+        assert_eq!(settings.games, 20);
+        assert!(settings.sprt.is_some_and(|sprt| sprt.elo1 == 5.0 && sprt.alpha == 0.05));
+        assert!(parse_args(&strings(&["--engine", "name=nothing"])).is_err());
+    }
+
+    #[test]
+    fn test_elo_and_sprt() {
+        assert!(elo_from_score(0.5).abs() < 1e-9);
+        assert!((elo_from_score(0.75) - 190.85).abs() < 0.01);
+
+        let even = Score { wins: 40, draws: 20, losses: 40 };
+        let (elo, error) = even.elo().unwrap();
+        assert!(elo.abs() < 1e-9 && error > 50.0 && error < 80.0);
+
+        let sprt = Sprt { elo0: 0.0, elo1: 10.0, alpha: 0.05, beta: 0.05 };
+        let (lower, upper) = sprt.bounds();
+        assert!((upper - 2.944).abs() < 0.001 && (lower + 2.944).abs() < 0.001);
+        assert_eq!(sprt.decide(&Score { wins: 10, draws: 10, losses: 10 }), SprtDecision::Continue);
+        assert_eq!(sprt.decide(&Score { wins: 700, draws: 200, losses: 400 }), SprtDecision::AcceptH1);
+        assert_eq!(sprt.decide(&Score { wins: 400, draws: 200, losses: 700 }), SprtDecision::AcceptH0);
+    }
+
+    #[test]
+    fn test_game_over() {
+        let repetitions = HashMap::new();
+        let mated = Position::from_fen("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1").unwrap();
+        assert_eq!(game_over(&mated, &repetitions), Some((1.0, "checkmate")));
+        let stalemate = Position::from_fen("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1").unwrap();
+        assert_eq!(game_over(&stalemate, &repetitions), Some((0.5, "stalemate")));
+        assert_eq!(game_over(&Position::default(), &repetitions), None);
+
+        let opening = random_opening(&mut rand::rng());
+        assert_eq!(opening.moves.len(), RANDOM_OPENING_PLIES);
+    }
+
+    #[test]
+    fn test_tournament_schedule_and_ratings() {
+        let schedule = tournament_schedule(4, 2, false);
+        assert_eq!(schedule.len(), 6 * 2 * 2);
+        assert!(schedule.iter().all(|pairing| pairing.white != pairing.black));
+        assert_eq!(tournament_schedule(4, 1, true).len(), 3 * 2);
+        // Both games of a meeting share an opening
+        assert_eq!(schedule[0].opening, schedule[1].opening);
+        assert_eq!((schedule[1].white, schedule[1].black), (schedule[0].black, schedule[0].white));
+
+        // The engine listed first wins three games in four against any later one and draws the rest
+        let mut results = vec![];
+        for pairing in &tournament_schedule(3, 4, false) {
+            let white_points = match pairing.number % 4 {
+                3 => 0.5,
+                _ if pairing.white < pairing.black => 1.0,
+                _ => 0.0,
+            };
+            results.push((*pairing, white_points));
+        }
+        let elo = ratings(3, &results);
+        assert!(elo[0] > elo[1] && elo[1] > elo[2]);
+        assert!(elo.iter().sum::<f64>().abs() < 1e-6);
+
+        let even: Vec<(Pairing, f64)> = tournament_schedule(3, 1, false).into_iter().map(|pairing| (pairing, 0.5)).collect();
+        assert!(ratings(3, &even).iter().all(|elo| elo.abs() < 1e-6));
+
+        let names: Vec<String> = ["a", "b", "c"].iter().map(|name| name.to_string()).collect();
+        let table = standings(&names, &results);
+        let rows: Vec<&str> = table.lines().collect();
+        assert!(rows[1].contains(" a ") && rows[3].contains(" c "));
+    }
+
+    #[test]
+    fn test_pgn_output() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(18_262), (2020, 1, 1));
+        assert_eq!(civil_date(19_417), (2023, 3, 1));
+        assert_eq!(score_comment("cp -35").as_deref(), Some("-0.35"));
+        assert_eq!(score_comment("mate 3").as_deref(), Some("+M3"));
+
+        let position = Position::default();
+        let e4 = position.parse_uci_move("e2e4").unwrap();
+        let after = position.make_move(e4);
+        let e5 = after.parse_uci_move("e7e5").unwrap();
+        let record = GameRecord {
+            white: "new".to_string(),
+            black: "old".to_string(),
+            opening: Opening { fen: START_FEN.to_string(), moves: vec![e4] },
+            moves: vec![e5],
+            scores: vec![Some("cp 20".to_string())],
+            result: 0.0,
+            reason: "new loses on time".to_string(),
+        };
+        let text = pgn_game(&record, "Test", "1.1", TimeControl { base_ms: 10_000, increment_ms: 100 });
+        assert!(text.contains("[TimeControl \"10+0.1\"]"));
+        assert!(text.contains("1. e4 e5 {+0.20} {new loses on time} 0-1"));
+        let games = parse_pgn(&text);
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[0].moves, ["e4", "e5"]);
+        assert_eq!(games[0].header("White"), Some("new"));
+    }
+
+    #[test]
+    fn test_tournament_state_resumes() {
+        let path = std::env::temp_dir().join("chess_again_test_tournament.state");
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+        let mut state = TournamentState::open(path, "tournament round-robin rounds 1 engines a b").unwrap();
+        state.record(3, 0.5).unwrap();
+        state.record(0, 1.0).unwrap();
+        let resumed = TournamentState::open(path, "tournament round-robin rounds 1 engines a b").unwrap();
+        assert_eq!(resumed.seed, state.seed);
+        assert_eq!(resumed.results, [(3, 0.5), (0, 1.0)]);
+        assert!(TournamentState::open(path, "tournament gauntlet rounds 1 engines a b").is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
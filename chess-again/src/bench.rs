@@ -0,0 +1,50 @@
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+use crate::position::Position;
+use crate::search::{SearchLimits, SearchOptions, search};
+use crate::tt::TranspositionTable;
+
+const BENCH_FENS: [&str; 4] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "r1bq1rk1/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP3PPP/R2QKB1R w KQ - 0 8",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+];
+
+// chess-again bench [milliseconds per position] [max threads]
+// Searches a few positions for a fixed time at 1, 2, 4... threads and prints nodes per second
+pub fn run(args: &[String]) {
+    let movetime: u64 = args.first().and_then(|arg| arg.parse().ok()).unwrap_or(2000);
+    let available = std::thread::available_parallelism().map_or(1, |count| count.get());
+    let max_threads: usize = args.get(1).and_then(|arg| arg.parse().ok()).unwrap_or(available);
+
+    println!("{:>8} {:>12} {:>12} {:>8}", "threads", "nodes", "nps", "speedup");
+    let mut single_nps = 0;
+    let mut threads = 1;
+    while threads <= max_threads {
+        let options = SearchOptions { threads, ..Default::default() };
+        let limits = SearchLimits {
+            movetime: Some(movetime),
+            move_overhead: 0,
+            ..Default::default()
+        };
+
+        let mut nodes = 0;
+        let mut time_ms = 0;
+        for fen in BENCH_FENS {
+            let position = Position::from_fen(fen).unwrap();
+            let tt = TranspositionTable::new(64);
+            let start = std::time::Instant::now();
+            nodes += search(&position, &limits, &options, &tt, Arc::new(AtomicBool::new(false)), |_| {}).nodes;
+            time_ms += start.elapsed().as_millis() as u64;
+        }
+
+        let nps = nodes * 1000 / time_ms.max(1);
+        if threads == 1 {
+            single_nps = nps;
+        }
+        println!("{:>8} {:>12} {:>12} {:>7.2}x", threads, nodes, nps, nps as f64 / single_nps.max(1) as f64);
+        threads *= 2;
+    }
+}
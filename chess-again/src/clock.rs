@@ -0,0 +1,348 @@
+use std::time::{Duration, Instant};
+
+use eframe::egui;
+
+use crate::Team;
+use crate::position::Position;
+
+const LOW_TIME: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Bonus {
+    Increment, // Fischer: added after every move
+    Delay,     // Bronstein: the time a move took is given back, up to the delay
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Stage {
+    moves: Option<u32>, // None for the rest of the game
+    time: Duration,
+    bonus: Duration,
+}
+
+// Stages like "40/90+30, 30+30": forty moves in 90 minutes, then 30 minutes for the rest
+// of the game, with 30 seconds a move throughout. A last stage with a move count repeats
+pub fn parse_time_control(text: &str) -> Result<Vec<Stage>, String> {
+    let stages = text
+        .split(',')
+        .map(|stage| {
+            let stage = stage.trim();
+            let (moves, rest) = match stage.split_once('/') {
+                Some((moves, rest)) => (Some(moves.trim().parse::<u32>().ok().filter(|moves| *moves > 0)), rest),
+                None => (None, stage),
+            };
+            let (minutes, seconds) = rest.split_once('+').unwrap_or((rest, "0"));
+            match (moves, minutes.trim().parse::<f64>(), seconds.trim().parse::<f64>()) {
+                (Some(None), _, _) => Err(format!("Bad move count in {}", stage)),
+                (_, Ok(minutes), Ok(seconds)) if minutes >= 0.0 && seconds >= 0.0 && minutes + seconds > 0.0 => Ok(Stage {
+                    moves: moves.flatten(),
+                    time: Duration::from_secs_f64(minutes * 60.0),
+                    bonus: Duration::from_secs_f64(seconds),
+                }),
+                _ => Err(format!("Can't read time control {}", stage)),
+            }
+        })
+        .collect::<Result<Vec<Stage>, String>>()?;
+    if stages[..stages.len() - 1].iter().any(|stage| stage.moves.is_none()) {
+        return Err("Only the last stage can be for the rest of the game".to_string());
+    }
+    Ok(stages)
+}
+
+#[derive(Clone, Copy)]
+struct Side {
+    left: Duration,
+    stage: usize,
+    moves: u32, // Played in this stage
+}
+
+// Both players' time, with one side's clock running at a time
+pub struct Clock {
+    stages: Vec<Stage>,
+    bonus: Bonus,
+    sides: [Side; 2],
+    running: Option<(Team, Instant)>, // Whose clock is going, and since when
+    flagged: Option<Team>,
+}
+
+fn index(team: Team) -> usize {
+    if team == Team::White { 0 } else { 1 }
+}
+
+fn opponent(team: Team) -> Team {
+    if team == Team::White { Team::Black } else { Team::White }
+}
+
+impl Clock {
+    pub fn new(stages: Vec<Stage>, bonus: Bonus) -> Clock {
+        let side = Side { left: stages[0].time, stage: 0, moves: 0 };
+        Clock { stages, bonus, sides: [side; 2], running: None, flagged: None }
+    }
+
+    pub fn running(&self) -> Option<Team> {
+        self.running.map(|(team, _)| team)
+    }
+
+    pub fn flagged(&self) -> Option<Team> {
+        self.flagged
+    }
+
+    pub fn start(&mut self, team: Team, now: Instant) {
+        if self.flagged.is_none() {
+            self.running = Some((team, now));
+        }
+    }
+
+    // Charges the running side for the time it used
+    pub fn stop(&mut self, now: Instant) {
+        if let Some((team, since)) = self.running.take() {
+            let side = &mut self.sides[index(team)];
+            side.left = side.left.saturating_sub(now - since);
+        }
+    }
+
+    pub fn time_left(&self, team: Team, now: Instant) -> Duration {
+        let left = self.sides[index(team)].left;
+        match self.running {
+            Some((running, since)) if running == team => left.saturating_sub(now - since),
+            _ => left,
+        }
+    }
+
+    // Ends the running side's move and starts the other clock, adding the bonus and the
+    // next stage's time once the side has made its moves
+    pub fn press(&mut self, now: Instant) {
+        let Some((team, since)) = self.running else {
+            return;
+        };
+        if self.check_flag(now).is_some() {
+            return;
+        }
+        let used = now - since;
+        let stage = self.stages[self.sides[index(team)].stage];
+        let side = &mut self.sides[index(team)];
+        side.left -= used;
+        side.left += match self.bonus {
+            Bonus::Increment => stage.bonus,
+            Bonus::Delay => used.min(stage.bonus),
+        };
+        side.moves += 1;
+        if stage.moves == Some(side.moves) {
+            side.stage = (side.stage + 1).min(self.stages.len() - 1);
+            side.moves = 0;
+            side.left += self.stages[side.stage].time;
+        }
+        self.running = Some((opponent(team), now));
+    }
+
+    // Stops everything once the running side is out of time
+    pub fn check_flag(&mut self, now: Instant) -> Option<Team> {
+        let team = self.running()?;
+        if !self.time_left(team, now).is_zero() {
+            return None;
+        }
+        self.stop(now);
+        self.flagged = Some(team);
+        self.flagged
+    }
+}
+
+// Running out of time loses, unless the other side has nothing left that could mate
+pub fn flag_result(position: &Position, flagged: Team) -> &'static str {
+    match (position.can_mate(opponent(flagged)), flagged) {
+        (false, _) => "1/2-1/2",
+        (true, Team::White) => "0-1",
+        (true, _) => "1-0",
+    }
+}
+
+// "1:29:59", "4:05" or, when it gets short, "0:09.4"
+pub fn format_time(time: Duration) -> String {
+    let seconds = time.as_secs();
+    if seconds >= 3600 {
+        format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    } else if time >= LOW_TIME {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    } else {
+        format!("0:{:02}.{}", seconds, time.subsec_millis() / 100)
+    }
+}
+
+fn team_name(team: Team) -> &'static str {
+    if team == Team::White { "White" } else { "Black" }
+}
+
+// The time control settings and the clocks shown beside the board for hot-seat games
+pub struct Clocks {
+    time_control: String,
+    bonus: Bonus,
+    clock: Option<Clock>,
+    error: Option<String>,
+}
+
+impl Default for Clocks {
+    fn default() -> Self {
+        Self { time_control: "5+3".to_string(), bonus: Bonus::Increment, clock: None, error: None }
+    }
+}
+
+impl Clocks {
+    pub fn flagged(&self) -> bool {
+        self.clock.as_ref().is_some_and(|clock| clock.flagged().is_some())
+    }
+
+    // Hands the move over after a move that carries the game on, not one in a variation
+    pub fn moved(&mut self, to_move: Team) {
+        if let Some(clock) = &mut self.clock
+            && clock.running().is_some_and(|team| team != to_move)
+        {
+            clock.press(Instant::now());
+        }
+    }
+
+    // The result to record when a flag has just fallen
+    pub fn check_flag(&mut self, position: &Position) -> Option<&'static str> {
+        let clock = self.clock.as_mut()?;
+        let flagged = clock.check_flag(Instant::now())?;
+        Some(flag_result(position, flagged))
+    }
+
+    // `position` is the latest one in the game, whose side to move has the clock running
+    pub fn show(&mut self, ui: &mut egui::Ui, position: &Position) {
+        ui.heading("Clocks");
+        ui.horizontal(|ui| {
+            ui.label("Time control");
+            ui.add(egui::TextEdit::singleline(&mut self.time_control).desired_width(110.0))
+                .on_hover_text("Minutes + seconds a move, with stages like 40/90+30, 30+30");
+        });
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut self.bonus, Bonus::Increment, "Increment (Fischer)");
+            ui.radio_value(&mut self.bonus, Bonus::Delay, "Delay (Bronstein)");
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Start clocks").clicked() {
+                match parse_time_control(&self.time_control) {
+                    Ok(stages) => {
+                        let mut clock = Clock::new(stages, self.bonus);
+                        clock.start(position.current_team, Instant::now());
+                        self.clock = Some(clock);
+                        self.error = None;
+                    }
+                    Err(error) => self.error = Some(error),
+                }
+            }
+            if self.clock.is_some() && ui.button("Remove clocks").clicked() {
+                self.clock = None;
+            }
+        });
+        if let Some(error) = &self.error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+
+        let Some(clock) = &mut self.clock else {
+            return;
+        };
+        if let Some(team) = clock.flagged() {
+            let result = if position.can_mate(opponent(team)) {
+                format!("{} wins", team_name(opponent(team)))
+            } else {
+                "a draw, as the other side can't mate".to_string()
+            };
+            ui.label(format!("{} ran out of time: {}", team_name(team), result));
+        } else if clock.running().is_some() && position.legal_moves().is_empty() {
+            clock.stop(Instant::now());
+        }
+    }
+
+    // One side's clock, lit up while it's running
+    pub fn show_face(&self, ui: &mut egui::Ui, team: Team) {
+        let Some(clock) = &self.clock else {
+            return;
+        };
+        let now = Instant::now();
+        let left = clock.time_left(team, now);
+        let running = clock.running() == Some(team);
+        let colour = if clock.flagged() == Some(team) || (running && left < LOW_TIME) {
+            egui::Color32::from_rgb(255, 80, 80)
+        } else if running {
+            egui::Color32::WHITE
+        } else {
+            egui::Color32::GRAY
+        };
+        ui.label(egui::RichText::new(format!("{}  {}", team_name(team), format_time(left))).monospace().color(colour).size(26.0));
+        if running {
+            ui.ctx().request_repaint_after(Duration::from_millis(100));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seconds(seconds: u64) -> Duration {
+        Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn test_time_controls_parse() {
+        let stages = parse_time_control("40/90+30, 30+30").unwrap();
+        assert_eq!(stages[0], Stage { moves: Some(40), time: seconds(5400), bonus: seconds(30) });
+        assert_eq!(stages[1], Stage { moves: None, time: seconds(1800), bonus: seconds(30) });
+        assert_eq!(parse_time_control("3").unwrap()[0].bonus, Duration::ZERO);
+        assert!(parse_time_control("5+3, 40/90").is_err());
+        assert!(parse_time_control("0/90").is_err());
+        assert!(parse_time_control("fast").is_err());
+
+        assert_eq!(format_time(seconds(5399)), "1:29:59");
+        assert_eq!(format_time(seconds(245)), "4:05");
+        assert_eq!(format_time(Duration::from_millis(9420)), "0:09.4");
+    }
+
+    #[test]
+    fn test_bonus_time_and_stages() {
+        let start = Instant::now();
+        let mut fischer = Clock::new(parse_time_control("1+2").unwrap(), Bonus::Increment);
+        fischer.start(Team::White, start);
+        fischer.press(start + seconds(5));
+        assert_eq!(fischer.time_left(Team::White, start + seconds(5)), seconds(57));
+        assert_eq!(fischer.time_left(Team::Black, start + seconds(8)), seconds(57));
+
+        // A delay only gives back what the move used
+        let mut bronstein = Clock::new(parse_time_control("1+2").unwrap(), Bonus::Delay);
+        bronstein.start(Team::White, start);
+        bronstein.press(start + seconds(1));
+        bronstein.press(start + seconds(6));
+        assert_eq!(bronstein.time_left(Team::White, start + seconds(6)), seconds(60));
+        assert_eq!(bronstein.time_left(Team::Black, start + seconds(6)), seconds(57));
+
+        // The second stage's time arrives with the second move
+        let mut stages = Clock::new(parse_time_control("2/1, 1/1").unwrap(), Bonus::Increment);
+        stages.start(Team::White, start);
+        for move_number in 1..=4 {
+            stages.press(start + seconds(move_number * 10));
+        }
+        assert_eq!(stages.time_left(Team::White, start + seconds(40)), seconds(100));
+        assert_eq!(stages.time_left(Team::Black, start + seconds(40)), seconds(100));
+        assert_eq!(stages.sides[0].stage, 1);
+    }
+
+    #[test]
+    fn test_flag_falls() {
+        let start = Instant::now();
+        let mut clock = Clock::new(parse_time_control("0.5").unwrap(), Bonus::Increment);
+        clock.start(Team::White, start);
+        assert_eq!(clock.check_flag(start + seconds(29)), None);
+        assert_eq!(clock.check_flag(start + seconds(31)), Some(Team::White));
+        clock.press(start + seconds(32));
+        assert_eq!((clock.running(), clock.flagged()), (None, Some(Team::White)));
+
+        // A lone knight can't mate a bare king, but could with a pawn for the king to get stuck behind
+        let bare = Position::from_fen("4k3/8/8/8/8/8/8/1n2K3 w - - 0 1").unwrap();
+        assert_eq!(flag_result(&bare, Team::White), "1/2-1/2");
+        let pawn = Position::from_fen("4k3/8/8/8/8/8/P7/1n2K3 w - - 0 1").unwrap();
+        assert_eq!(flag_result(&pawn, Team::White), "0-1");
+        assert_eq!(flag_result(&pawn, Team::Black), "1-0");
+        assert_eq!(flag_result(&Position::default(), Team::Black), "1-0");
+    }
+}
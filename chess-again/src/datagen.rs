@@ -0,0 +1,416 @@
+// Self-play games at a fixed node count, written out as training positions.
+//
+// Text output is one position per line, "<fen> | <score> | <result>", with the score in
+// centipawns from White's side and the result 1.0, 0.5 or 0.0 for White. The tuner reads it
+// as it is.
+//
+// Binary output is 32 bytes per position, little endian:
+//   u64      occupancy, bit 0 = a1 through bit 63 = h8
+//   16 bytes a nibble per occupied square in bit order, low nibble first: piece type
+//            (pawn, knight, bishop, rook, queen, king = 0..5) plus 8 for Black
+//   i16      score in centipawns from White's side
+//   u8       result for White: 0 loss, 1 draw, 2 win
+//   u8       side to move in bit 0 (1 = Black), castling rights KQkq in bits 1 to 4
+//   u8       en passant file plus one, 0 for none
+//   u8       halfmove clock
+//   u16      fullmove number
+//   u16      unused, zero
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::time::Instant;
+
+use rand::Rng;
+use rand::seq::IndexedRandom;
+
+use crate::book::{Book, BookSelection};
+use crate::position::{ChessMove, Position};
+use crate::search::{SearchLimits, SearchOptions, search};
+use crate::syzygy::{TB_WIN, piece_count};
+use crate::tt::TranspositionTable;
+use crate::{PieceType, Team, zobrist};
+
+const RECORD_SIZE: usize = 32;
+const HASH_MB: usize = 16;
+const MAX_GAME_PLIES: usize = 400;
+// Both sides have to agree the game is over for this many plies in a row
+const ADJUDICATE_SCORE: i32 = 2500;
+const ADJUDICATE_PLIES: usize = 8;
+
+struct Settings {
+    output: String,
+    games: usize,
+    nodes: u64,
+    threads: usize,
+    book: Option<Book>,
+    random_plies: usize, // Random moves played after the book to spread the openings out
+    skip_plies: usize,   // Positions this early in the game are not written
+    keep_checks: bool,
+    keep_captures: bool, // Positions where the best move captures or promotes are noisy for a static evaluation
+}
+
+struct Record {
+    position: Position,
+    score: i32, // White's side
+    result: f64,
+}
+
+#[derive(PartialEq)]
+enum Outcome {
+    Finished(f64),
+    Ongoing,
+}
+
+fn outcome(position: &Position, repetitions: &HashMap<u64, u32>) -> Outcome {
+    if position.legal_moves().is_empty() {
+        return match (position.in_check(), position.current_team) {
+            (true, Team::White) => Outcome::Finished(0.0),
+            (true, _) => Outcome::Finished(1.0),
+            (false, _) => Outcome::Finished(0.5),
+        };
+    }
+    if position.halfmove_clock >= 100 || position.insufficient_material() || repetitions.get(&zobrist::hash(position)).is_some_and(|count| *count >= 3) {
+        return Outcome::Finished(0.5);
+    }
+    Outcome::Ongoing
+}
+
+// From the start position, book moves while the book has any, then random ones. None if the
+// game ended on the way.
+fn opening(settings: &Settings, rng: &mut impl Rng) -> Option<Position> {
+    let mut position = Position::default();
+    if let Some(book) = &settings.book {
+        while let Some(book_move) = book.pick(&position, BookSelection::WeightedRandom) {
+            position = position.make_move(book_move);
+        }
+    }
+    for _ in 0..settings.random_plies {
+        let moves = position.legal_moves();
+        position = position.make_move(*moves.choose(rng)?);
+    }
+    (!position.legal_moves().is_empty()).then_some(position)
+}
+
+fn game_ply(position: &Position) -> usize {
+    (position.fullmove_number as usize).saturating_sub(1) * 2 + (position.current_team == Team::Black) as usize
+}
+
+// Plies in a row the same side has been winning by ADJUDICATE_SCORE, counted up while it's
+// White and down while it's Black, so a swing to the other side starts again
+fn count_decided(decided: i32, white_score: i32) -> i32 {
+    if white_score >= ADJUDICATE_SCORE {
+        decided.max(0) + 1
+    } else if white_score <= -ADJUDICATE_SCORE {
+        decided.min(0) - 1
+    } else {
+        0
+    }
+}
+
+fn noisy(position: &Position, best_move: ChessMove) -> bool {
+    position.is_capture(best_move) || best_move.promotion != PieceType::None
+}
+
+// Plays one game and returns the positions worth keeping, labelled with the result
+fn play_game(settings: &Settings, tt: &TranspositionTable, rng: &mut impl Rng) -> Vec<Record> {
+    let Some(mut position) = opening(settings, rng) else {
+        return vec![];
+    };
+    tt.clear();
+    let limits = SearchLimits { nodes: Some(settings.nodes), move_overhead: 0, ..Default::default() };
+    let options = SearchOptions::default();
+    let mut repetitions: HashMap<u64, u32> = HashMap::new();
+    let mut kept: Vec<(Position, i32)> = vec![];
+    let mut decided_plies = 0;
+    let mut drawn_plies = 0;
+    let mut ply = 0;
+
+    let result = loop {
+        *repetitions.entry(zobrist::hash(&position)).or_insert(0) += 1;
+        if let Outcome::Finished(result) = outcome(&position, &repetitions) {
+            break result;
+        }
+        if ply >= MAX_GAME_PLIES {
+            break 0.5;
+        }
+
+        let found = search(&position, &limits, &options, tt, Arc::new(AtomicBool::new(false)), |_| {});
+        let Some(best_move) = found.best_move else {
+            break 0.5;
+        };
+        let white_score = if position.current_team == Team::White { found.score } else { -found.score };
+
+        // A search that never ran (only one legal move) has no score worth keeping, and mate
+        // and tablebase scores say nothing about the evaluation
+        let keep = found.depth > 0
+            && game_ply(&position) >= settings.skip_plies
+            && (settings.keep_checks || !position.in_check())
+            && (settings.keep_captures || !noisy(&position, best_move))
+            && found.score.abs() < TB_WIN - 1000;
+        if keep {
+            kept.push((position, white_score));
+        }
+
+        decided_plies = count_decided(decided_plies, white_score);
+        if decided_plies.unsigned_abs() as usize >= ADJUDICATE_PLIES {
+            break if decided_plies > 0 { 1.0 } else { 0.0 };
+        }
+        // Dead drawn endings get cut short the same way
+        drawn_plies = if found.score == 0 && piece_count(&position) <= 6 { drawn_plies + 1 } else { 0 };
+        if drawn_plies >= ADJUDICATE_PLIES {
+            break 0.5;
+        }
+
+        position = position.make_move(best_move);
+        ply += 1;
+    };
+
+    kept.into_iter().map(|(position, score)| Record { position, score, result }).collect()
+}
+
+fn encode(record: &Record) -> [u8; RECORD_SIZE] {
+    let mut bytes = [0; RECORD_SIZE];
+    let position = &record.position;
+    let mut occupancy = 0u64;
+    let mut nibbles = 0;
+    for square in 0..64 {
+        let piece = position.board[square % 8][square / 8];
+        let kind = match piece.piece_type {
+            PieceType::Pawn => 0,
+            PieceType::Knight => 1,
+            PieceType::Bishop => 2,
+            PieceType::Rook => 3,
+            PieceType::Queen => 4,
+            PieceType::King => 5,
+            PieceType::None => continue,
+        };
+        let nibble = if piece.team == Team::Black { kind + 8 } else { kind };
+        occupancy |= 1 << square;
+        // Real positions never have more than 32 pieces
+        if nibbles < 32 {
+            bytes[8 + nibbles / 2] |= nibble << (4 * (nibbles % 2));
+        }
+        nibbles += 1;
+    }
+    bytes[0..8].copy_from_slice(&occupancy.to_le_bytes());
+    bytes[24..26].copy_from_slice(&(record.score.clamp(i16::MIN as i32, i16::MAX as i32) as i16).to_le_bytes());
+    bytes[26] = (record.result * 2.0) as u8;
+    let castling = position.castling_rights();
+    bytes[27] = (position.current_team == Team::Black) as u8
+        | castling.iter().enumerate().fold(0, |flags, (i, allowed)| flags | (*allowed as u8) << (i + 1));
+    bytes[28] = position.en_passant.map_or(0, |square| square[0] as u8 + 1);
+    bytes[29] = position.halfmove_clock.min(255) as u8;
+    bytes[30..32].copy_from_slice(&(position.fullmove_number.min(u16::MAX as u32) as u16).to_le_bytes());
+    bytes
+}
+
+fn text_line(record: &Record) -> String {
+    format!("{} | {} | {:.1}\n", record.position.to_fen(), record.score, record.result)
+}
+
+fn parse_args(args: &[String]) -> Result<Settings, String> {
+    let output = args.first().ok_or("usage: datagen <output> [--games N] [--nodes N] [--threads N] [--book FILE] [--random-plies N] [--skip-plies N] [--keep-checks] [--keep-captures]")?;
+    let mut settings = Settings {
+        output: output.clone(),
+        games: 100,
+        nodes: 5000,
+        threads: std::thread::available_parallelism().map_or(1, |count| count.get()),
+        book: None,
+        random_plies: 8,
+        skip_plies: 16,
+        keep_checks: false,
+        keep_captures: false,
+    };
+    let mut i = 1;
+    while i < args.len() {
+        let value = args.get(i + 1).map(|value| value.as_str()).unwrap_or("");
+        let number = || value.parse::<u64>().map_err(|_| format!("{} needs a number", args[i]));
+        match args[i].as_str() {
+            "--keep-checks" => settings.keep_checks = true,
+            "--keep-captures" => settings.keep_captures = true,
+            "--games" => settings.games = number()? as usize,
+            "--nodes" => settings.nodes = number()?,
+            "--threads" => settings.threads = (number()? as usize).max(1),
+            "--random-plies" => settings.random_plies = number()? as usize,
+            "--skip-plies" => settings.skip_plies = number()? as usize,
+            "--book" => settings.book = Some(Book::load(value)?),
+            other => return Err(format!("Unknown option {}", other)),
+        }
+        i += if args[i].starts_with("--keep") { 1 } else { 2 };
+    }
+    Ok(settings)
+}
+
+// datagen <output> [options]: text when the output ends in .txt or .epd, binary otherwise
+pub fn run(args: &[String]) {
+    let settings = match parse_args(args) {
+        Ok(settings) => settings,
+        Err(error) => {
+            println!("{}", error);
+            return;
+        }
+    };
+    let text = settings.output.ends_with(".txt") || settings.output.ends_with(".epd");
+    let mut file = match std::fs::OpenOptions::new().create(true).append(true).open(&settings.output) {
+        Ok(file) => std::io::BufWriter::new(file),
+        Err(error) => {
+            println!("Can't open {}: {}", settings.output, error);
+            return;
+        }
+    };
+
+    let started = Instant::now();
+    let next_game = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel::<Vec<Record>>();
+    std::thread::scope(|scope| {
+        for _ in 0..settings.threads {
+            let (sender, settings, next_game) = (sender.clone(), &settings, &next_game);
+            scope.spawn(move || {
+                let tt = TranspositionTable::new(HASH_MB);
+                let mut rng = rand::rng();
+                while next_game.fetch_add(1, Ordering::Relaxed) < settings.games {
+                    if sender.send(play_game(settings, &tt, &mut rng)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        // Games finish on the worker threads and get written here, one at a time
+        let (mut games, mut positions) = (0, 0);
+        let mut results = [0; 3];
+        for records in receiver {
+            games += 1;
+            positions += records.len();
+            if let Some(record) = records.first() {
+                results[(record.result * 2.0) as usize] += 1;
+            }
+            for record in &records {
+                let written = if text { file.write_all(text_line(record).as_bytes()) } else { file.write_all(&encode(record)) };
+                if let Err(error) = written {
+                    println!("Can't write {}: {}", settings.output, error);
+                    return;
+                }
+            }
+            if games % 10 == 0 || games == settings.games {
+                let seconds = started.elapsed().as_secs_f64();
+                println!(
+                    "{:>6} games {:>9} positions  +{} ={} -{}  {:.0} positions/s",
+                    games,
+                    positions,
+                    results[2],
+                    results[1],
+                    results[0],
+                    positions as f64 / seconds.max(0.001)
+                );
+            }
+        }
+    });
+    if let Err(error) = file.flush() {
+        println!("Can't write {}: {}", settings.output, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &[u8]) -> (Position, i32, f64) {
+        let occupancy = u64::from_le_bytes(bytes[0..8].try_into().unwrap());
+        let mut fen_board = [['.'; 8]; 8];
+        for (i, square) in (0..64).filter(|square| occupancy & (1 << square) != 0).enumerate() {
+            let nibble = (bytes[8 + i / 2] >> (4 * (i % 2))) & 15;
+            let letter = ['p', 'n', 'b', 'r', 'q', 'k'][(nibble & 7) as usize];
+            fen_board[7 - square / 8][square % 8] = if nibble & 8 == 0 { letter.to_ascii_uppercase() } else { letter };
+        }
+        let ranks: Vec<String> = fen_board
+            .iter()
+            .map(|rank| {
+                let mut text = String::new();
+                let mut empty = 0;
+                for c in rank {
+                    if *c == '.' {
+                        empty += 1;
+                        continue;
+                    }
+                    if empty > 0 {
+                        text.push_str(&empty.to_string());
+                        empty = 0;
+                    }
+                    text.push(*c);
+                }
+                if empty > 0 {
+                    text.push_str(&empty.to_string());
+                }
+                text
+            })
+            .collect();
+        let castling: String = "KQkq".chars().enumerate().filter(|(i, _)| bytes[27] & (2 << i) != 0).map(|(_, c)| c).collect();
+        let en_passant = match bytes[28] {
+            0 => "-".to_string(),
+            file => format!("{}{}", (b'a' + file - 1) as char, if bytes[27] & 1 == 0 { 6 } else { 3 }),
+        };
+        let fen = format!(
+            "{} {} {} {} {} {}",
+            ranks.join("/"),
+            if bytes[27] & 1 == 0 { "w" } else { "b" },
+            if castling.is_empty() { "-".to_string() } else { castling },
+            en_passant,
+            bytes[29],
+            u16::from_le_bytes([bytes[30], bytes[31]])
+        );
+        let score = i16::from_le_bytes([bytes[24], bytes[25]]) as i32;
+        (Position::from_fen(&fen).unwrap(), score, bytes[26] as f64 / 2.0)
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        for fen in [
+            "r3k2r/pppq1ppp/2n2n2/3pp3/3PP3/2N2N2/PPPQ1PPP/R3K2R w KQkq - 3 9",
+            "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 41",
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1",
+        ] {
+            let record = Record { position: Position::from_fen(fen).unwrap(), score: -123, result: 0.5 };
+            let (position, score, result) = decode(&encode(&record));
+            assert_eq!(position.to_fen(), fen);
+            assert_eq!((score, result), (-123, 0.5));
+        }
+    }
+
+    #[test]
+    fn test_games_are_labelled() {
+        let settings = parse_args(&["out.bin".to_string(), "--nodes".to_string(), "300".to_string(), "--skip-plies".to_string(), "0".to_string()]).unwrap();
+        let tt = TranspositionTable::new(1);
+        let records = play_game(&settings, &tt, &mut rand::rng());
+        for record in &records {
+            assert!([0.0, 0.5, 1.0].contains(&record.result));
+            assert_eq!(record.result, records[0].result);
+            assert!(!record.position.in_check());
+            let line = text_line(record);
+            assert_eq!(line.split(" | ").count(), 3);
+        }
+        assert!(parse_args(&["out.bin".to_string(), "--bogus".to_string()]).is_err());
+        assert!(parse_args(&["out.bin".to_string(), "--games".to_string(), "many".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_game_endings() {
+        let repetitions = HashMap::new();
+        let mated = Position::from_fen("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1").unwrap();
+        assert!(outcome(&mated, &repetitions) == Outcome::Finished(1.0));
+        let bare = Position::from_fen("8/8/4k3/8/8/2N5/8/4K3 w - - 0 1").unwrap();
+        assert!(outcome(&bare, &repetitions) == Outcome::Finished(0.5));
+        assert!(outcome(&Position::default(), &repetitions) == Outcome::Ongoing);
+
+        // Adjudication only counts plies that agree on who's winning
+        let decided = [2600, 3000, -2600, -2700, 100, 2500].iter().scan(0, |decided, score| {
+            *decided = count_decided(*decided, *score);
+            Some(*decided)
+        });
+        assert_eq!(decided.collect::<Vec<_>>(), [1, 2, -1, -2, 0, 1]);
+    }
+}
@@ -0,0 +1,985 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use rand::Rng;
+
+use crate::position::{ChessMove, Position};
+use crate::{PieceType, Team};
+
+// Distance to mate tables for endings with up to 4 pieces, generated by retrograde analysis:
+// start from the mates and walk backwards one move at a time. Values are for the side to move.
+const DRAW: u8 = 0; // Also "not known yet" while generating
+const LOSS: u8 = 128; // LOSS + n: mated after n more moves, so LOSS itself is checkmate
+const ILLEGAL: u8 = 255;
+const NO_EXIT: u8 = 254; // Every move stays in the same table
+const MAX_MOVES: u8 = 120;
+
+const MAX_PIECES: usize = 4;
+const FILE_MAGIC: &[u8] = b"CADTM\x01";
+
+// Every 3 and 4 piece ending that isn't a plain draw, strongest side first
+pub const DEFAULT_TABLES: [&str; 33] = [
+    "KQvK", "KRvK", "KPvK", "KQQvK", "KQRvK", "KQBvK", "KQNvK", "KQPvK", "KRRvK", "KRBvK", "KRNvK",
+    "KRPvK", "KBBvK", "KBNvK", "KBPvK", "KNNvK", "KNPvK", "KPPvK", "KQvKQ", "KQvKR", "KQvKB", "KQvKN",
+    "KQvKP", "KRvKR", "KRvKB", "KRvKN", "KRvKP", "KBvKB", "KBvKN", "KBvKP", "KNvKN", "KNvKP", "KPvKP",
+];
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Dtm {
+    Win(u32), // Mates in this many moves
+    Draw,
+    Loss(u32), // Gets mated in this many moves
+}
+
+fn to_dtm(value: u8) -> Dtm {
+    if is_win(value) {
+        Dtm::Win(value as u32)
+    } else if is_loss(value) {
+        Dtm::Loss((value - LOSS) as u32)
+    } else {
+        Dtm::Draw
+    }
+}
+
+fn is_win(value: u8) -> bool {
+    (1..LOSS).contains(&value)
+}
+
+fn is_loss(value: u8) -> bool {
+    (LOSS..NO_EXIT).contains(&value)
+}
+
+// The value for the side that moved, given the value for the side to move after it
+fn negate(value: u8) -> u8 {
+    if is_loss(value) {
+        value - LOSS + 1
+    } else if is_win(value) {
+        LOSS + value
+    } else {
+        DRAW
+    }
+}
+
+// Higher is better for the side to move: quick wins, then draws, then slow losses
+fn rank(value: u8) -> i32 {
+    if is_win(value) {
+        1000 - value as i32
+    } else if is_loss(value) {
+        -1000 + (value - LOSS) as i32
+    } else {
+        0
+    }
+}
+
+fn piece_order(piece_type: PieceType) -> usize {
+    match piece_type {
+        PieceType::King => 0,
+        PieceType::Queen => 1,
+        PieceType::Rook => 2,
+        PieceType::Bishop => 3,
+        PieceType::Knight => 4,
+        _ => 5,
+    }
+}
+
+fn piece_letter(piece_type: PieceType) -> char {
+    ['K', 'Q', 'R', 'B', 'N', 'P'][piece_order(piece_type)]
+}
+
+fn letter_piece(letter: char) -> Option<PieceType> {
+    Some(match letter {
+        'K' => PieceType::King,
+        'Q' => PieceType::Queen,
+        'R' => PieceType::Rook,
+        'B' => PieceType::Bishop,
+        'N' => PieceType::Knight,
+        'P' => PieceType::Pawn,
+        _ => return None,
+    })
+}
+
+fn material_strength(pieces: &[PieceType]) -> (i32, Vec<usize>) {
+    let value = pieces.iter().map(|piece| crate::see::piece_value(*piece) * (*piece != PieceType::King) as i32).sum();
+    (value, pieces.iter().map(|piece| 5 - piece_order(*piece)).collect())
+}
+
+// The table name for some material, and whether the colours have to be swapped to use it
+fn material_name(mut white: Vec<PieceType>, mut black: Vec<PieceType>) -> (String, bool) {
+    white.sort_by_key(|piece| piece_order(*piece));
+    black.sort_by_key(|piece| piece_order(*piece));
+    let swapped = material_strength(&white) < material_strength(&black);
+    if swapped {
+        std::mem::swap(&mut white, &mut black);
+    }
+    let name = format!(
+        "{}v{}",
+        white.iter().map(|piece| piece_letter(*piece)).collect::<String>(),
+        black.iter().map(|piece| piece_letter(*piece)).collect::<String>()
+    );
+    (name, swapped)
+}
+
+fn parse_material(name: &str) -> Option<(Vec<PieceType>, Vec<PieceType>)> {
+    let (white, black) = name.split_once('v')?;
+    let white: Vec<PieceType> = white.chars().map(letter_piece).collect::<Option<_>>()?;
+    let black: Vec<PieceType> = black.chars().map(letter_piece).collect::<Option<_>>()?;
+    let kings = |pieces: &[PieceType]| pieces.iter().filter(|piece| **piece == PieceType::King).count();
+    if kings(&white) != 1 || kings(&black) != 1 || white.len() + black.len() > MAX_PIECES {
+        return None;
+    }
+    Some((white, black))
+}
+
+// Squares are 0..64 with a1 = 0, b1 = 1, ..., h8 = 63
+#[derive(Copy, Clone)]
+struct Board {
+    pieces: [(PieceType, Team, i8); MAX_PIECES],
+    count: usize,
+    turn: Team,
+}
+
+fn other(team: Team) -> Team {
+    if team == Team::White { Team::Black } else { Team::White }
+}
+
+const KING_STEPS: [(i8, i8); 8] = [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)];
+const KNIGHT_STEPS: [(i8, i8); 8] = [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)];
+const ROOK_DIRECTIONS: [(i8, i8); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+const BISHOP_DIRECTIONS: [(i8, i8); 4] = [(1, 1), (-1, 1), (-1, -1), (1, -1)];
+
+fn offset(square: i8, (dx, dy): (i8, i8)) -> Option<i8> {
+    let (x, y) = (square % 8 + dx, square / 8 + dy);
+    ((0..8).contains(&x) && (0..8).contains(&y)).then_some(y * 8 + x)
+}
+
+fn slides(piece_type: PieceType) -> &'static [(i8, i8)] {
+    match piece_type {
+        PieceType::Rook => &ROOK_DIRECTIONS,
+        PieceType::Bishop => &BISHOP_DIRECTIONS,
+        PieceType::Queen => &KING_STEPS, // Every direction
+        _ => &[],
+    }
+}
+
+fn pawn_direction(team: Team) -> i8 {
+    if team == Team::White { 1 } else { -1 }
+}
+
+impl Board {
+    fn at(&self, square: i8) -> Option<usize> {
+        (0..self.count).find(|i| self.pieces[*i].2 == square)
+    }
+
+    fn king(&self, team: Team) -> i8 {
+        self.pieces[..self.count]
+            .iter()
+            .find(|(piece_type, piece_team, _)| *piece_type == PieceType::King && *piece_team == team)
+            .map_or(-1, |piece| piece.2)
+    }
+
+    fn attacks(&self, (piece_type, team, from): (PieceType, Team, i8), to: i8) -> bool {
+        let (dx, dy) = (to % 8 - from % 8, to / 8 - from / 8);
+        let clear_path = || {
+            let step = (dx.signum(), dy.signum());
+            let mut square = from + step.1 * 8 + step.0;
+            while square != to {
+                if self.at(square).is_some() {
+                    return false;
+                }
+                square += step.1 * 8 + step.0;
+            }
+            true
+        };
+        match piece_type {
+            PieceType::King => dx.abs().max(dy.abs()) == 1,
+            PieceType::Knight => dx.abs() * dy.abs() == 2,
+            PieceType::Pawn => dy == pawn_direction(team) && dx.abs() == 1,
+            PieceType::Rook => (dx == 0) != (dy == 0) && clear_path(),
+            PieceType::Bishop => dx != 0 && dx.abs() == dy.abs() && clear_path(),
+            PieceType::Queen => ((dx == 0) != (dy == 0) || (dx != 0 && dx.abs() == dy.abs())) && clear_path(),
+            PieceType::None => false,
+        }
+    }
+
+    fn attacked(&self, square: i8, by: Team) -> bool {
+        self.pieces[..self.count].iter().any(|piece| piece.1 == by && self.attacks(*piece, square))
+    }
+
+    fn in_check(&self, team: Team) -> bool {
+        self.attacked(self.king(team), other(team))
+    }
+
+    fn valid(&self) -> bool {
+        for i in 0..self.count {
+            let (piece_type, _, square) = self.pieces[i];
+            if piece_type == PieceType::Pawn && !(8..56).contains(&square) {
+                return false;
+            }
+            if (i + 1..self.count).any(|j| self.pieces[j].2 == square) {
+                return false;
+            }
+        }
+        !self.in_check(other(self.turn))
+    }
+
+    fn with_move(&self, slot: usize, to: i8, promotion: PieceType) -> Board {
+        let mut board = *self;
+        board.pieces[slot].2 = to;
+        if promotion != PieceType::None {
+            board.pieces[slot].0 = promotion;
+        }
+        if let Some(captured) = self.at(to) {
+            board.pieces[captured] = board.pieces[board.count - 1];
+            board.count -= 1;
+        }
+        board.turn = other(self.turn);
+        board
+    }
+
+    // Calls `visit` with every position a legal move leads to, and whether the move left
+    // this table by capturing or promoting
+    fn for_each_move(&self, mut visit: impl FnMut(Board, bool)) {
+        let mover = self.turn;
+        for slot in 0..self.count {
+            let (piece_type, team, from) = self.pieces[slot];
+            if team != mover {
+                continue;
+            }
+            let mut try_move = |to: i8, promotion: PieceType| {
+                let target = self.at(to);
+                if let Some(target) = target
+                    && (self.pieces[target].1 == mover || self.pieces[target].0 == PieceType::King)
+                {
+                    return;
+                }
+                let child = self.with_move(slot, to, promotion);
+                if !child.in_check(mover) {
+                    visit(child, target.is_some() || promotion != PieceType::None);
+                }
+            };
+
+            match piece_type {
+                PieceType::King | PieceType::Knight => {
+                    let steps = if piece_type == PieceType::King { &KING_STEPS } else { &KNIGHT_STEPS };
+                    for to in steps.iter().filter_map(|step| offset(from, *step)) {
+                        try_move(to, PieceType::None);
+                    }
+                }
+                PieceType::Pawn => {
+                    let direction = pawn_direction(team);
+                    let last_rank = if team == Team::White { 7 } else { 0 };
+                    let promotions: &[PieceType] = if from / 8 + direction == last_rank {
+                        &[PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight]
+                    } else {
+                        &[PieceType::None]
+                    };
+                    let one = from + direction * 8;
+                    if self.at(one).is_none() {
+                        for promotion in promotions {
+                            try_move(one, *promotion);
+                        }
+                        let start_rank = if team == Team::White { 1 } else { 6 };
+                        if from / 8 == start_rank && self.at(one + direction * 8).is_none() {
+                            try_move(one + direction * 8, PieceType::None);
+                        }
+                    }
+                    for to in [(-1, direction), (1, direction)].iter().filter_map(|step| offset(from, *step)) {
+                        if self.at(to).is_some() {
+                            for promotion in promotions {
+                                try_move(to, *promotion);
+                            }
+                        }
+                    }
+                }
+                _ => {
+                    for direction in slides(piece_type).iter().copied() {
+                        let mut square = from;
+                        while let Some(to) = offset(square, direction) {
+                            try_move(to, PieceType::None);
+                            if self.at(to).is_some() {
+                                break;
+                            }
+                            square = to;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Every position that reaches this one with a quiet move, the reverse of the moves above
+    // that stay in the table
+    fn for_each_unmove(&self, mut visit: impl FnMut(Board)) {
+        let mover = other(self.turn);
+        for slot in 0..self.count {
+            let (piece_type, team, to) = self.pieces[slot];
+            if team != mover {
+                continue;
+            }
+            let mut try_unmove = |from: i8| {
+                let mut board = *self;
+                board.pieces[slot].2 = from;
+                board.turn = mover;
+                if !board.in_check(self.turn) {
+                    visit(board);
+                }
+            };
+
+            match piece_type {
+                PieceType::King | PieceType::Knight => {
+                    let steps = if piece_type == PieceType::King { &KING_STEPS } else { &KNIGHT_STEPS };
+                    for from in steps.iter().filter_map(|step| offset(to, *step)) {
+                        if self.at(from).is_none() {
+                            try_unmove(from);
+                        }
+                    }
+                }
+                PieceType::Pawn => {
+                    let direction = pawn_direction(team);
+                    let from = to - direction * 8;
+                    if (8..56).contains(&from) && self.at(from).is_none() {
+                        try_unmove(from);
+                        let double_rank = if team == Team::White { 3 } else { 4 };
+                        if to / 8 == double_rank && self.at(from - direction * 8).is_none() {
+                            try_unmove(from - direction * 8);
+                        }
+                    }
+                }
+                _ => {
+                    for direction in slides(piece_type).iter().copied() {
+                        let mut square = to;
+                        while let Some(from) = offset(square, direction) {
+                            if self.at(from).is_some() {
+                                break;
+                            }
+                            try_unmove(from);
+                            square = from;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn material(&self) -> (Vec<PieceType>, Vec<PieceType>) {
+        let side = |team: Team| self.pieces[..self.count].iter().filter(|piece| piece.1 == team).map(|piece| piece.0).collect();
+        (side(Team::White), side(Team::Black))
+    }
+
+    // The same position with the colours swapped and the board flipped
+    fn swap_colours(&self) -> Board {
+        let mut board = *self;
+        for piece in &mut board.pieces[..self.count] {
+            piece.1 = other(piece.1);
+            piece.2 ^= 56;
+        }
+        board.turn = other(self.turn);
+        board
+    }
+}
+
+// The a1-d1-d4 triangle the white king is moved into when there are no pawns
+const TRIANGLE: [i8; 10] = [0, 1, 2, 3, 9, 10, 11, 18, 19, 27];
+
+fn transpose(square: i8) -> i8 {
+    square % 8 * 8 + square / 8
+}
+
+// How positions of one material map to table indices. Mirror images share an entry: without
+// pawns the white king is kept in the a1-d1-d4 triangle, with pawns on the a-d files.
+struct Layout {
+    pieces: Vec<(PieceType, Team)>,
+    has_pawns: bool,
+}
+
+impl Layout {
+    fn new(name: &str) -> Option<Layout> {
+        let (white, black) = parse_material(name)?;
+        let mut pieces: Vec<(PieceType, Team)> = white.into_iter().map(|piece| (piece, Team::White)).collect();
+        pieces.extend(black.into_iter().map(|piece| (piece, Team::Black)));
+        pieces.sort_by_key(|(piece, team)| (*team != Team::White, piece_order(*piece)));
+        let has_pawns = pieces.iter().any(|(piece, _)| *piece == PieceType::Pawn);
+        Some(Layout { pieces, has_pawns })
+    }
+
+    fn king_squares(&self) -> usize {
+        if self.has_pawns { 32 } else { TRIANGLE.len() }
+    }
+
+    // Positions with one side to move; all of White's come first
+    fn half(&self) -> usize {
+        self.king_squares() * 64usize.pow(self.pieces.len() as u32 - 1)
+    }
+
+    fn size(&self) -> usize {
+        self.half() * 2
+    }
+
+    fn encode(&self, squares: &[i8], turn: Team) -> usize {
+        let king = if self.has_pawns {
+            (squares[0] / 8 * 4 + squares[0] % 8) as usize
+        } else {
+            TRIANGLE.iter().position(|square| *square == squares[0]).unwrap()
+        };
+        let index = squares[1..].iter().fold(king, |index, square| index * 64 + *square as usize);
+        index + if turn == Team::Black { self.half() } else { 0 }
+    }
+
+    // Identical pieces are kept in square order so each position has exactly one index
+    fn sort_identical(&self, squares: &mut [i8]) {
+        for i in 1..squares.len() {
+            let mut j = i;
+            while j > 1 && self.pieces[j - 1] == self.pieces[j] && squares[j - 1] > squares[j] {
+                squares.swap(j - 1, j);
+                j -= 1;
+            }
+        }
+    }
+
+    fn index(&self, board: &Board) -> usize {
+        // Line the pieces up with the layout
+        let mut squares = [0i8; MAX_PIECES];
+        let mut used = [false; MAX_PIECES];
+        for (slot, (piece_type, team)) in self.pieces.iter().enumerate() {
+            let i = (0..board.count)
+                .find(|i| !used[*i] && board.pieces[*i].0 == *piece_type && board.pieces[*i].1 == *team)
+                .unwrap();
+            used[i] = true;
+            squares[slot] = board.pieces[i].2;
+        }
+        let squares = &mut squares[..self.pieces.len()];
+
+        if squares[0] % 8 > 3 {
+            squares.iter_mut().for_each(|square| *square ^= 7);
+        }
+        if !self.has_pawns {
+            if squares[0] / 8 > 3 {
+                squares.iter_mut().for_each(|square| *square ^= 56);
+            }
+            if squares[0] / 8 > squares[0] % 8 {
+                squares.iter_mut().for_each(|square| *square = transpose(*square));
+            }
+        }
+        self.sort_identical(squares);
+        let index = self.encode(squares, board.turn);
+
+        // A king on the diagonal can still be mirrored along it, so take the smaller index
+        if !self.has_pawns && squares[0] / 8 == squares[0] % 8 {
+            let mut mirrored = [0i8; MAX_PIECES];
+            let mirrored = &mut mirrored[..squares.len()];
+            for (i, square) in squares.iter().enumerate() {
+                mirrored[i] = transpose(*square);
+            }
+            self.sort_identical(mirrored);
+            return index.min(self.encode(mirrored, board.turn));
+        }
+        index
+    }
+
+    fn board(&self, mut index: usize) -> Board {
+        let turn = if index < self.half() { Team::White } else { Team::Black };
+        index %= self.half();
+        let mut board = Board {
+            pieces: [(PieceType::None, Team::None, 0); MAX_PIECES],
+            count: self.pieces.len(),
+            turn,
+        };
+        for slot in (1..self.pieces.len()).rev() {
+            board.pieces[slot] = (self.pieces[slot].0, self.pieces[slot].1, (index % 64) as i8);
+            index /= 64;
+        }
+        let king = if self.has_pawns { (index / 4 * 8 + index % 4) as i8 } else { TRIANGLE[index] };
+        board.pieces[0] = (PieceType::King, Team::White, king);
+        board
+    }
+}
+
+pub struct Table {
+    layout: Layout,
+    values: Vec<u8>,
+}
+
+// Value of any position in the tables given, from the side to move's point of view
+fn lookup(tables: &HashMap<String, Table>, board: &Board) -> Option<u8> {
+    if board.count == 2 {
+        return Some(DRAW);
+    }
+    let (white, black) = board.material();
+    let (name, swapped) = material_name(white, black);
+    let table = tables.get(&name)?;
+    let board = if swapped { board.swap_colours() } else { *board };
+    Some(table.values[table.layout.index(&board)])
+}
+
+// Every table the captures and promotions of this one lead to
+fn dependencies(name: &str) -> Vec<String> {
+    let Some((white, black)) = parse_material(name) else {
+        return vec![];
+    };
+    let mut names: Vec<String> = vec![];
+    for (side, pieces, others) in [(0, &white, &black), (1, &black, &white)] {
+        for (i, piece) in pieces.iter().enumerate() {
+            if *piece == PieceType::King {
+                continue;
+            }
+            let mut variants: Vec<Vec<PieceType>> = vec![pieces.iter().enumerate().filter(|(j, _)| *j != i).map(|(_, piece)| *piece).collect()];
+            if *piece == PieceType::Pawn {
+                for promotion in [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight] {
+                    let mut promoted = pieces.clone();
+                    promoted[i] = promotion;
+                    variants.push(promoted);
+                }
+            }
+            for variant in variants {
+                let (name, _) = if side == 0 { material_name(variant, others.clone()) } else { material_name(others.clone(), variant) };
+                if name != "KvK" && !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+    }
+    names
+}
+
+fn threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |count| count.get())
+}
+
+// The retrograde analysis itself. Every table a capture or promotion leads to must already
+// be in `tables`. En passant is ignored.
+fn generate(name: &str, tables: &HashMap<String, Table>) -> Option<Table> {
+    let layout = Layout::new(name)?;
+    let size = layout.size();
+    let mut values = vec![DRAW; size];
+    let mut exits = vec![NO_EXIT; size]; // Best result through a capture or promotion
+    let mut counters = vec![0u8; size]; // Positions in this table a move leads to that aren't lost yet
+
+    // Mark illegal positions, mates, and the results of leaving the table
+    let chunk_size = size.div_ceil(threads());
+    std::thread::scope(|scope| {
+        for (chunk, ((values, exits), counters)) in values
+            .chunks_mut(chunk_size)
+            .zip(exits.chunks_mut(chunk_size))
+            .zip(counters.chunks_mut(chunk_size))
+            .enumerate()
+        {
+            let layout = &layout;
+            scope.spawn(move || {
+                let mut children: Vec<usize> = Vec::with_capacity(64);
+                for i in 0..values.len() {
+                    let index = chunk * chunk_size + i;
+                    let board = layout.board(index);
+                    if !board.valid() || layout.index(&board) != index {
+                        values[i] = ILLEGAL;
+                        continue;
+                    }
+
+                    children.clear();
+                    let mut any_move = false;
+                    board.for_each_move(|child, exit| {
+                        any_move = true;
+                        if exit {
+                            let value = negate(lookup(tables, &child).expect("missing a table this one depends on"));
+                            if exits[i] == NO_EXIT || rank(value) > rank(exits[i]) {
+                                exits[i] = value;
+                            }
+                        } else {
+                            children.push(layout.index(&child));
+                        }
+                    });
+                    children.sort_unstable();
+                    children.dedup();
+                    counters[i] = children.len() as u8;
+
+                    if !any_move {
+                        values[i] = if board.in_check(board.turn) { LOSS } else { DRAW };
+                    } else if children.is_empty() {
+                        values[i] = exits[i];
+                    }
+                }
+            });
+        }
+    });
+
+    let mut last_pending = values
+        .iter()
+        .chain(exits.iter())
+        .filter(|value| is_win(**value) || is_loss(**value))
+        .map(|value| if is_win(*value) { *value } else { *value - LOSS })
+        .max()
+        .unwrap_or(0);
+
+    let mut predecessors: Vec<usize> = Vec::with_capacity(128);
+    for moves in 1..=MAX_MOVES {
+        let mut changed = false;
+
+        // Anything that can reach a lost position wins, as can quick enough captures
+        for index in 0..size {
+            if values[index] == LOSS + moves - 1 {
+                layout.board(index).for_each_unmove(|board| {
+                    let previous = layout.index(&board);
+                    if values[previous] == DRAW {
+                        values[previous] = moves;
+                        changed = true;
+                    }
+                });
+            } else if values[index] == DRAW && exits[index] == moves {
+                values[index] = moves;
+                changed = true;
+            }
+        }
+
+        // Anything whose every move reaches a won position is lost
+        for index in 0..size {
+            if values[index] != moves {
+                continue;
+            }
+            predecessors.clear();
+            layout.board(index).for_each_unmove(|board| predecessors.push(layout.index(&board)));
+            predecessors.sort_unstable();
+            predecessors.dedup();
+            for previous in predecessors.iter().copied() {
+                if values[previous] != DRAW {
+                    continue;
+                }
+                counters[previous] -= 1;
+                if counters[previous] > 0 {
+                    continue;
+                }
+                let exit = exits[previous];
+                if exit == NO_EXIT {
+                    values[previous] = LOSS + moves;
+                } else if is_loss(exit) {
+                    values[previous] = LOSS + moves.max(exit - LOSS);
+                    last_pending = last_pending.max(exit - LOSS);
+                }
+                changed = true;
+            }
+        }
+
+        if !changed && moves > last_pending {
+            break;
+        }
+    }
+
+    Some(Table { layout, values })
+}
+
+// Deflate compressed. Illegal positions are never looked up, so they take the value before
+// them, which makes for long runs that compress well.
+fn encode_values(values: &[u8]) -> Vec<u8> {
+    let mut filled: Vec<u8> = Vec::with_capacity(values.len());
+    let mut previous = DRAW;
+    for value in values.iter().copied() {
+        if value != ILLEGAL {
+            previous = value;
+        }
+        filled.push(previous);
+    }
+    miniz_oxide::deflate::compress_to_vec(&filled, 9)
+}
+
+fn decode_values(bytes: &[u8], size: usize) -> Option<Vec<u8>> {
+    let values = miniz_oxide::inflate::decompress_to_vec_with_limit(bytes, size).ok()?;
+    (values.len() == size).then_some(values)
+}
+
+impl Table {
+    fn save(&self, name: &str, directory: &str) -> Result<(), String> {
+        let mut bytes = FILE_MAGIC.to_vec();
+        bytes.push(name.len() as u8);
+        bytes.extend(name.as_bytes());
+        bytes.extend(encode_values(&self.values));
+        let path = std::path::Path::new(directory).join(format!("{}.dtm", name));
+        std::fs::write(&path, bytes).map_err(|error| format!("Can't write {}: {}", path.display(), error))
+    }
+
+    fn load(path: &std::path::Path) -> Result<(String, Table), String> {
+        let bytes = std::fs::read(path).map_err(|error| format!("Can't read {}: {}", path.display(), error))?;
+        let invalid = || format!("{} is not an endgame table", path.display());
+        let rest = bytes.strip_prefix(FILE_MAGIC).ok_or_else(invalid)?;
+        let length = *rest.first().ok_or_else(invalid)? as usize;
+        let name = std::str::from_utf8(rest.get(1..1 + length).ok_or_else(invalid)?).map_err(|_| invalid())?;
+        let layout = Layout::new(name).ok_or_else(invalid)?;
+        let values = decode_values(&rest[1 + length..], layout.size()).ok_or_else(invalid)?;
+        Ok((name.to_string(), Table { layout, values }))
+    }
+}
+
+// The tables ignore en passant, so positions where it can be played are left to the search.
+// Every double push sets the square, but only the ones a pawn can take on are turned down
+fn board_from_position(position: &Position) -> Option<Board> {
+    if position.castling_rights().contains(&true) {
+        return None;
+    }
+    if position.en_passant.is_some() && position.legal_moves().into_iter().any(|chess_move| position.is_en_passant(chess_move)) {
+        return None;
+    }
+    let mut board = Board {
+        pieces: [(PieceType::None, Team::None, 0); MAX_PIECES],
+        count: 0,
+        turn: position.current_team,
+    };
+    for x in 0..8 {
+        for y in 0..8 {
+            let piece = position.board[x][y];
+            if piece.team == Team::None {
+                continue;
+            }
+            if board.count == MAX_PIECES {
+                return None;
+            }
+            board.pieces[board.count] = (piece.piece_type, piece.team, (y * 8 + x) as i8);
+            board.count += 1;
+        }
+    }
+    Some(board)
+}
+
+fn position_from_board(board: &Board) -> Position {
+    let mut rows: Vec<String> = vec![];
+    for y in (0..8).rev() {
+        let mut row = String::new();
+        let mut empty = 0;
+        for x in 0..8 {
+            match board.at(y * 8 + x) {
+                Some(i) => {
+                    if empty > 0 {
+                        row.push_str(&empty.to_string());
+                        empty = 0;
+                    }
+                    let (piece_type, team, _) = board.pieces[i];
+                    let letter = piece_letter(piece_type);
+                    row.push(if team == Team::White { letter } else { letter.to_ascii_lowercase() });
+                }
+                None => empty += 1,
+            }
+        }
+        if empty > 0 {
+            row.push_str(&empty.to_string());
+        }
+        rows.push(row);
+    }
+    let turn = if board.turn == Team::White { "w" } else { "b" };
+    Position::from_fen(&format!("{} {} - - 0 1", rows.join("/"), turn)).unwrap()
+}
+
+#[derive(Default)]
+pub struct EndgameTables {
+    tables: HashMap<String, Table>,
+}
+
+impl EndgameTables {
+    // Every .dtm file in the directory
+    pub fn load(directory: &str) -> Result<EndgameTables, String> {
+        let entries = std::fs::read_dir(directory).map_err(|error| format!("Can't read {}: {}", directory, error))?;
+        let mut tables = HashMap::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|extension| extension == "dtm") {
+                let (name, table) = Table::load(&path)?;
+                tables.insert(name, table);
+            }
+        }
+        if tables.is_empty() {
+            return Err(format!("No endgame tables in {}", directory));
+        }
+        Ok(EndgameTables { tables })
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.tables.keys().cloned().collect();
+        names.sort_by_key(|name| (name.len(), DEFAULT_TABLES.iter().position(|default| default == name)));
+        names
+    }
+
+    pub fn probe(&self, position: &Position) -> Option<Dtm> {
+        let value = lookup(&self.tables, &board_from_position(position)?)?;
+        (value != ILLEGAL).then(|| to_dtm(value))
+    }
+
+    // The quickest mate, or the longest resistance when losing. A double push that could be
+    // taken en passant isn't in the tables, so it's passed over
+    pub fn best_move(&self, position: &Position) -> Option<(ChessMove, Dtm)> {
+        let board = board_from_position(position)?;
+        let value = lookup(&self.tables, &board)?;
+        let mut best: Option<(ChessMove, u8)> = None;
+        for chess_move in position.legal_moves() {
+            let Some(child) = board_from_position(&position.make_move(chess_move)) else {
+                continue;
+            };
+            let child_value = negate(lookup(&self.tables, &child)?);
+            if best.is_none_or(|(_, best_value)| rank(child_value) > rank(best_value)) {
+                best = Some((chess_move, child_value));
+            }
+        }
+        best.map(|(chess_move, _)| (chess_move, to_dtm(value)))
+    }
+
+    // A random position from the table that White to move wins in at least `min_moves`, or
+    // the longest win found if there don't seem to be any
+    pub fn random_position(&self, name: &str, min_moves: u32) -> Option<Position> {
+        let table = self.tables.get(name)?;
+        let mut rng = rand::rng();
+        let mut longest: Option<(u8, Board)> = None;
+        for _ in 0..200_000 {
+            let index = rng.random_range(0..table.layout.half());
+            let board = table.layout.board(index);
+            let value = table.values[index];
+            // Illegal entries hold whatever value their run had, so check the board too
+            if !is_win(value) || longest.is_some_and(|(best, _)| best >= value) || !board.valid() || table.layout.index(&board) != index {
+                continue;
+            }
+            longest = Some((value, board));
+            if value as u32 >= min_moves {
+                break;
+            }
+        }
+        longest.map(|(_, board)| position_from_board(&board))
+    }
+}
+
+// chess-again gentb <directory> [tables...]
+pub fn run_generate(args: &[String]) {
+    let Some(directory) = args.first() else {
+        println!("Usage: chess-again gentb <directory> [tables, e.g. KQvK KRvKP]");
+        return;
+    };
+    let wanted: Vec<String> = if args.len() > 1 { args[1..].to_vec() } else { DEFAULT_TABLES.iter().map(|name| name.to_string()).collect() };
+    if let Err(error) = std::fs::create_dir_all(directory) {
+        println!("Can't create {}: {}", directory, error);
+        return;
+    }
+
+    let mut tables: HashMap<String, Table> = HashMap::new();
+    // Tables already on disk don't need making again
+    if let Ok(existing) = EndgameTables::load(directory) {
+        tables = existing.tables;
+    }
+
+    let mut queue: Vec<String> = vec![];
+    for name in &wanted {
+        let Some((white, black)) = parse_material(name) else {
+            println!("{} is not an ending with 3 or 4 pieces", name);
+            return;
+        };
+        queue.push(material_name(white, black).0);
+    }
+
+    while let Some(name) = queue.last().cloned() {
+        if tables.contains_key(&name) {
+            queue.pop();
+            continue;
+        }
+        let missing: Vec<String> = dependencies(&name).into_iter().filter(|dependency| !tables.contains_key(dependency)).collect();
+        if !missing.is_empty() {
+            queue.extend(missing);
+            continue;
+        }
+
+        let start = Instant::now();
+        let table = generate(&name, &tables).unwrap();
+        let longest = table.values.iter().copied().filter(|value| is_win(*value)).max().unwrap_or(0);
+        match table.save(&name, directory) {
+            Ok(()) => println!("{:<8} longest mate {:>2} moves  {:.1}s", name, longest, start.elapsed().as_secs_f64()),
+            Err(error) => {
+                println!("{}", error);
+                return;
+            }
+        }
+        tables.insert(name, table);
+        queue.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate_all(names: &[&str]) -> EndgameTables {
+        let mut tables = HashMap::new();
+        for name in names {
+            let table = generate(name, &tables).unwrap();
+            tables.insert(name.to_string(), table);
+        }
+        EndgameTables { tables }
+    }
+
+    fn longest_win(tables: &EndgameTables, name: &str) -> u8 {
+        tables.tables[name].values.iter().copied().filter(|value| is_win(*value)).max().unwrap()
+    }
+
+    #[test]
+    fn test_unmoves_undo_moves() {
+        let layout = Layout::new("KRvKP").unwrap();
+        for index in (0..layout.size()).step_by(9973) {
+            let board = layout.board(index);
+            if !board.valid() {
+                continue;
+            }
+            board.for_each_move(|child, exit| {
+                if !exit {
+                    let mut found = false;
+                    child.for_each_unmove(|previous| found |= layout.index(&previous) == layout.index(&board));
+                    assert!(found);
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn test_known_mate_lengths() {
+        let tables = generate_all(&["KQvK", "KRvK"]);
+        // The longest wins with the king and queen or rook against a lone king
+        assert_eq!(longest_win(&tables, "KQvK"), 10);
+        assert_eq!(longest_win(&tables, "KRvK"), 16);
+
+        let probe = |fen: &str| tables.probe(&Position::from_fen(fen).unwrap());
+        assert_eq!(probe("k7/8/1K6/8/8/8/8/7R w - - 0 1"), Some(Dtm::Win(1)));
+        assert_eq!(probe("R6k/8/6K1/8/8/8/8/8 b - - 0 1"), Some(Dtm::Loss(0)));
+        assert_eq!(probe("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"), Some(Dtm::Draw)); // Stalemate
+        assert_eq!(probe("8/8/8/8/8/5k2/6q1/7K w - - 0 1"), Some(Dtm::Loss(0))); // Mirrored colours
+        assert!(matches!(probe("k7/8/8/8/8/8/8/K6R w - - 0 1"), Some(Dtm::Win(_))));
+
+        let position = Position::from_fen("k7/8/1K6/8/8/8/8/7R w - - 0 1").unwrap();
+        assert_eq!(tables.best_move(&position).unwrap().0.to_uci(), "h1h8");
+    }
+
+    #[test]
+    fn test_pawn_endings() {
+        let tables = generate_all(&["KQvK", "KRvK", "KBvK", "KNvK", "KPvK"]);
+        let probe = |fen: &str| tables.probe(&Position::from_fen(fen).unwrap());
+        // A king on the sixth in front of its pawn wins whoever moves
+        assert!(matches!(probe("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1"), Some(Dtm::Win(_))));
+        assert!(matches!(probe("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1"), Some(Dtm::Loss(_))));
+        // Stalemate, and a rook pawn against a king in the corner
+        assert_eq!(probe("4k3/4P3/4K3/8/8/8/8/8 b - - 0 1"), Some(Dtm::Draw));
+        assert_eq!(probe("k7/8/8/8/8/8/P7/K7 w - - 0 1"), Some(Dtm::Draw));
+        // Just after a double push the pawn can be taken en passant, which the tables don't know
+        let board = |fen: &str| board_from_position(&Position::from_fen(fen).unwrap());
+        assert!(board("8/8/8/8/3pP3/8/8/K6k b - e3 0 1").is_none());
+        assert!(board("8/8/8/8/2p1P3/8/8/K6k b - e3 0 1").is_some());
+
+        let position = Position::from_fen("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1").unwrap();
+        let (best_move, _) = tables.best_move(&position).unwrap();
+        assert!(matches!(tables.probe(&position.make_move(best_move)), Some(Dtm::Loss(_))));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let tables = generate_all(&["KQvK"]);
+        let directory = std::env::temp_dir().join("chess_again_test_dtm");
+        std::fs::create_dir_all(&directory).unwrap();
+        tables.tables["KQvK"].save("KQvK", directory.to_str().unwrap()).unwrap();
+
+        let loaded = EndgameTables::load(directory.to_str().unwrap()).unwrap();
+        let (original, loaded_values) = (&tables.tables["KQvK"].values, &loaded.tables["KQvK"].values);
+        assert!(original.iter().zip(loaded_values).all(|(a, b)| *a == ILLEGAL || a == b));
+        let file = std::fs::metadata(directory.join("KQvK.dtm")).unwrap().len() as usize;
+        assert!(file < original.len() / 5);
+        std::fs::remove_dir_all(directory).unwrap();
+
+        let position = loaded.random_position("KQvK", 8).unwrap();
+        assert!(matches!(loaded.probe(&position), Some(Dtm::Win(moves)) if moves >= 8));
+    }
+}
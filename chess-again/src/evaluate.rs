@@ -0,0 +1,273 @@
+use std::sync::OnceLock;
+
+use crate::position::Position;
+use crate::see::piece_value;
+use crate::{PieceType, Team};
+
+// Piece-square tables, written from White's side with rank 8 at the top
+const PAWN_TABLE: [[i32; 8]; 8] = [
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [50, 50, 50, 50, 50, 50, 50, 50],
+    [10, 10, 20, 30, 30, 20, 10, 10],
+    [5, 5, 10, 25, 25, 10, 5, 5],
+    [0, 0, 0, 20, 20, 0, 0, 0],
+    [5, -5, -10, 0, 0, -10, -5, 5],
+    [5, 10, 10, -20, -20, 10, 10, 5],
+    [0, 0, 0, 0, 0, 0, 0, 0],
+];
+
+const KNIGHT_TABLE: [[i32; 8]; 8] = [
+    [-50, -40, -30, -30, -30, -30, -40, -50],
+    [-40, -20, 0, 0, 0, 0, -20, -40],
+    [-30, 0, 10, 15, 15, 10, 0, -30],
+    [-30, 5, 15, 20, 20, 15, 5, -30],
+    [-30, 0, 15, 20, 20, 15, 0, -30],
+    [-30, 5, 10, 15, 15, 10, 5, -30],
+    [-40, -20, 0, 5, 5, 0, -20, -40],
+    [-50, -40, -30, -30, -30, -30, -40, -50],
+];
+
+const BISHOP_TABLE: [[i32; 8]; 8] = [
+    [-20, -10, -10, -10, -10, -10, -10, -20],
+    [-10, 0, 0, 0, 0, 0, 0, -10],
+    [-10, 0, 5, 10, 10, 5, 0, -10],
+    [-10, 5, 5, 10, 10, 5, 5, -10],
+    [-10, 0, 10, 10, 10, 10, 0, -10],
+    [-10, 10, 10, 10, 10, 10, 10, -10],
+    [-10, 5, 0, 0, 0, 0, 5, -10],
+    [-20, -10, -10, -10, -10, -10, -10, -20],
+];
+
+const ROOK_TABLE: [[i32; 8]; 8] = [
+    [0, 0, 0, 0, 0, 0, 0, 0],
+    [5, 10, 10, 10, 10, 10, 10, 5],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+    [-5, 0, 0, 0, 0, 0, 0, -5],
+    [0, 0, 0, 5, 5, 0, 0, 0],
+];
+
+const QUEEN_TABLE: [[i32; 8]; 8] = [
+    [-20, -10, -10, -5, -5, -10, -10, -20],
+    [-10, 0, 0, 0, 0, 0, 0, -10],
+    [-10, 0, 5, 5, 5, 5, 0, -10],
+    [-5, 0, 5, 5, 5, 5, 0, -5],
+    [0, 0, 5, 5, 5, 5, 0, -5],
+    [-10, 5, 5, 5, 5, 5, 0, -10],
+    [-10, 0, 5, 0, 0, 0, 0, -10],
+    [-20, -10, -10, -5, -5, -10, -10, -20],
+];
+
+const KING_TABLE: [[i32; 8]; 8] = [
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-30, -40, -40, -50, -50, -40, -40, -30],
+    [-20, -30, -30, -40, -40, -30, -30, -20],
+    [-10, -20, -20, -20, -20, -20, -20, -10],
+    [20, 20, 0, 0, 0, 0, 20, 20],
+    [20, 30, 10, 0, 0, 10, 30, 20],
+];
+
+const PIECE_NAMES: [&str; 6] = ["pawn", "knight", "bishop", "rook", "queen", "king"];
+
+// Everything the evaluation weighs, as the tuner sees it: the material values of pawn to queen,
+// then a square table per piece from pawn to king, written from White's side with a8 first
+pub const PARAM_COUNT: usize = 5 + 6 * 64;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct EvalParams {
+    pub material: [i32; 5],
+    pub tables: [[i32; 64]; 6],
+}
+
+impl Default for EvalParams {
+    fn default() -> Self {
+        let mut tables = [[0; 64]; 6];
+        for (table, default) in tables.iter_mut().zip([&PAWN_TABLE, &KNIGHT_TABLE, &BISHOP_TABLE, &ROOK_TABLE, &QUEEN_TABLE, &KING_TABLE]) {
+            *table = *default.as_flattened().as_array().unwrap();
+        }
+        let material = [PieceType::Pawn, PieceType::Knight, PieceType::Bishop, PieceType::Rook, PieceType::Queen].map(piece_value);
+        Self { material, tables }
+    }
+}
+
+// Set once at startup, before anything evaluates
+static PARAMS: OnceLock<EvalParams> = OnceLock::new();
+
+pub fn params() -> &'static EvalParams {
+    PARAMS.get_or_init(EvalParams::default)
+}
+
+pub fn load_params(path: &str) -> Result<(), String> {
+    let loaded = EvalParams::load(path)?;
+    PARAMS.set(loaded).map_err(|_| "Evaluation parameters are already set".to_string())
+}
+
+fn piece_index(piece_type: PieceType) -> Option<usize> {
+    match piece_type {
+        PieceType::Pawn => Some(0),
+        PieceType::Knight => Some(1),
+        PieceType::Bishop => Some(2),
+        PieceType::Rook => Some(3),
+        PieceType::Queen => Some(4),
+        PieceType::King => Some(5),
+        PieceType::None => None,
+    }
+}
+
+// Material in whole pawns, for the advantage shown next to the board
+pub fn material_points(piece_type: PieceType) -> i32 {
+    piece_index(piece_type).and_then(|index| params().material.get(index)).map_or(0, |value| (value + 50) / 100)
+}
+
+// Where board[x][y] is in a square table for `team`
+fn table_square(team: Team, x: usize, y: usize) -> usize {
+    let row = if team == Team::White { 7 - y } else { y };
+    row * 8 + x
+}
+
+impl EvalParams {
+    pub fn to_vec(&self) -> Vec<i32> {
+        self.material.iter().chain(self.tables.as_flattened()).copied().collect()
+    }
+
+    pub fn from_vec(values: &[i32]) -> EvalParams {
+        let mut params = EvalParams::default();
+        params.material.copy_from_slice(&values[..5]);
+        params.tables.as_flattened_mut().copy_from_slice(&values[5..PARAM_COUNT]);
+        params
+    }
+
+    // A plain text file: "material" and five values, then each piece's name followed by its 64
+    // table values. Anything left out keeps its default, and # starts a comment.
+    pub fn load(path: &str) -> Result<EvalParams, String> {
+        let text = std::fs::read_to_string(path).map_err(|error| format!("Can't read {}: {}", path, error))?;
+        let mut params = EvalParams::default();
+        let mut tokens = text.lines().flat_map(|line| line.split('#').next().unwrap_or("").split_whitespace());
+        while let Some(name) = tokens.next() {
+            let values = if name == "material" {
+                &mut params.material[..]
+            } else if let Some(piece) = PIECE_NAMES.iter().position(|piece| *piece == name) {
+                &mut params.tables[piece][..]
+            } else {
+                return Err(format!("Unknown parameter \"{}\" in {}", name, path));
+            };
+            for value in values.iter_mut() {
+                *value = tokens
+                    .next()
+                    .and_then(|token| token.parse().ok())
+                    .ok_or(format!("Expected more numbers after \"{}\" in {}", name, path))?;
+            }
+        }
+        Ok(params)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let mut text = "# Evaluation parameters in centipawns, square tables from White's side with rank 8 first\n".to_string();
+        text.push_str(&format!("material {}\n", self.material.map(|value| value.to_string()).join(" ")));
+        for (name, table) in PIECE_NAMES.iter().zip(&self.tables) {
+            text.push_str(&format!("\n{}\n", name));
+            for row in table.chunks(8) {
+                let row: Vec<String> = row.iter().map(|value| format!("{:4}", value)).collect();
+                text.push_str(&format!("{}\n", row.join(" ")));
+            }
+        }
+        std::fs::write(path, text).map_err(|error| format!("Can't write {}: {}", path, error))
+    }
+}
+
+// How often each parameter counts towards the score from White's side, so the score is the
+// sum of coefficient times value. Used by the tuner, and gives the same answer as `evaluate`.
+pub fn features(position: &Position) -> Vec<(u16, i8)> {
+    let mut counts = [0i8; PARAM_COUNT];
+    for x in 0..8 {
+        for y in 0..8 {
+            let piece = position.board[x][y];
+            let Some(index) = piece_index(piece.piece_type) else {
+                continue;
+            };
+            let sign = match piece.team {
+                Team::White => 1,
+                Team::Black => -1,
+                Team::None => continue,
+            };
+            if index < 5 {
+                counts[index] += sign;
+            }
+            counts[5 + index * 64 + table_square(piece.team, x, y)] += sign;
+        }
+    }
+    counts
+        .iter()
+        .enumerate()
+        .filter(|(_, count)| **count != 0)
+        .map(|(index, count)| (index as u16, *count))
+        .collect()
+}
+
+// Score in centipawns from the point of view of the side to move
+pub fn evaluate(position: &Position) -> i32 {
+    let params = params();
+    let mut score = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let piece = position.board[x][y];
+            let Some(index) = piece_index(piece.piece_type) else {
+                continue;
+            };
+            let material = params.material.get(index).copied().unwrap_or(0);
+            let value = material + params.tables[index][table_square(piece.team, x, y)];
+            match piece.team {
+                Team::White => score += value,
+                Team::Black => score -= value,
+                Team::None => {}
+            }
+        }
+    }
+
+    if position.current_team == Team::White { score } else { -score }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_features_match_evaluate() {
+        let values = EvalParams::default().to_vec();
+        for fen in [
+            "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4",
+            "8/5k2/3p4/1p1Pp2p/pP2Pp1P/P4P1K/8/8 b - - 99 50",
+            "4k3/8/8/8/8/8/8/4K2Q b - - 0 1",
+        ] {
+            let position = Position::from_fen(fen).unwrap();
+            let white: i32 = features(&position).iter().map(|(index, count)| values[*index as usize] * *count as i32).sum();
+            let expected = if position.current_team == Team::White { white } else { -white };
+            assert_eq!(evaluate(&position), expected, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn test_params_round_trip() {
+        let mut params = EvalParams::default();
+        params.material[1] = 321;
+        params.tables[5][60] = -7;
+        let path = std::env::temp_dir().join("chess_again_test_params.txt");
+        let path = path.to_str().unwrap();
+        params.save(path).unwrap();
+        assert_eq!(EvalParams::load(path).unwrap(), params);
+        assert_eq!(EvalParams::from_vec(&params.to_vec()), params);
+
+        std::fs::write(path, "material 90 310 330 500 950\n# just the pieces\n").unwrap();
+        let loaded = EvalParams::load(path).unwrap();
+        assert_eq!(loaded.material, [90, 310, 330, 500, 950]);
+        assert_eq!(loaded.tables, EvalParams::default().tables);
+
+        std::fs::write(path, "material 90 310\n").unwrap();
+        assert!(EvalParams::load(path).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,441 @@
+use crate::Team;
+use crate::annotation::{self, Annotation};
+use crate::pgn::{MoveText, PgnGame, format_header, wrap_movetext};
+use crate::position::{ChessMove, Position, START_FEN};
+use crate::san::{move_to_san, parse_san};
+
+pub type NodeId = usize;
+
+const ROOT: NodeId = 0;
+
+struct Node {
+    parent: Option<NodeId>,
+    children: Vec<NodeId>, // The first one carries the line on, the rest are variations
+    chess_move: Option<ChessMove>, // None for the starting position
+    san: String,
+    position: Position, // After the move
+    comment: String,
+    annotations: Vec<Annotation>, // Arrows and circled squares drawn on the position
+}
+
+// Every move tried from every position, as a tree whose first branches make up the main
+// line, and which node the board is showing
+pub struct Game {
+    nodes: Vec<Node>, // Deleted variations stay in here but can't be reached
+    current: NodeId,
+    headers: Vec<(String, String)>, // As loaded from PGN, written back when saving
+    result: String,
+}
+
+// The result once the side to move is mated or stalemated
+pub fn board_result(position: &Position) -> Option<&'static str> {
+    if !position.legal_moves().is_empty() {
+        return None;
+    }
+    match (position.in_check(), position.current_team) {
+        (false, _) => Some("1/2-1/2"),
+        (true, Team::White) => Some("0-1"),
+        (true, _) => Some("1-0"),
+    }
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Game::new(Position::default())
+    }
+}
+
+impl Game {
+    pub fn new(start: Position) -> Game {
+        let root = Node { parent: None, children: vec![], chess_move: None, san: String::new(), position: start, comment: String::new(), annotations: vec![] };
+        Game { nodes: vec![root], current: ROOT, headers: vec![], result: "*".to_string() }
+    }
+
+    pub fn position(&self) -> Position {
+        self.nodes[self.current].position
+    }
+
+    pub fn start(&self) -> Position {
+        self.nodes[ROOT].position
+    }
+
+    pub fn root(&self) -> NodeId {
+        ROOT
+    }
+
+    pub fn current(&self) -> NodeId {
+        self.current
+    }
+
+    pub fn children(&self, node: NodeId) -> &[NodeId] {
+        &self.nodes[node].children
+    }
+
+    pub fn san(&self, node: NodeId) -> &str {
+        &self.nodes[node].san
+    }
+
+    pub fn comment(&self, node: NodeId) -> &str {
+        &self.nodes[node].comment
+    }
+
+    pub fn set_comment(&mut self, node: NodeId, comment: String) {
+        self.nodes[node].comment = comment;
+    }
+
+    pub fn annotations(&self, node: NodeId) -> &[Annotation] {
+        &self.nodes[node].annotations
+    }
+
+    pub fn toggle_annotation(&mut self, node: NodeId, drawn: Annotation) {
+        annotation::toggle(&mut self.nodes[node].annotations, drawn);
+    }
+
+    // Where the line through the current node ends up
+    pub fn end_position(&self) -> Position {
+        let end = std::iter::successors(Some(&self.current), |node| self.nodes[**node].children.first()).last().unwrap();
+        self.nodes[*end].position
+    }
+
+    // Whether the current node ends the main line, so a move here carries on the game itself
+    pub fn at_main_line_end(&self) -> bool {
+        self.nodes[self.current].children.is_empty()
+            && std::iter::successors(Some(self.current), |node| self.nodes[*node].parent)
+                .all(|node| self.nodes[node].parent.is_none_or(|parent| self.nodes[parent].children[0] == node))
+    }
+
+    pub fn set_result(&mut self, result: &str) {
+        self.result = result.to_string();
+    }
+
+    // The move that led to the position on the board
+    pub fn last_move(&self) -> Option<ChessMove> {
+        self.nodes[self.current].chess_move
+    }
+
+    // The position the node's move was played from
+    pub fn position_before(&self, node: NodeId) -> Position {
+        let parent = self.nodes[node].parent.unwrap_or(ROOT);
+        self.nodes[parent].position
+    }
+
+    pub fn ply(&self) -> usize {
+        std::iter::successors(self.nodes[self.current].parent, |node| self.nodes[*node].parent).count()
+    }
+
+    // How many plies the line through the current node runs to
+    pub fn line_length(&self) -> usize {
+        self.ply() + std::iter::successors(self.nodes[self.current].children.first(), |node| self.nodes[**node].children.first()).count()
+    }
+
+    fn add(&mut self, parent: NodeId, chess_move: ChessMove) -> NodeId {
+        if let Some(existing) = self.nodes[parent].children.iter().find(|child| self.nodes[**child].chess_move == Some(chess_move)) {
+            return *existing;
+        }
+        let before = self.nodes[parent].position;
+        self.nodes.push(Node {
+            parent: Some(parent),
+            children: vec![],
+            chess_move: Some(chess_move),
+            san: move_to_san(&before, chess_move),
+            position: before.make_move(chess_move),
+            comment: String::new(),
+            annotations: vec![],
+        });
+        let node = self.nodes.len() - 1;
+        self.nodes[parent].children.push(node);
+        node
+    }
+
+    // Steps onto the move if it's already in the tree, otherwise starts a new variation
+    // (or carries on the line, at the end of one)
+    pub fn play(&mut self, chess_move: ChessMove) {
+        self.current = self.add(self.current, chess_move);
+    }
+
+    pub fn go_to(&mut self, node: NodeId) {
+        self.current = node;
+    }
+
+    pub fn back(&mut self) {
+        self.current = self.nodes[self.current].parent.unwrap_or(ROOT);
+    }
+
+    pub fn forward(&mut self) {
+        if let Some(next) = self.nodes[self.current].children.first() {
+            self.current = *next;
+        }
+    }
+
+    pub fn go_to_start(&mut self) {
+        self.current = ROOT;
+    }
+
+    pub fn go_to_end(&mut self) {
+        while let Some(next) = self.nodes[self.current].children.first() {
+            self.current = *next;
+        }
+    }
+
+    // Moves the variation the node is in one level up, so at the top level it becomes
+    // the main line
+    pub fn promote(&mut self, node: NodeId) {
+        let mut node = node;
+        while let Some(parent) = self.nodes[node].parent {
+            let siblings = &mut self.nodes[parent].children;
+            let index = siblings.iter().position(|child| *child == node).unwrap();
+            if index > 0 {
+                siblings.remove(index);
+                siblings.insert(0, node);
+                return;
+            }
+            node = parent;
+        }
+    }
+
+    // Removes the move and everything after it
+    pub fn delete(&mut self, node: NodeId) {
+        let Some(parent) = self.nodes[node].parent else {
+            return;
+        };
+        self.nodes[parent].children.retain(|child| *child != node);
+        let mut ancestor = Some(self.current);
+        while let Some(current) = ancestor {
+            if current == node {
+                self.current = parent;
+                break;
+            }
+            ancestor = self.nodes[current].parent;
+        }
+    }
+
+    pub fn from_pgn(pgn: &PgnGame) -> Result<Game, String> {
+        let start = match pgn.header("FEN") {
+            Some(fen) => Position::from_fen(fen)?,
+            None => Position::default(),
+        };
+        let mut game = Game::new(start);
+        game.headers = pgn.headers.clone();
+        game.result = pgn.result.clone();
+
+        // Where each open variation returns to once it's closed
+        let mut returns: Vec<NodeId> = vec![];
+        let mut current = ROOT;
+        for item in &pgn.movetext {
+            match item {
+                MoveText::Move(text) => {
+                    let position = game.nodes[current].position;
+                    let Some(chess_move) = parse_san(&position, text) else {
+                        return Err(format!("Illegal move {} in {}", text, position.to_fen()));
+                    };
+                    current = game.add(current, chess_move);
+                }
+                MoveText::Comment(text) => {
+                    let (text, annotations) = annotation::split_comment(text);
+                    let node = &mut game.nodes[current];
+                    node.annotations.extend(annotations);
+                    if !node.comment.is_empty() && !text.is_empty() {
+                        node.comment.push(' ');
+                    }
+                    node.comment.push_str(&text);
+                }
+                // A variation is another try at the move just played
+                MoveText::StartVariation => {
+                    returns.push(current);
+                    current = game.nodes[current].parent.ok_or("Variation before the first move")?;
+                }
+                MoveText::EndVariation => current = returns.pop().unwrap_or(current),
+            }
+        }
+        Ok(game)
+    }
+
+    // The comment with the annotations written in as commands, if there's anything to say
+    fn comment_token(&self, node: NodeId) -> Option<String> {
+        let node = &self.nodes[node];
+        let commands = annotation::format_commands(&node.annotations);
+        let text = [node.comment.as_str(), commands.as_str()].into_iter().filter(|part| !part.is_empty()).collect::<Vec<_>>().join(" ");
+        (!text.is_empty()).then(|| format!("{{{}}}", text))
+    }
+
+    fn push_move(&self, node: NodeId, numbered: bool, tokens: &mut Vec<String>) {
+        let before = self.position_before(node);
+        if before.current_team == Team::White {
+            tokens.push(format!("{}.", before.fullmove_number));
+        } else if numbered {
+            tokens.push(format!("{}...", before.fullmove_number));
+        }
+        tokens.push(self.nodes[node].san.clone());
+        tokens.extend(self.comment_token(node));
+    }
+
+    // Writes the line carrying on from `node`, each move followed by the variations on it
+    fn push_line(&self, node: NodeId, mut numbered: bool, tokens: &mut Vec<String>) {
+        let mut node = node;
+        while let Some((&main, variations)) = self.nodes[node].children.split_first() {
+            self.push_move(main, numbered, tokens);
+            numbered = self.comment_token(main).is_some();
+            for &variation in variations {
+                let mut inner = vec![];
+                self.push_move(variation, true, &mut inner);
+                self.push_line(variation, self.comment_token(variation).is_some(), &mut inner);
+                inner[0].insert(0, '(');
+                inner.last_mut().unwrap().push(')');
+                tokens.extend(inner);
+                numbered = true;
+            }
+            node = main;
+        }
+    }
+
+    pub fn to_pgn(&self) -> String {
+        let header = |name: &str, default: &str| {
+            let value = self.headers.iter().find(|(key, _)| key == name).map_or(default, |(_, value)| value.as_str());
+            (name.to_string(), value.to_string())
+        };
+        let mut headers = vec![
+            header("Event", "?"),
+            header("Site", "?"),
+            header("Date", "????.??.??"),
+            header("Round", "?"),
+            header("White", "?"),
+            header("Black", "?"),
+            ("Result".to_string(), self.result.clone()),
+        ];
+        let roster = ["Event", "Site", "Date", "Round", "White", "Black", "Result", "SetUp", "FEN"];
+        headers.extend(self.headers.iter().filter(|(name, _)| !roster.contains(&name.as_str())).cloned());
+        let fen = self.start().to_fen();
+        if fen != START_FEN {
+            headers.push(("SetUp".to_string(), "1".to_string()));
+            headers.push(("FEN".to_string(), fen));
+        }
+
+        let mut text = String::new();
+        for (name, value) in headers {
+            text.push_str(&format_header(&name, &value));
+        }
+        text.push('\n');
+
+        let mut tokens = vec![];
+        tokens.extend(self.comment_token(ROOT));
+        self.push_line(ROOT, true, &mut tokens);
+        tokens.push(self.result.clone());
+        text.push_str(&wrap_movetext(&tokens));
+        text.push('\n');
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PieceType;
+    use crate::pgn::parse_pgn;
+
+    fn play(game: &mut Game, moves: &[&str]) {
+        for text in moves {
+            let chess_move = game.position().parse_uci_move(text).unwrap();
+            game.play(chess_move);
+        }
+    }
+
+    fn main_line(game: &Game) -> Vec<&str> {
+        std::iter::successors(game.children(ROOT).first(), |node| game.children(**node).first()).map(|node| game.san(*node)).collect()
+    }
+
+    #[test]
+    fn test_stepping_back_keeps_the_later_moves() {
+        let mut game = Game::default();
+        play(&mut game, &["e2e4", "d7d5", "e4e5", "f7f5"]);
+        let end = game.position().to_fen();
+
+        game.back();
+        // En passant is only available right after f7f5
+        assert_eq!(game.position().current_team, Team::Black);
+        assert!(game.position().en_passant.is_none());
+        game.go_to_start();
+        assert_eq!(game.position().to_fen(), Position::default().to_fen());
+        assert_eq!((game.ply(), game.line_length()), (0, 4));
+
+        game.go_to_end();
+        assert_eq!(game.position().to_fen(), end);
+        assert_eq!(game.position().en_passant, Some([5, 5]));
+        assert_eq!(main_line(&game), ["e4", "d5", "e5", "f5"]);
+    }
+
+    #[test]
+    fn test_new_moves_become_variations() {
+        let mut game = Game::default();
+        play(&mut game, &["g1f3", "g8f6", "g2g3", "g7g6", "f1g2", "f8g7", "e1g1"]);
+        assert_eq!(game.position().board[6][0].piece_type, PieceType::King);
+        for _ in 0..3 {
+            game.back();
+        }
+        // Replaying a move steps along it, and castling rights come back with the position
+        play(&mut game, &["f1g2"]);
+        assert_eq!(game.line_length(), 7);
+        assert!(game.position().castling_rights()[0]);
+
+        play(&mut game, &["d7d6"]);
+        let variation = game.current();
+        assert_eq!(game.children(game.nodes[variation].parent.unwrap()).len(), 2);
+        assert_eq!(main_line(&game), ["Nf3", "Nf6", "g3", "g6", "Bg2", "Bg7", "O-O"]);
+
+        game.promote(variation);
+        assert_eq!(main_line(&game), ["Nf3", "Nf6", "g3", "g6", "Bg2", "d6"]);
+        game.delete(variation);
+        assert_eq!(main_line(&game), ["Nf3", "Nf6", "g3", "g6", "Bg2", "Bg7", "O-O"]);
+        assert_eq!(game.ply(), 5);
+
+        // The end of a variation isn't where the game carries on
+        play(&mut game, &["c7c6"]);
+        assert!(!game.at_main_line_end());
+        game.go_to_end();
+        assert!(!game.at_main_line_end());
+        game.go_to_start();
+        game.go_to_end();
+        assert!(game.at_main_line_end());
+    }
+
+    #[test]
+    fn test_mate_and_stalemate_end_the_game() {
+        let result = |fen: &str| board_result(&Position::from_fen(fen).unwrap());
+        assert_eq!(result("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1"), Some("1-0"));
+        assert_eq!(result("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"), Some("1/2-1/2"));
+        assert_eq!(result("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1"), None);
+    }
+
+    #[test]
+    fn test_pgn_round_trip() {
+        let text = r#"[Event "Training"]
+[White "A"]
+[Black "B"]
+[Result "1-0"]
+[Annotator "C"]
+
+{A quiet opening} 1. e4 e5 (1... c5 {the Sicilian} 2. Nf3 (2. Nc3 Nc6) d6) 2. Nf3 Nc6 {developing [%cal Gf8c5] [%csl Re5]} 3. Bb5 (3. Bc4 Bc5 (3... Nf6)) a6 1-0
+"#;
+        let pgn = &parse_pgn(text)[0];
+        let game = Game::from_pgn(pgn).unwrap();
+        assert_eq!(main_line(&game), ["e4", "e5", "Nf3", "Nc6", "Bb5", "a6"]);
+        assert_eq!(game.comment(ROOT), "A quiet opening");
+        let nc6 = game.children(game.children(game.children(game.children(ROOT)[0])[0])[0])[0];
+        assert_eq!((game.comment(nc6), game.annotations(nc6).len()), ("developing", 2));
+
+        let written = game.to_pgn();
+        let movetext = written.replace('\n', " ");
+        assert!(written.contains("[Annotator \"C\"]"));
+        assert!(movetext.contains("{A quiet opening} 1. e4 e5 (1... c5 {the Sicilian} 2. Nf3 (2. Nc3 Nc6) 2... d6) 2. Nf3"));
+        assert!(movetext.contains("Nc6 {developing [%csl Re5] [%cal Gf8c5]} 3. Bb5 (3. Bc4 Bc5 (3... Nf6)) 3... a6 1-0"));
+        let again = Game::from_pgn(&parse_pgn(&written)[0]).unwrap();
+        assert_eq!(again.to_pgn(), written);
+
+        // A game set up from a FEN keeps it
+        let mut game = Game::new(Position::from_fen("4k3/8/8/8/8/8/4P3/4K3 b - - 0 1").unwrap());
+        play(&mut game, &["e8d7"]);
+        let written = game.to_pgn();
+        assert!(written.contains("[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 1\"]"));
+        assert!(written.contains("1... Kd7 *"));
+        assert_eq!(main_line(&Game::from_pgn(&parse_pgn(&written)[0]).unwrap()), ["Kd7"]);
+    }
+}
@@ -1,10 +1,91 @@
 use eframe::egui;
 use egui::Color32;
 
+mod analysis;
+mod animation;
+mod annotation;
+mod arena;
+mod bench;
+mod book;
+mod clock;
+mod datagen;
+mod endgame;
+mod evaluate;
+mod game;
+mod mate;
+mod movelist;
+mod nnue;
+mod opponent;
+mod pgn;
+mod position;
+mod san;
+mod search;
+mod see;
+mod settings;
+mod skill;
+mod syzygy;
+mod theme;
+mod timeman;
+mod trainer;
+mod tt;
+mod tune;
+mod uci;
+mod zobrist;
+
+use position::{Position, square_name};
+
 fn main() -> eframe::Result {
     env_logger::init();
+    let mut args: Vec<String> = std::env::args().collect();
+    // `--eval <file>` ahead of everything else swaps in tuned evaluation parameters
+    if args.len() > 2 && args[1] == "--eval" {
+        if let Err(error) = evaluate::load_params(&args[2]) {
+            eprintln!("{}", error);
+        }
+        args.drain(1..3);
+    }
+    match args.get(1).map(|arg| arg.as_str()) {
+        Some("uci") => {
+            uci::run();
+            return Ok(());
+        }
+        Some("bench") => {
+            bench::run(&args[2..]);
+            return Ok(());
+        }
+        Some("gentb") => {
+            endgame::run_generate(&args[2..]);
+            return Ok(());
+        }
+        Some("makebook") => {
+            book::run_makebook(&args[2..]);
+            return Ok(());
+        }
+        Some("datagen") => {
+            datagen::run(&args[2..]);
+            return Ok(());
+        }
+        Some("match") => {
+            arena::run_match(&args[2..]);
+            return Ok(());
+        }
+        Some("tournament") => {
+            arena::run_tournament(&args[2..]);
+            return Ok(());
+        }
+        Some("mate") => {
+            mate::run(&args[2..]);
+            return Ok(());
+        }
+        Some("tune") => {
+            tune::run(&args[2..]);
+            return Ok(());
+        }
+        _ => {}
+    }
+
     let options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default().with_inner_size([640.0, 640.0]),
+        viewport: egui::ViewportBuilder::default().with_inner_size([1240.0, 700.0]),
         ..Default::default()
     };
 
@@ -29,14 +110,14 @@ enum Direction {
     NorthWest
 }
 
-#[derive(std::marker::Copy, Clone, PartialEq)]
+#[derive(std::marker::Copy, Clone, PartialEq, Debug)]
 enum Team {
     Black,
     White,
     None
 }
 
-#[derive(std::marker::Copy, Clone, PartialEq)]
+#[derive(std::marker::Copy, Clone, PartialEq, Debug)]
 enum PieceType {
     Rook,
     Knight,
@@ -301,16 +382,18 @@ fn final_move_list(piece: Piece, pos: [isize; 2], board: &[[Piece; 8]; 8], filte
 }
 
 fn is_in_check(team: Team, board: &[[Piece; 8]; 8]) -> bool {
+    let enemy = match team {
+        Team::White => Team::Black,
+        Team::Black => Team::White,
+        Team::None => return false,
+    };
+
+    // Looking outwards from the king is much cheaper than generating every enemy move
     for y in (0..=7).rev() {
         for x in 0..=7 {
             let piece = board[x as usize][y as usize];
-            if piece.team != team && piece.piece_type != PieceType::None { // On other team
-                let moves = final_move_list(board[x as usize][y as usize], [x, y], board, false);
-                for available_moves in moves {
-                    if board[available_moves[0] as usize][available_moves[1] as usize].piece_type == PieceType::King && board[available_moves[0] as usize][available_moves[1] as usize].team == team {
-                        return true;
-                    }
-                }
+            if piece.piece_type == PieceType::King && piece.team == team && !see::attackers_to(board, [x, y], enemy).is_empty() {
+                return true;
             }
         }
     }
@@ -318,6 +401,8 @@ fn is_in_check(team: Team, board: &[[Piece; 8]; 8]) -> bool {
     return false;
 }
 
+// Only the tests still use this; games end through game::board_result
+#[cfg(test)]
 fn is_stalemate(team: Team, board: &[[Piece; 8]; 8]) -> bool {
     let mut found_piece = false;
     for y in (0..=7).rev() {
@@ -340,6 +425,11 @@ fn is_stalemate(team: Team, board: &[[Piece; 8]; 8]) -> bool {
     return true;
 }
 
+const MIN_SQUARE: f32 = 24.0;
+const COORDINATE_MARGIN: f32 = 20.0; // Left of and under the board, for coordinates outside it
+// Room kept under the board for the clock, material count and hanging pieces
+const BELOW_BOARD: f32 = 110.0;
+
 fn calculate_advantage(board: &[[Piece; 8]; 8]) -> (i32, i32) {
     let mut white_team = 0;
     let mut black_team = 0;
@@ -348,23 +438,9 @@ fn calculate_advantage(board: &[[Piece; 8]; 8]) -> (i32, i32) {
         for x in 0..=7 {
             let piece = board[x][y];
             if piece.team == Team::White {
-                match piece.piece_type {
-                    PieceType::Pawn => { white_team += 1; },
-                    PieceType::Knight => { white_team += 3; },
-                    PieceType::Bishop => { white_team += 3; },
-                    PieceType::Rook => { white_team += 5; },
-                    PieceType::Queen => { white_team += 9; },
-                    _ => {}
-                }
+                white_team += evaluate::material_points(piece.piece_type);
             } else {
-                match piece.piece_type {
-                    PieceType::Pawn => { black_team += 1; },
-                    PieceType::Knight => { black_team += 3; },
-                    PieceType::Bishop => { black_team += 3; },
-                    PieceType::Rook => { black_team += 5; },
-                    PieceType::Queen => { black_team += 9; },
-                    _ => {}
-                }
+                black_team += evaluate::material_points(piece.piece_type);
             }
         }
     }
@@ -372,121 +448,6 @@ fn calculate_advantage(board: &[[Piece; 8]; 8]) -> (i32, i32) {
     return (white_team, black_team);
 }
 
-fn castle(can_castle: bool, x: i32, y: i32, board: &mut [[Piece; 8]; 8], selected_piece_0: i32, selected_piece_1: i32, current_team: &mut Team, history: &mut Vec<[[Piece; 8]; 8]>) {
-    let selected_piece = [selected_piece_0, selected_piece_1];
-    if can_castle && [x,y] == [6,0] || [x,y] == [2,0] || [x,y] == [6,7] || [x,y] == [2,7] {
-        let current_piece = board[selected_piece[0] as usize][selected_piece[1] as usize];
-        if current_piece.piece_type == PieceType::King && current_piece.team == *current_team {
-            if current_piece.team == Team::White {
-                if [x, y] == [6, 0] {
-                    if board[(selected_piece[0]+1) as usize][selected_piece[1] as usize].piece_type == PieceType::None                                                            && board[(selected_piece[0]+2) as usize][selected_piece[1] as usize].piece_type == PieceType::None {
-                        history.insert(0, *board);
-                        board[x as usize][y as usize] = board[selected_piece[0] as usize][selected_piece[1] as usize];
-                        board[x as usize][y as usize].moved = true;
-                        board[selected_piece[0] as usize][selected_piece[1] as usize].team = Team::None;
-                        board[selected_piece[0] as usize][selected_piece[1] as usize].piece_type = PieceType::None;
-                        board[7][0].team = Team::None;
-                        board[7][0].piece_type = PieceType::None;
-                        board[5][0].team = Team::White;
-                        board[5][0].piece_type = PieceType::Rook;
-
-                        switch_teams(current_team);
-                    }
-                } else if [x, y] == [2, 0] {
-                    if board[(selected_piece[0]-1) as usize][selected_piece[1] as usize].piece_type == PieceType::None                                                            && board[(selected_piece[0]-2) as usize][selected_piece[1] as usize].piece_type == PieceType::None
-                       && board[(selected_piece[0]-3) as usize][selected_piece[1] as usize].piece_type == PieceType::None {
-                        history.insert(0, *board);
-                        board[x as usize][y as usize] = board[selected_piece[0] as usize][selected_piece[1] as usize];
-                        board[x as usize][y as usize].moved = true;
-                        board[selected_piece[0] as usize][selected_piece[1] as usize].team = Team::None;
-                        board[selected_piece[0] as usize][selected_piece[1] as usize].piece_type = PieceType::None;
-                        board[0][0].team = Team::None;
-                        board[0][0].piece_type = PieceType::None;
-                        board[3][0].team = Team::White;
-                        board[3][0].piece_type = PieceType::Rook;
-
-                        switch_teams(current_team);
-                    }
-                }
-            } else {
-                if [x, y] == [6, 7] {
-                    if board[(selected_piece[0]+1) as usize][selected_piece[1] as usize].piece_type == PieceType::None                                                            && board[(selected_piece[0]+2) as usize][selected_piece[1] as usize].piece_type == PieceType::None {
-                        history.insert(0, *board);
-                        board[x as usize][y as usize] = board[selected_piece[0] as usize][selected_piece[1] as usize];
-                        board[x as usize][y as usize].moved = true;
-                        board[selected_piece[0] as usize][selected_piece[1] as usize].team = Team::None;
-                        board[selected_piece[0] as usize][selected_piece[1] as usize].piece_type = PieceType::None;
-                        board[7][7].team = Team::None;
-                        board[7][7].piece_type = PieceType::None;
-                        board[5][7].team = Team::Black;
-                        board[5][7].piece_type = PieceType::Rook;
-
-                        switch_teams(current_team);
-                    }
-                } else if [x, y] == [2, 7] {
-                    if board[(selected_piece[0]-1) as usize][selected_piece[1] as usize].piece_type == PieceType::None                                                            && board[(selected_piece[0]-2) as usize][selected_piece[1] as usize].piece_type == PieceType::None
-                       && board[(selected_piece[0]-3) as usize][selected_piece[1] as usize].piece_type == PieceType::None {
-                        history.insert(0, *board);
-                        board[x as usize][y as usize] = board[selected_piece[0] as usize][selected_piece[1] as usize];
-                        board[x as usize][y as usize].moved = true;
-                        board[selected_piece[0] as usize][selected_piece[1] as usize].team = Team::None;
-                        board[selected_piece[0] as usize][selected_piece[1] as usize].piece_type = PieceType::None;
-                        board[0][7].team = Team::None;
-                        board[0][7].piece_type = PieceType::None;
-                        board[3][7].team = Team::Black;
-                        board[3][7].piece_type = PieceType::Rook;
-
-                        switch_teams(current_team);
-                    }
-                }
-            }
-        }
-    }
-}
-
-fn en_passant(x: i32, y: i32, board: &mut [[Piece; 8]; 8], selected_piece_0: i32, selected_piece_1: i32, current_team: &mut Team, history: &mut Vec<[[Piece; 8]; 8]>) {
-    let selected_piece = [selected_piece_0, selected_piece_1];
-    if board[selected_piece[0] as usize][selected_piece[1] as usize].piece_type == PieceType::Pawn {
-        if y == 2 { // White en passant
-            if board[x as usize][(y+1) as usize].piece_type == PieceType::Pawn &&
-            board[x as usize][(y+1) as usize].team == Team::White {
-                if let Some(i) = history.first() {
-                    if i[x as usize][(y-1) as usize].piece_type == PieceType::Pawn &&
-                    i[x as usize][(y-1) as usize].team == Team::White &&
-                    i[x as usize][(y-1) as usize].moved == false {
-                        history.insert(0, *board);
-                        board[x as usize][y as usize] = board[selected_piece[0] as usize][selected_piece[1] as usize];
-                        board[x as usize][(y+1) as usize].piece_type = PieceType::None;
-                        board[x as usize][(y+1) as usize].team = Team::None;
-                        board[selected_piece[0] as usize][selected_piece[1] as usize].piece_type = PieceType::None;
-                        board[selected_piece[0] as usize][selected_piece[1] as usize].team = Team::None;
-                        
-                        switch_teams(current_team);
-                    }
-                }
-            }
-        } else if y == 5 { // Black en passant
-            if board[x as usize][(y-1) as usize].piece_type == PieceType::Pawn &&
-            board[x as usize][(y-1) as usize].team == Team::Black {
-                if let Some(i) = history.first() {
-                    if i[x as usize][(y+1) as usize].piece_type == PieceType::Pawn &&
-                    i[x as usize][(y+1) as usize].team == Team::Black &&
-                    i[x as usize][(y+1) as usize].moved == false {
-                        history.insert(0, *board);
-                        board[x as usize][y as usize] = board[selected_piece[0] as usize][selected_piece[1] as usize];
-                        board[x as usize][(y-1) as usize].piece_type = PieceType::None;
-                        board[x as usize][(y-1) as usize].team = Team::None;
-                        board[selected_piece[0] as usize][selected_piece[1] as usize].piece_type = PieceType::None;
-                        board[selected_piece[0] as usize][selected_piece[1] as usize].team = Team::None;
-
-                        switch_teams(current_team);
-                    }
-                }
-            }
-        }
-    }
-}
-
 fn switch_teams(team: &mut Team) {
     *team = match *team {
         Team::Black => Team::White,
@@ -495,40 +456,376 @@ fn switch_teams(team: &mut Team) {
     }
 }
 
+enum Step {
+    Back(usize),
+    Forward(usize),
+    Start,
+    End,
+}
+
 struct Chess {
     board: [[Piece; 8]; 8],
-    history: Vec<[[Piece; 8]; 8]>,
     valid_moves: Vec<[isize; 2]>,
-    can_castle: bool,
-    can_en_passant: bool,
     current_team: Team,
     piece_selected: bool,
     selected_piece: [isize; 2],
     advantage: (i32, i32),
-    selecting_promotion: bool
+    flipped: bool, // Black at the bottom
+    dragging: Option<[isize; 2]>, // Square the piece under the mouse was picked up from
+    annotating: Option<[isize; 2]>, // Where a right-drag started
+    animation: Option<animation::Animation>,
+    square_rects: [[egui::Rect; 8]; 8], // Where each square was drawn last frame
+    settings: settings::Settings,
+    selecting_promotion: bool,
+    analysis: analysis::Analysis,
+    trainer: trainer::Trainer,
+    opponent: opponent::Opponent,
+    clocks: clock::Clocks,
+    game: game::Game,
+    move_list: movelist::MoveList
 }
 
 impl Default for Chess {
     fn default() -> Self {
         Self {
             board: create_board(),
-            history: vec![],
             valid_moves: vec![],
-            can_castle: true,
-            can_en_passant: true,
             current_team: Team::White,
             piece_selected: false,
             selected_piece: [0, 0],
             advantage: (0, 0),
-            selecting_promotion: false
+            flipped: false,
+            dragging: None,
+            annotating: None,
+            animation: None,
+            square_rects: [[egui::Rect::NOTHING; 8]; 8],
+            settings: settings::Settings::load(settings::SETTINGS_FILE),
+            selecting_promotion: false,
+            analysis: analysis::Analysis::default(),
+            trainer: trainer::Trainer::default(),
+            opponent: opponent::Opponent::default(),
+            clocks: clock::Clocks::default(),
+            game: game::Game::default(),
+            move_list: movelist::MoveList::default()
+        }
+    }
+}
+
+impl Chess {
+    fn position(&self) -> Position {
+        self.game.position()
+    }
+
+    // Shows whichever position the game is on, sliding the pieces there
+    fn refresh_board(&mut self) {
+        let position = self.game.position();
+        self.animation = animation::Animation::between(&self.board, &position.board, &self.square_rects, self.settings.animation());
+        self.dragging = None;
+        self.board = position.board;
+        self.current_team = position.current_team;
+        self.piece_selected = false;
+        self.advantage = calculate_advantage(&self.board);
+    }
+
+    fn set_position(&mut self, position: Position) {
+        self.game = game::Game::new(position);
+        self.refresh_board();
+    }
+
+    fn play_move(&mut self, chess_move: position::ChessMove) {
+        // Variations tried from earlier positions are analysis, and leave the clocks alone
+        let extends_game = self.game.at_main_line_end();
+        self.game.play(chess_move);
+        if extends_game {
+            self.clocks.moved(self.game.position().current_team);
+            if let Some(result) = game::board_result(&self.game.position()) {
+                self.game.set_result(result);
+            }
+        }
+        self.refresh_board();
+    }
+
+    fn select(&mut self, position: &Position, square: [isize; 2]) {
+        self.selected_piece = square;
+        self.piece_selected = true;
+        self.valid_moves = position.legal_moves().into_iter()
+            .filter(|chess_move| chess_move.from == square)
+            .map(|chess_move| chess_move.to)
+            .collect();
+    }
+
+    // Plays the move if it's legal
+    fn try_move(&mut self, from: [isize; 2], to: [isize; 2]) -> bool {
+        // Promotions come queen first, which is what gets played
+        let chess_move = self.position().legal_moves().into_iter()
+            .find(|chess_move| chess_move.from == from && chess_move.to == to);
+        let Some(chess_move) = chess_move else {
+            return false;
+        };
+        self.play_move(chess_move);
+        true
+    }
+
+    fn square_at(&self, at: egui::Pos2) -> Option<[isize; 2]> {
+        (0..8).flat_map(|x| (0..8).map(move |y| [x, y]))
+            .find(|square: &[isize; 2]| self.square_rects[square[0] as usize][square[1] as usize].contains(at))
+    }
+
+    // The arrow being drawn, or a circle if it's let go on the square it started from
+    fn drawn_annotation(&self, ctx: &egui::Context, from: [isize; 2]) -> Option<annotation::Annotation> {
+        let (pointer, modifiers) = ctx.input(|input| (input.pointer.latest_pos(), input.modifiers));
+        let to = self.square_at(pointer?)?;
+        Some(annotation::Annotation { colour: annotation::Colour::from_modifiers(modifiers), from, to })
+    }
+
+    fn show_annotations(&self, ui: &egui::Ui) {
+        for drawn in self.game.annotations(self.game.current()) {
+            annotation::paint(ui.painter(), *drawn, &self.square_rects);
+        }
+        if let Some(from) = self.annotating && let Some(drawing) = self.drawn_annotation(ui.ctx(), from) {
+            annotation::paint(ui.painter(), drawing, &self.square_rects);
+        }
+    }
+
+    // Ranks up the left and files along the bottom, whichever way round the board is
+    fn show_coordinates(&self, ui: &egui::Ui, ranks: &[isize], files: &[isize]) {
+        let side = self.square_rects[0][0].width();
+        let font = egui::FontId::proportional((side * 0.2).clamp(10.0, COORDINATE_MARGIN * 0.8));
+        for &y in ranks {
+            for &x in files {
+                let rect = self.square_rects[x as usize][y as usize];
+                let rank = ((b'1' + y as u8) as char).to_string();
+                let file = ((b'a' + x as u8) as char).to_string();
+                match self.settings.coordinates() {
+                    settings::Coordinates::Inside => {
+                        // In the other square colour
+                        let colour = self.settings.square_colour([x + 1, y]);
+                        let inset = egui::vec2(side * 0.05, side * 0.03);
+                        if x == files[0] {
+                            ui.painter().text(rect.left_top() + inset, egui::Align2::LEFT_TOP, rank, font.clone(), colour);
+                        }
+                        if y == ranks[7] {
+                            ui.painter().text(rect.right_bottom() - inset, egui::Align2::RIGHT_BOTTOM, file, font.clone(), colour);
+                        }
+                    }
+                    settings::Coordinates::Outside => {
+                        let colour = ui.visuals().text_color();
+                        let gap = COORDINATE_MARGIN / 2.0;
+                        if x == files[0] {
+                            ui.painter().text(rect.left_center() - egui::vec2(gap, 0.0), egui::Align2::CENTER_CENTER, rank, font.clone(), colour);
+                        }
+                        if y == ranks[7] {
+                            ui.painter().text(rect.center_bottom() + egui::vec2(0.0, gap), egui::Align2::CENTER_CENTER, file, font.clone(), colour);
+                        }
+                    }
+                    settings::Coordinates::Hidden => {}
+                }
+            }
+        }
+    }
+
+    // Plays the dragged piece onto the square it was let go over, or slides it back
+    fn drop_piece(&mut self, at: egui::Pos2) {
+        let Some(from) = self.dragging.take() else {
+            return;
+        };
+        let target = self.square_at(at);
+        if target == Some(from) {
+            return; // Put back down, so it stays picked for clicking a square instead
+        }
+        if let Some(to) = target && self.try_move(from, to) {
+            if let Some(animation) = &mut self.animation {
+                animation.skip(to);
+            }
+            return;
+        }
+        self.piece_selected = false;
+        let piece = self.board[from[0] as usize][from[1] as usize];
+        let sprite = animation::Sprite { piece, from: at, to: from, fade: animation::Fade::None };
+        self.animation = animation::Animation::new(vec![sprite], self.settings.animation());
+    }
+
+    fn show_dragged_piece(&self, ctx: &egui::Context, from: [isize; 2]) {
+        let Some(pointer) = ctx.input(|input| input.pointer.latest_pos()) else {
+            return;
+        };
+        let piece = self.board[from[0] as usize][from[1] as usize];
+        let size = self.square_rects[from[0] as usize][from[1] as usize].size();
+        egui::Area::new(egui::Id::new("dragged piece")).order(egui::Order::Tooltip).fixed_pos(egui::Pos2::ZERO).interactable(false).show(ctx, |ui| {
+            self.settings.pieces().picture(piece, size).paint_at(ui, egui::Rect::from_center_size(pointer, size));
+        });
+    }
+
+    fn navigate(&mut self, step: Step) {
+        match step {
+            Step::Back(plies) => (0..plies).for_each(|_| self.game.back()),
+            Step::Forward(plies) => (0..plies).for_each(|_| self.game.forward()),
+            Step::Start => self.game.go_to_start(),
+            Step::End => self.game.go_to_end(),
+        }
+        self.refresh_board();
+    }
+
+    // Against the computer a takeback covers your move and its reply
+    fn undo_plies(&self) -> usize {
+        if self.opponent.playing() && !self.opponent.computers_turn(self.current_team) { 2 } else { 1 }
+    }
+
+    fn handle_shortcuts(&mut self, ctx: &egui::Context) {
+        if ctx.memory(|memory| memory.focused().is_some()) {
+            return; // The keys belong to whatever is being typed in
+        }
+        let undo = egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Z);
+        let redo = egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Y);
+        let shift_redo = egui::KeyboardShortcut::new(egui::Modifiers::COMMAND | egui::Modifiers::SHIFT, egui::Key::Z);
+        let steps = self.undo_plies();
+        let step = ctx.input_mut(|input| {
+            // Ctrl+Shift+Z would match Ctrl+Z too, so it goes first
+            if input.consume_shortcut(&shift_redo) || input.consume_shortcut(&redo) {
+                Some(Step::Forward(steps))
+            } else if input.consume_shortcut(&undo) {
+                Some(Step::Back(steps))
+            } else if input.consume_key(egui::Modifiers::NONE, egui::Key::ArrowLeft) {
+                Some(Step::Back(1))
+            } else if input.consume_key(egui::Modifiers::NONE, egui::Key::ArrowRight) {
+                Some(Step::Forward(1))
+            } else if input.consume_key(egui::Modifiers::NONE, egui::Key::Home) {
+                Some(Step::Start)
+            } else if input.consume_key(egui::Modifiers::NONE, egui::Key::End) {
+                Some(Step::End)
+            } else {
+                None
+            }
+        });
+        if let Some(step) = step {
+            self.navigate(step);
+        }
+        if ctx.input_mut(|input| input.consume_key(egui::Modifiers::NONE, egui::Key::F)) {
+            self.flipped = !self.flipped;
+        }
+    }
+
+    // Analysis, training, the computer opponent, clocks and settings
+    fn show_tools(&mut self, ui: &mut egui::Ui, position: &Position, latest: &Position, browsing: bool) -> (Option<trainer::TrainerAction>, Option<opponent::OpponentAction>) {
+        self.analysis.show(ui, position);
+        ui.separator();
+        let trainer_action = self.trainer.show(ui, position);
+        ui.separator();
+        let opponent_action = self.opponent.show(ui, position, browsing);
+        ui.separator();
+        self.clocks.show(ui, latest);
+        ui.separator();
+        self.settings.show(ui);
+        (trainer_action, opponent_action)
+    }
+
+    // The material lead, if the side has one
+    fn show_advantage(&self, ui: &mut egui::Ui, team: Team) {
+        let (own, other) = if team == Team::White { self.advantage } else { (self.advantage.1, self.advantage.0) };
+        // Always takes up the line, so the board doesn't change size as pieces come off
+        let text = if own > other { format!("+{}", own - other) } else { " ".to_string() };
+        ui.label(egui::RichText::new(text).color(egui::Color32::from_rgb(255, 255, 255)).size(25.0));
+    }
+
+    fn show_navigation(&mut self, ui: &mut egui::Ui) {
+        let (ply, length, steps) = (self.game.ply(), self.game.line_length(), self.undo_plies());
+        let mut step = None;
+        ui.horizontal(|ui| {
+            if ui.add_enabled(ply > 0, egui::Button::new("Undo")).on_hover_text("Ctrl+Z").clicked() {
+                step = Some(Step::Back(steps));
+            }
+            if ui.add_enabled(ply < length, egui::Button::new("Redo")).on_hover_text("Ctrl+Y").clicked() {
+                step = Some(Step::Forward(steps));
+            }
+            ui.separator();
+            let buttons = [
+                ("|<", "Start (Home)", ply > 0, Step::Start),
+                ("<", "Back (Left)", ply > 0, Step::Back(1)),
+                (">", "Forward (Right)", ply < length, Step::Forward(1)),
+                (">|", "End of the line (End)", ply < length, Step::End),
+            ];
+            for (text, hint, enabled, target) in buttons {
+                if ui.add_enabled(enabled, egui::Button::new(text)).on_hover_text(hint).clicked() {
+                    step = Some(target);
+                }
+            }
+            ui.label(format!("Ply {} of {}", ply, length));
+            ui.separator();
+            if ui.button("Flip board").on_hover_text("F").clicked() {
+                self.flipped = !self.flipped;
+            }
+        });
+        if let Some(step) = step {
+            self.navigate(step);
+            ui.ctx().request_repaint();
         }
     }
 }
 
 impl eframe::App for Chess {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.handle_shortcuts(ctx);
+        let position = self.position();
+        let browsing = !self.game.children(self.game.current()).is_empty();
+        let latest = self.game.end_position();
+        if let Some(result) = self.clocks.check_flag(&latest) {
+            self.game.set_result(result);
+        }
+        let mut moved = false;
+        egui::SidePanel::left("moves")
+            .default_width(220.0)
+            .show(ctx, |ui| {
+                moved = self.move_list.show(ui, &mut self.game);
+            });
+        if moved {
+            self.refresh_board();
+        }
+        let tools = |chess: &mut Chess, ui: &mut egui::Ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| chess.show_tools(ui, &position, &latest, browsing)).inner
+        };
+        let (trainer_action, opponent_action) = if self.settings.tools_window() {
+            let builder = egui::ViewportBuilder::default().with_title("Chess tools").with_inner_size([380.0, 700.0]);
+            ctx.show_viewport_immediate(egui::ViewportId::from_hash_of("tools"), builder, |ctx, class| {
+                if ctx.input(|input| input.viewport().close_requested()) {
+                    self.settings.dock_tools();
+                }
+                if class == egui::ViewportClass::Embedded {
+                    // Without native windows it floats over the board instead
+                    egui::Window::new("Tools").show(ctx, |ui| tools(self, ui)).and_then(|response| response.inner).unwrap_or_default()
+                } else {
+                    egui::CentralPanel::default().show(ctx, |ui| tools(self, ui)).inner
+                }
+            })
+        } else {
+            egui::SidePanel::right("analysis")
+                .default_width(360.0)
+                .show(ctx, |ui| tools(self, ui))
+                .inner
+        };
+        match trainer_action {
+            Some(trainer::TrainerAction::Setup(start)) => self.set_position(start),
+            Some(trainer::TrainerAction::Play(reply)) => self.play_move(reply),
+            None => {}
+        }
+        if trainer_action.is_some() {
+            ctx.request_repaint();
+        }
+        match opponent_action {
+            Some(opponent::OpponentAction::NewGame) => {
+                self.set_position(Position::default());
+                // Your own side goes at the bottom
+                self.flipped = self.opponent.computers_turn(Team::White);
+            }
+            Some(opponent::OpponentAction::Play(reply)) => self.play_move(reply),
+            None => {}
+        }
+        if opponent_action.is_some() {
+            ctx.request_repaint();
+        }
+        self.analysis.set_endgame_tables(self.trainer.tables());
 
         egui::CentralPanel::default().show(ctx, |ui| {
+        let panel_height = ui.available_height();
         egui::ScrollArea::vertical().show(ui, |ui| {
             match self.current_team {
                 Team::Black => { ui.label(egui::RichText::new("Current Team: Black").color(egui::Color32::from_rgb(255, 255, 255)).size(30.0)); }
@@ -537,28 +834,47 @@ impl eframe::App for Chess {
                     self.current_team = Team::White;
                 }
             }
-
-            if self.advantage.1 > self.advantage.0 {
-                ui.label(egui::RichText::new(format!("+{}", self.advantage.1 - self.advantage.0)).color(egui::Color32::from_rgb(255, 255, 255)).size(25.0));
-            }
-
+            self.show_navigation(ui);
+
+            let (top, bottom) = if self.flipped { (Team::White, Team::Black) } else { (Team::Black, Team::White) };
+            self.show_advantage(ui, top);
+
+            let hanging = see::hanging_pieces(&position, self.current_team);
+            let highlights = self.settings.highlights();
+            let last_move = self.game.last_move();
+            let king_in_check = position.in_check().then(|| position.king_square(position.current_team)).flatten();
+            self.clocks.show_face(ui, top);
+            let ranks: Vec<isize> = if self.flipped { (0..=7).collect() } else { (0..=7).rev().collect() };
+            let files: Vec<isize> = if self.flipped { (0..=7).rev().collect() } else { (0..=7).collect() };
+
+            // The board fills whatever room the panel has, in whole pixels so the squares line up
+            let margin = if self.settings.coordinates() == settings::Coordinates::Outside { COORDINATE_MARGIN } else { 0.0 };
+            let above = ui.min_rect().height();
+            let room = (ui.available_width() - margin).min(panel_height - above - BELOW_BOARD - margin);
+            let pixels_per_point = ctx.pixels_per_point();
+            let side = ((room / 8.0).max(MIN_SQUARE) * pixels_per_point).floor() / pixels_per_point;
+            let square = egui::vec2(side, side);
+
+            let mut dropped = None;
+            let mut annotated = None;
+            ui.horizontal(|ui| {
+            ui.add_space(margin);
             egui::Grid::new("grid")
-                .min_col_width(64.0)
-                .min_row_height(64.0)
+                .spacing([0.0, 0.0])
+                .min_col_width(side)
+                .min_row_height(side)
                 .show(ui, |ui| {
-                for y in (0..=7).rev() {
-                    for x in 0..=7 {
-                        let mut bg_colour = if [x, y] == self.selected_piece && self.piece_selected == true {
-                            Color32::from_rgb(0, 0, 255)
-                        } else {
-                            if (x+y) % 2 == 0 {
-                                Color32::from_rgb(117, 149, 85)
-                            } else {
-                                Color32::from_rgb(237, 237, 209)
-                            }
-                        };
-                        if self.valid_moves.contains(&[x, y]) && self.piece_selected == true {
-                            bg_colour = Color32::from_rgb(255, 0, 0);
+                for &y in &ranks {
+                    for &x in &files {
+                        let mut bg_colour = self.settings.square_colour([x, y]);
+                        if last_move.is_some_and(|chess_move| chess_move.from == [x, y] || chess_move.to == [x, y]) {
+                            bg_colour = bg_colour.blend(highlights.last_move);
+                        }
+                        if hanging.contains(&[x, y]) {
+                            bg_colour = bg_colour.blend(Color32::from_rgba_unmultiplied(255, 165, 0, 150));
+                        }
+                        if self.piece_selected && [x, y] == self.selected_piece {
+                            bg_colour = bg_colour.blend(highlights.selected);
                         }
                         let frame = egui::Frame::new()
                             .fill(bg_colour)
@@ -566,111 +882,59 @@ impl eframe::App for Chess {
                         
                         frame.show(ui, |ui| {
                             let piece = self.board[x as usize][y as usize];
-                            let image = match (piece.team, piece.piece_type) {
-                                (Team::Black, PieceType::Rook) => egui::include_image!("../assets/black_rook.png"),
-                                (Team::Black, PieceType::Knight) => egui::include_image!("../assets/black_knight.png"),
-                                (Team::Black, PieceType::Bishop) => egui::include_image!("../assets/black_bishop.png"),
-                                (Team::Black, PieceType::Queen) => egui::include_image!("../assets/black_queen.png"),
-                                (Team::Black, PieceType::King) => egui::include_image!("../assets/black_king.png"),
-                                (Team::Black, PieceType::Pawn) => egui::include_image!("../assets/black_pawn.png"),
-                                (Team::White, PieceType::Rook) => egui::include_image!("../assets/white_rook.png"),
-                                (Team::White, PieceType::Knight) => egui::include_image!("../assets/white_knight.png"),
-                                (Team::White, PieceType::Bishop) => egui::include_image!("../assets/white_bishop.png"),
-                                (Team::White, PieceType::Queen) => egui::include_image!("../assets/white_queen.png"),
-                                (Team::White, PieceType::King) => egui::include_image!("../assets/white_king.png"),
-                                (Team::White, PieceType::Pawn) => egui::include_image!("../assets/white_pawn.png"),
-                                _ => egui::include_image!("../assets/blank.png"),
-                            };
+                            // Lifted by the mouse, or still on its way here
+                            let hidden = self.dragging == Some([x, y]) || self.animation.as_ref().is_some_and(|animation| animation.hides([x, y]));
+                            let shown = if hidden { create_piece(PieceType::None, Team::None) } else { piece };
 
+                            // Kept under the piece, which is only drawn next
+                            let glow = ui.painter().add(egui::Shape::Noop);
                             let response = ui.add(
-                                egui::ImageButton::new(image)
+                                egui::ImageButton::new(self.settings.pieces().picture(shown, square))
                                     .frame(false)
+                                    .sense(egui::Sense::click_and_drag())
                             );
-
-                            if response.clicked() {
-                                if self.piece_selected == false {
-                                    if self.board[x as usize][y as usize].team == self.current_team { 
-                                        self.selected_piece = [x as isize, y as isize];
-                                        self.piece_selected = true;
-                                        self.valid_moves = final_move_list(self.board[x as usize][y as usize], [x, y], &self.board, true);
-                                        // Check for castling
-                                        
-                                        self.can_castle = false;
-                                        self.can_en_passant = false;
-                                        
-                                        if self.board[x as usize][y as usize].piece_type == PieceType::King && self.board[x as usize][y as usize].moved == false {
-                                            // King able to castle
-                                            if self.board[(x+3) as usize][y as usize].piece_type == PieceType::Rook && self.board[(x+3) as usize][y as usize].moved == false {
-                                                // Rook able to castle
-                                                self.can_castle = true;
-                                            }
-                                        }
-
-                                        if self.board[x as usize][y as usize].piece_type == PieceType::Pawn && self.board[x as usize][y as usize].moved == true {
-                                            if y == 3 {
-                                                self.can_en_passant = true;
-                                            }
-                                        }
-                                    }
+                            self.square_rects[x as usize][y as usize] = response.rect;
+                            let centre = response.rect.center();
+                            if king_in_check == Some([x, y]) {
+                                // Rings that get smaller as they stack up towards the middle
+                                let radius = response.rect.width() / 2.0;
+                                let rings = (1..=5).rev().map(|ring| egui::Shape::circle_filled(centre, radius * ring as f32 / 5.0, highlights.check));
+                                ui.painter().set(glow, egui::Shape::Vec(rings.collect()));
+                            }
+                            if self.piece_selected && self.valid_moves.contains(&[x, y]) {
+                                let chess_move = position::ChessMove::new(self.selected_piece, [x, y]);
+                                if position.is_capture(chess_move) {
+                                    ui.painter().circle_stroke(centre, side * 0.45, egui::Stroke::new(side * 0.08, highlights.moves));
                                 } else {
-                                    if self.valid_moves.contains(&[x, y]) {
-                                        self.history.insert(0, self.board);
-                                        self.board[x as usize][y as usize] = self.board[self.selected_piece[0] as usize][self.selected_piece[1] as usize];
-                                        self.board[x as usize][y as usize].moved = true;
-                                        self.board[self.selected_piece[0] as usize][self.selected_piece[1] as usize].team = Team::None;
-                                        self.board[self.selected_piece[0] as usize][self.selected_piece[1] as usize].piece_type = PieceType::None;
-
-                                        self.piece_selected = false;
-
-                                        self.advantage = calculate_advantage(&self.board);
-
-                                        switch_teams(&mut self.current_team);
-                                        
-                                        for y2 in (0..=7).rev() {
-                                            for x2 in 0..=7 {
-                                                let current_piece = self.board[x2 as usize][y2 as usize];
-                                                if current_piece.piece_type == PieceType::King && current_piece.team == self.current_team {
-                                                    let check_list = final_move_list(current_piece, [x2, y2], &self.board, true);
-                                                    let check = is_in_check(self.current_team, &self.board);
-
-                                                    if check_list.is_empty() && check == true {
-                                                        println!("GAME OVER!!!");
-                                                        match self.current_team {
-                                                            Team::White => { println!("BLACK WINS!"); },
-                                                            Team::Black => { println!("WHITE WINS!"); },
-                                                            _ => { println!("ERROR!"); }
-                                                        }
-                                                        std::process::exit(0);
-                                                    }
-                                                }
-                                            }
-                                        }
-
-                                        if is_stalemate(self.current_team, &self.board) == true {
-                                            println!("GAME OVER!! STALEMATE!!");
-                                            std::process::exit(1);
-                                        }
-
-                                    } else {
-                                        castle(self.can_castle,
-                                               x as i32,
-                                               y as i32,
-                                               &mut self.board,
-                                               self.selected_piece[0] as i32,
-                                               self.selected_piece[1] as i32,
-                                               &mut self.current_team,
-                                               &mut self.history);
-                                        
-                                        en_passant(x as i32,
-                                                   y as i32,
-                                                   &mut self.board,
-                                                   self.selected_piece[0] as i32,
-                                                   self.selected_piece[1] as i32,
-                                                   &mut self.current_team,
-                                                   &mut self.history);
-                                        
-                                        self.piece_selected = false;
+                                    ui.painter().circle_filled(centre, side * 0.14, highlights.moves);
+                                }
+                            }
+
+                            let can_move = !self.opponent.computers_turn(self.current_team) && !self.clocks.flagged();
+                            if response.drag_started_by(egui::PointerButton::Primary) && can_move && piece.team == self.current_team {
+                                self.select(&position, [x, y]);
+                                self.dragging = Some([x, y]);
+                            }
+                            if response.drag_stopped_by(egui::PointerButton::Primary) && self.dragging == Some([x, y]) {
+                                dropped = ui.ctx().input(|input| input.pointer.latest_pos());
+                            }
+                            // Right-drags draw arrows and right-clicks circle squares
+                            if response.drag_started_by(egui::PointerButton::Secondary) {
+                                self.annotating = Some([x, y]);
+                            }
+                            if response.drag_stopped_by(egui::PointerButton::Secondary) && self.annotating == Some([x, y]) {
+                                annotated = self.annotating.take().and_then(|from| self.drawn_annotation(ui.ctx(), from));
+                            }
+                            if response.secondary_clicked() {
+                                annotated = self.drawn_annotation(ui.ctx(), [x, y]);
+                            }
+                            if response.clicked() && can_move {
+                                if !self.piece_selected {
+                                    if piece.team == self.current_team {
+                                        self.select(&position, [x, y]);
                                     }
+                                } else if !self.try_move(self.selected_piece, [x, y]) {
+                                    self.piece_selected = false;
                                 }
                             }
                         });
@@ -678,56 +942,30 @@ impl eframe::App for Chess {
                     ui.end_row();
                 }
             });
+            });
+            ui.add_space(margin);
+            self.show_coordinates(ui, &ranks, &files);
 
-            if self.advantage.0 > self.advantage.1 {
-                ui.label(egui::RichText::new(format!("+{}", self.advantage.0 - self.advantage.1)).color(egui::Color32::from_rgb(255, 255, 255)).size(25.0));
+            if let Some(at) = dropped {
+                self.drop_piece(at);
+            }
+            if let Some(drawn) = annotated {
+                self.game.toggle_annotation(self.game.current(), drawn);
+            }
+            self.show_annotations(ui);
+            if let Some(from) = self.dragging {
+                self.show_dragged_piece(ctx, from);
+            }
+            if let Some(animation) = &self.animation && !animation.show(ctx, &self.square_rects, self.settings.pieces()) {
+                self.animation = None;
             }
 
-            for (i, board) in self.history.clone().into_iter().enumerate() {
-                ui.label("\n"); // Seriously egui, seriously?
-                egui::Grid::new(format!("grid {}", i))
-                    .min_col_width(32.0)
-                    .min_row_height(32.0)
-                    .show(ui, |ui| {
-                    for y in (0..=7).rev() {
-                        for x in 0..=7 {
-                            let mut bg_colour = if (x+y) % 2 == 0 {
-                                Color32::from_rgb(117, 149, 85)
-                            } else {
-                                Color32::from_rgb(237, 237, 209)
-                            };
-
-                            let frame = egui::Frame::new()
-                                .fill(bg_colour)
-                                .inner_margin(egui::Margin::ZERO);
-                        
-                            frame.show(ui, |ui| {
-                                let piece = board[x as usize][y as usize];
-                                let image = match (piece.team, piece.piece_type) {
-                                    (Team::Black, PieceType::Rook) => egui::include_image!("../assets/black_rook.png"),
-                                    (Team::Black, PieceType::Knight) => egui::include_image!("../assets/black_knight.png"),
-                                    (Team::Black, PieceType::Bishop) => egui::include_image!("../assets/black_bishop.png"),
-                                    (Team::Black, PieceType::Queen) => egui::include_image!("../assets/black_queen.png"),
-                                    (Team::Black, PieceType::King) => egui::include_image!("../assets/black_king.png"),
-                                    (Team::Black, PieceType::Pawn) => egui::include_image!("../assets/black_pawn.png"),
-                                    (Team::White, PieceType::Rook) => egui::include_image!("../assets/white_rook.png"),
-                                    (Team::White, PieceType::Knight) => egui::include_image!("../assets/white_knight.png"),
-                                    (Team::White, PieceType::Bishop) => egui::include_image!("../assets/white_bishop.png"),
-                                    (Team::White, PieceType::Queen) => egui::include_image!("../assets/white_queen.png"),
-                                    (Team::White, PieceType::King) => egui::include_image!("../assets/white_king.png"),
-                                    (Team::White, PieceType::Pawn) => egui::include_image!("../assets/white_pawn.png"),
-                                    _ => egui::include_image!("../assets/blank.png"),
-                                };
-
-                                let response = ui.add(
-                                    egui::ImageButton::new(image)
-                                        .frame(false)
-                                );
-                            });
-                        }
-                        ui.end_row();
-                    }
-                });
+            self.clocks.show_face(ui, bottom);
+            self.show_advantage(ui, bottom);
+
+            if !hanging.is_empty() {
+                let squares: Vec<String> = hanging.iter().map(|pos| square_name(*pos)).collect();
+                ui.label(egui::RichText::new(format!("Hanging: {}", squares.join(", "))).color(egui::Color32::from_rgb(255, 165, 0)).size(20.0));
             }
         });
         });
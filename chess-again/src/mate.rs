@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use crate::PieceType;
+use crate::position::{ChessMove, Position};
+use crate::san::{format_line, move_to_san};
+use crate::zobrist;
+
+pub enum MateResult {
+    Mate(Vec<ChessMove>), // The mating line against the longest defence
+    NoMate,
+    Stopped,
+}
+
+// Proves mates by trying every attacking move, checks first, against every defence. Only
+// checks are tried on the last move, since nothing else can mate
+struct Solver<'a> {
+    stop: &'a AtomicBool,
+    nodes: u64,
+    // The move that mates within the given number of moves, or None when nothing does
+    known: HashMap<(u64, u32), Option<ChessMove>>,
+}
+
+fn is_checkmate(position: &Position) -> bool {
+    position.in_check() && position.legal_moves().is_empty()
+}
+
+impl Solver<'_> {
+    fn stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    // Checks, then captures, then everything else
+    fn attacking_moves(&mut self, position: &Position, moves_left: u32) -> Vec<(ChessMove, Position)> {
+        let mut moves: Vec<(u8, ChessMove, Position)> = vec![];
+        for chess_move in position.legal_moves() {
+            let next = position.make_move(chess_move);
+            self.nodes += 1;
+            let order = match (next.in_check(), position.is_capture(chess_move)) {
+                (true, _) => 0,
+                (false, _) if moves_left == 1 => continue,
+                (false, true) => 1,
+                (false, false) => 2,
+            };
+            moves.push((order, chess_move, next));
+        }
+        moves.sort_by_key(|(order, _, _)| *order);
+        moves.into_iter().map(|(_, chess_move, next)| (chess_move, next)).collect()
+    }
+
+    fn attack(&mut self, position: &Position, moves_left: u32) -> Option<ChessMove> {
+        let key = (zobrist::hash(position), moves_left);
+        if let Some(known) = self.known.get(&key) {
+            return *known;
+        }
+        let mut found = None;
+        for (chess_move, next) in self.attacking_moves(position, moves_left) {
+            if self.stopped() {
+                return None; // Not remembered, since it wasn't worked out
+            }
+            if is_checkmate(&next) || (moves_left > 1 && self.defence_fails(&next, moves_left - 1)) {
+                found = Some(chess_move);
+                break;
+            }
+        }
+        // A stop during the last move's defences ends the loop too, with nothing proved
+        if found.is_none() && self.stopped() {
+            return None;
+        }
+        self.known.insert(key, found);
+        found
+    }
+
+    // Whether every defence still gets mated within `moves_left` moves
+    fn defence_fails(&mut self, position: &Position, moves_left: u32) -> bool {
+        let mut defences = position.legal_moves();
+        if defences.is_empty() {
+            return position.in_check();
+        }
+        // Captures and king moves are the likeliest to escape, so they're tried first
+        defences.sort_by_key(|chess_move| {
+            let king = position.piece_at(chess_move.from).piece_type == PieceType::King;
+            !(position.is_capture(*chess_move) || king)
+        });
+        defences.into_iter().all(|defence| {
+            self.nodes += 1;
+            self.attack(&position.make_move(defence), moves_left).is_some()
+        })
+    }
+
+    // The quickest mate, if there is one within `max_moves`
+    fn shortest_mate(&mut self, position: &Position, max_moves: u32) -> Option<(u32, ChessMove)> {
+        (1..=max_moves).find_map(|moves| self.attack(position, moves).map(|chess_move| (moves, chess_move)))
+    }
+
+    // Plays out a proven mate in `moves`, with the defence holding out as long as it can
+    fn mating_line(&mut self, position: &Position, moves: u32, first: ChessMove) -> Vec<ChessMove> {
+        let mut line = vec![first];
+        let mut position = position.make_move(first);
+        let mut moves_left = moves - 1;
+        while moves_left > 0 && !self.stopped() {
+            let mut longest: Option<(u32, ChessMove, ChessMove)> = None;
+            for defence in position.legal_moves() {
+                let after = position.make_move(defence);
+                if let Some((moves, reply)) = self.shortest_mate(&after, moves_left)
+                    && longest.is_none_or(|(most, _, _)| moves > most)
+                {
+                    longest = Some((moves, defence, reply));
+                }
+            }
+            let Some((moves, defence, reply)) = longest else {
+                break;
+            };
+            line.push(defence);
+            line.push(reply);
+            position = position.make_move(defence).make_move(reply);
+            moves_left = moves - 1;
+        }
+        line
+    }
+}
+
+// Looks for a mate in 1, then 2 and so on up to `max_moves`, telling `report` as each one
+// is ruled out
+pub fn solve(position: &Position, max_moves: u32, stop: &AtomicBool, mut report: impl FnMut(u32, u64)) -> (MateResult, u64) {
+    let mut solver = Solver { stop, nodes: 0, known: HashMap::new() };
+    for moves in 1..=max_moves {
+        if let Some(first) = solver.attack(position, moves) {
+            let line = solver.mating_line(position, moves, first);
+            return (MateResult::Mate(line), solver.nodes);
+        }
+        if solver.stopped() {
+            return (MateResult::Stopped, solver.nodes);
+        }
+        report(moves, solver.nodes);
+    }
+    (MateResult::NoMate, solver.nodes)
+}
+
+// Every first move that mates in `moves` or fewer, which a composed problem wants to be just one
+fn key_moves(position: &Position, moves: u32, stop: &AtomicBool) -> Vec<ChessMove> {
+    let mut solver = Solver { stop, nodes: 0, known: HashMap::new() };
+    position
+        .legal_moves()
+        .into_iter()
+        .filter(|chess_move| {
+            let next = position.make_move(*chess_move);
+            is_checkmate(&next) || (moves > 1 && solver.defence_fails(&next, moves - 1))
+        })
+        .collect()
+}
+
+// mate <moves> <fen>
+pub fn run(args: &[String]) {
+    let Some(moves) = args.first().and_then(|arg| arg.parse::<u32>().ok()).filter(|moves| *moves > 0) else {
+        println!("usage: mate <moves> <fen>");
+        return;
+    };
+    let position = match Position::from_fen(&args[1..].join(" ")) {
+        Ok(position) => position,
+        Err(error) => {
+            println!("{}", error);
+            return;
+        }
+    };
+
+    let stop = AtomicBool::new(false);
+    let started = Instant::now();
+    let (result, nodes) = solve(&position, moves, &stop, |moves, nodes| {
+        println!("no mate in {} ({} nodes, {:.1}s)", moves, nodes, started.elapsed().as_secs_f64());
+    });
+    let MateResult::Mate(line) = result else {
+        println!("No forced mate in {}", moves);
+        return;
+    };
+
+    let found = line.len().div_ceil(2) as u32;
+    println!("Mate in {}: {}", found, format_line(&position, &line));
+    println!("{} nodes in {:.1}s", nodes, started.elapsed().as_secs_f64());
+    let keys = key_moves(&position, found, &stop);
+    if keys.len() > 1 {
+        let names: Vec<String> = keys.iter().map(|chess_move| move_to_san(&position, *chess_move)).collect();
+        println!("Not unique, these all mate in {}: {}", found, names.join(", "));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solve_fen(fen: &str, moves: u32) -> (Position, MateResult) {
+        let position = Position::from_fen(fen).unwrap();
+        let (result, _) = solve(&position, moves, &AtomicBool::new(false), |_, _| {});
+        (position, result)
+    }
+
+    #[test]
+    fn test_finds_short_mates() {
+        // Back rank mate
+        let (position, result) = solve_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 3);
+        let MateResult::Mate(line) = result else { panic!("no mate found") };
+        assert_eq!(format_line(&position, &line), "1. Ra8#");
+
+        // Queen sacrifice then smothered mate: 1. Qg8+ Rxg8 2. Nf7#
+        let (position, result) = solve_fen("4r2k/6pp/7N/3Q4/8/8/8/6K1 w - - 0 1", 2);
+        let MateResult::Mate(line) = result else { panic!("no mate found") };
+        assert_eq!(format_line(&position, &line), "1. Qg8+ Rxg8 2. Nf7#");
+    }
+
+    #[test]
+    fn test_mate_in_two_needs_the_defence_covered() {
+        // Ra8+ only drives the king to h7, so the king has to come up first
+        let (position, result) = solve_fen("7k/8/5K2/8/8/8/8/R7 w - - 0 1", 2);
+        let MateResult::Mate(line) = result else { panic!("no mate found") };
+        assert_eq!(line.len(), 3);
+        let end = line.iter().fold(position, |position, chess_move| position.make_move(*chess_move));
+        assert!(is_checkmate(&end));
+    }
+
+    #[test]
+    fn test_reports_when_there_is_no_mate() {
+        let (_, result) = solve_fen("4k3/8/8/8/8/8/8/4K2R w K - 0 1", 2);
+        assert!(matches!(result, MateResult::NoMate));
+
+        let stop = AtomicBool::new(true);
+        let position = Position::from_fen("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3").unwrap();
+        assert!(matches!(solve(&position, 3, &stop, |_, _| {}).0, MateResult::Stopped));
+
+        // Nothing gets remembered as unmated once the stop has come
+        let mut solver = Solver { stop: &stop, nodes: 0, known: HashMap::new() };
+        assert_eq!(solver.attack(&position, 1), None);
+        assert!(solver.known.is_empty());
+    }
+}
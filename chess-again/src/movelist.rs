@@ -0,0 +1,197 @@
+use eframe::egui;
+
+use crate::Team;
+use crate::game::{Game, NodeId};
+use crate::pgn::parse_pgn;
+
+const MOVE_WIDTH: f32 = 72.0;
+const NUMBER_WIDTH: f32 = 32.0;
+
+enum Action {
+    GoTo(NodeId),
+    Promote(NodeId),
+    Delete(NodeId),
+}
+
+// The main line in numbered White/Black pairs, with variations and comments in between,
+// plus the comment on the current move and loading and saving PGN
+pub struct MoveList {
+    path: String,
+    message: Option<Result<String, String>>,
+}
+
+impl Default for MoveList {
+    fn default() -> Self {
+        Self { path: "game.pgn".to_string(), message: None }
+    }
+}
+
+fn show_comment(ui: &mut egui::Ui, comment: &str) {
+    if !comment.is_empty() {
+        ui.label(egui::RichText::new(comment).italics().weak());
+    }
+}
+
+// "12. e4", "12... e5" or just "e5"
+fn move_text(game: &Game, node: NodeId, numbered: bool) -> String {
+    let before = game.position_before(node);
+    if before.current_team == Team::White {
+        format!("{}. {}", before.fullmove_number, game.san(node))
+    } else if numbered {
+        format!("{}... {}", before.fullmove_number, game.san(node))
+    } else {
+        game.san(node).to_string()
+    }
+}
+
+fn show_move(ui: &mut egui::Ui, game: &Game, node: NodeId, text: String, width: Option<f32>, action: &mut Option<Action>) {
+    let label = egui::SelectableLabel::new(game.current() == node, text);
+    let response = match width {
+        Some(width) => ui.add_sized([width, ui.spacing().interact_size.y], label),
+        None => ui.add(label),
+    };
+    if response.clicked() {
+        *action = Some(Action::GoTo(node));
+    }
+    response.context_menu(|ui| {
+        if ui.button("Promote variation").clicked() {
+            *action = Some(Action::Promote(node));
+            ui.close_menu();
+        }
+        if ui.button("Delete from here").clicked() {
+            *action = Some(Action::Delete(node));
+            ui.close_menu();
+        }
+    });
+}
+
+// A variation written out in one run, with its own variations in brackets
+fn show_variation(ui: &mut egui::Ui, game: &Game, first: NodeId, action: &mut Option<Action>) {
+    ui.label("(");
+    show_move(ui, game, first, move_text(game, first, true), None, action);
+    show_comment(ui, game.comment(first));
+    let mut numbered = !game.comment(first).is_empty();
+    let mut node = first;
+    while let Some((&main, variations)) = game.children(node).split_first() {
+        show_move(ui, game, main, move_text(game, main, numbered), None, action);
+        show_comment(ui, game.comment(main));
+        numbered = !game.comment(main).is_empty() || !variations.is_empty();
+        for &variation in variations {
+            show_variation(ui, game, variation, action);
+        }
+        node = main;
+    }
+    ui.label(")");
+}
+
+fn show_row(ui: &mut egui::Ui, game: &Game, number: u32, white: Option<NodeId>, black: Option<NodeId>, action: &mut Option<Action>) {
+    ui.horizontal(|ui| {
+        ui.add_sized([NUMBER_WIDTH, ui.spacing().interact_size.y], egui::Label::new(format!("{}.", number)));
+        match white {
+            Some(node) => show_move(ui, game, node, game.san(node).to_string(), Some(MOVE_WIDTH), action),
+            None => {
+                ui.add_sized([MOVE_WIDTH, ui.spacing().interact_size.y], egui::Label::new("..."));
+            }
+        }
+        if let Some(node) = black {
+            show_move(ui, game, node, game.san(node).to_string(), Some(MOVE_WIDTH), action);
+        }
+    });
+}
+
+fn show_main_line(ui: &mut egui::Ui, game: &Game, action: &mut Option<Action>) {
+    if ui.selectable_label(game.current() == game.root(), "Start").clicked() {
+        *action = Some(Action::GoTo(game.root()));
+    }
+    show_comment(ui, game.comment(game.root()));
+
+    let mut white: Option<NodeId> = None; // Waiting for Black's reply to fill its row
+    let mut node = game.root();
+    while let Some((&main, variations)) = game.children(node).split_first() {
+        let before = game.position_before(main);
+        let interrupted = !variations.is_empty() || !game.comment(main).is_empty();
+        if before.current_team == Team::White {
+            white = Some(main);
+            if interrupted {
+                show_row(ui, game, before.fullmove_number, white.take(), None, action);
+            }
+        } else {
+            show_row(ui, game, before.fullmove_number, white.take(), Some(main), action);
+        }
+
+        if interrupted {
+            ui.indent(main, |ui| {
+                show_comment(ui, game.comment(main));
+                for &variation in variations {
+                    ui.horizontal_wrapped(|ui| show_variation(ui, game, variation, action));
+                }
+            });
+        }
+        node = main;
+    }
+    if let Some(main) = white {
+        show_row(ui, game, game.position_before(main).fullmove_number, Some(main), None, action);
+    }
+}
+
+impl MoveList {
+    fn show_files(&mut self, ui: &mut egui::Ui, game: &mut Game) -> bool {
+        let mut loaded = false;
+        ui.horizontal(|ui| {
+            ui.label("PGN:");
+            ui.add(egui::TextEdit::singleline(&mut self.path).desired_width(90.0));
+            if ui.button("Load").clicked() {
+                let path = self.path.trim();
+                let result = std::fs::read_to_string(path)
+                    .map_err(|error| format!("Can't read {}: {}", path, error))
+                    .and_then(|text| parse_pgn(&text).first().map(Game::from_pgn).unwrap_or(Err(format!("No games in {}", path))));
+                self.message = Some(result.map(|loaded_game| {
+                    *game = loaded_game;
+                    loaded = true;
+                    format!("Loaded {}", path)
+                }));
+            }
+            if ui.button("Save").clicked() {
+                let path = self.path.trim();
+                self.message = Some(match std::fs::write(path, game.to_pgn()) {
+                    Ok(()) => Ok(format!("Saved {}", path)),
+                    Err(error) => Err(format!("Can't write {}: {}", path, error)),
+                });
+            }
+        });
+        match &self.message {
+            Some(Ok(text)) => {
+                ui.label(text);
+            }
+            Some(Err(error)) => {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+            None => {}
+        }
+        loaded
+    }
+
+    // Returns whether the board needs to show a different position
+    pub fn show(&mut self, ui: &mut egui::Ui, game: &mut Game) -> bool {
+        ui.heading("Moves");
+        let loaded = self.show_files(ui, game);
+
+        let current = game.current();
+        let mut comment = game.comment(current).to_string();
+        ui.add(egui::TextEdit::multiline(&mut comment).hint_text("Comment on this move").desired_rows(2));
+        if comment != game.comment(current) {
+            game.set_comment(current, comment);
+        }
+        ui.separator();
+
+        let mut action = None;
+        egui::ScrollArea::vertical().auto_shrink([false, false]).show(ui, |ui| show_main_line(ui, game, &mut action));
+        match action {
+            Some(Action::GoTo(node)) => game.go_to(node),
+            Some(Action::Promote(node)) => game.promote(node),
+            Some(Action::Delete(node)) => game.delete(node),
+            None => {}
+        }
+        loaded || action.is_some()
+    }
+}
@@ -0,0 +1,374 @@
+// An efficiently updatable neural network evaluation with a (768 -> N) x 2 -> 1 layout.
+//
+// Each side has its own accumulator holding the hidden layer for the board seen from that
+// side. Inputs are one per (colour, piece type, square), with colour meaning "ours" or "theirs"
+// and Black's squares flipped vertically, so one set of weights serves both sides. A move only
+// touches two to four inputs, so the accumulators are updated from the parent's instead of
+// being added up from scratch. The output is the clipped ReLU of the side to move's
+// accumulator followed by the other one, dotted with the output weights.
+//
+// File format, all little endian:
+//   4 bytes   "CANN"
+//   u32       version, 1
+//   u32       hidden size N, a multiple of 16
+//   768 * N   i16 input weights, input by input (the N weights of input 0 come first)
+//   N         i16 hidden biases
+//   2 * N     i16 output weights, the side to move's half first
+//   i32       output bias
+// Input index = colour * 384 + piece * 64 + square, with colour 0 for the perspective's own
+// pieces, pieces ordered pawn, knight, bishop, rook, queen, king, and square 0 = a1, 63 = h8.
+// Hidden values are quantised by QA = 255 and output weights by QB = 64; the output bias is
+// already at QA * QB. The score is the output times 400 / (QA * QB), in centipawns.
+
+use std::io::Read;
+
+use crate::position::Position;
+use crate::{PieceType, Team};
+
+const MAGIC: &[u8; 4] = b"CANN";
+const VERSION: u32 = 1;
+const INPUTS: usize = 768;
+const QA: i32 = 255;
+const QB: i32 = 64;
+const SCALE: i32 = 400;
+const MAX_HIDDEN: usize = 4096;
+
+pub struct Network {
+    hidden: usize,
+    input_weights: Vec<i16>,
+    hidden_biases: Vec<i16>,
+    output_weights: Vec<i16>,
+    output_bias: i32,
+    avx2: bool,
+}
+
+// The hidden layer from both sides, kept one per ply by the search
+#[derive(Clone)]
+pub struct Accumulator {
+    white: Vec<i16>,
+    black: Vec<i16>,
+}
+
+fn piece_index(piece_type: PieceType) -> Option<usize> {
+    match piece_type {
+        PieceType::Pawn => Some(0),
+        PieceType::Knight => Some(1),
+        PieceType::Bishop => Some(2),
+        PieceType::Rook => Some(3),
+        PieceType::Queen => Some(4),
+        PieceType::King => Some(5),
+        PieceType::None => None,
+    }
+}
+
+// The inputs for a piece on board[x][y], as seen by White and by Black
+fn inputs(piece_type: PieceType, team: Team, x: usize, y: usize) -> Option<(usize, usize)> {
+    let piece = piece_index(piece_type)?;
+    let square = y * 8 + x;
+    let (white_colour, black_colour) = match team {
+        Team::White => (0, 1),
+        Team::Black => (1, 0),
+        Team::None => return None,
+    };
+    Some((white_colour * 384 + piece * 64 + square, black_colour * 384 + piece * 64 + (square ^ 56)))
+}
+
+fn read_u32(reader: &mut impl Read) -> std::io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_i16s(reader: &mut impl Read, count: usize) -> std::io::Result<Vec<i16>> {
+    let mut bytes = vec![0; count * 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes.chunks_exact(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]])).collect())
+}
+
+// Written as plain loops so the compiler vectorises them; the AVX2 copies below are the same
+// code compiled with wider registers allowed
+#[inline(always)]
+fn add_input(accumulator: &mut [i16], weights: &[i16]) {
+    for (value, weight) in accumulator.iter_mut().zip(weights) {
+        *value = value.wrapping_add(*weight);
+    }
+}
+
+#[inline(always)]
+fn sub_input(accumulator: &mut [i16], weights: &[i16]) {
+    for (value, weight) in accumulator.iter_mut().zip(weights) {
+        *value = value.wrapping_sub(*weight);
+    }
+}
+
+#[inline(always)]
+fn clipped_dot(accumulator: &[i16], weights: &[i16]) -> i32 {
+    accumulator
+        .iter()
+        .zip(weights)
+        .map(|(value, weight)| (*value).clamp(0, QA as i16) as i32 * *weight as i32)
+        .sum()
+}
+
+#[inline(always)]
+fn apply_changes(accumulator: &mut [i16], weights: &[i16], hidden: usize, added: &[usize], removed: &[usize]) {
+    for &input in added {
+        add_input(accumulator, &weights[input * hidden..(input + 1) * hidden]);
+    }
+    for &input in removed {
+        sub_input(accumulator, &weights[input * hidden..(input + 1) * hidden]);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+fn apply_changes_avx2(accumulator: &mut [i16], weights: &[i16], hidden: usize, added: &[usize], removed: &[usize]) {
+    apply_changes(accumulator, weights, hidden, added, removed);
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+fn clipped_dot_avx2(accumulator: &[i16], weights: &[i16]) -> i32 {
+    clipped_dot(accumulator, weights)
+}
+
+impl Network {
+    fn new(hidden: usize, input_weights: Vec<i16>, hidden_biases: Vec<i16>, output_weights: Vec<i16>, output_bias: i32) -> Network {
+        #[cfg(target_arch = "x86_64")]
+        let avx2 = std::arch::is_x86_feature_detected!("avx2");
+        #[cfg(not(target_arch = "x86_64"))]
+        let avx2 = false;
+        Network { hidden, input_weights, hidden_biases, output_weights, output_bias, avx2 }
+    }
+
+    pub fn load(path: &str) -> Result<Network, String> {
+        let file = std::fs::File::open(path).map_err(|error| format!("Can't open {}: {}", path, error))?;
+        Network::read(&mut std::io::BufReader::new(file)).map_err(|error| format!("Can't read network {}: {}", path, error))
+    }
+
+    fn read(reader: &mut impl Read) -> std::io::Result<Network> {
+        let invalid = |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string());
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a network file"));
+        }
+        if read_u32(reader)? != VERSION {
+            return Err(invalid("unsupported version"));
+        }
+        let hidden = read_u32(reader)? as usize;
+        if hidden == 0 || hidden > MAX_HIDDEN || !hidden.is_multiple_of(16) {
+            return Err(invalid("hidden size must be a multiple of 16 up to 4096"));
+        }
+
+        let input_weights = read_i16s(reader, INPUTS * hidden)?;
+        let hidden_biases = read_i16s(reader, hidden)?;
+        let output_weights = read_i16s(reader, 2 * hidden)?;
+        let output_bias = read_u32(reader)? as i32;
+        if reader.read(&mut [0])? != 0 {
+            return Err(invalid("trailing data after the output bias"));
+        }
+        Ok(Network::new(hidden, input_weights, hidden_biases, output_weights, output_bias))
+    }
+
+    pub fn new_accumulator(&self) -> Accumulator {
+        Accumulator {
+            white: self.hidden_biases.clone(),
+            black: self.hidden_biases.clone(),
+        }
+    }
+
+    fn apply(&self, accumulator: &mut [i16], added: &[usize], removed: &[usize]) {
+        #[cfg(target_arch = "x86_64")]
+        if self.avx2 {
+            // SAFETY: only taken when the CPU has AVX2
+            unsafe { apply_changes_avx2(accumulator, &self.input_weights, self.hidden, added, removed) };
+            return;
+        }
+        apply_changes(accumulator, &self.input_weights, self.hidden, added, removed);
+    }
+
+    // Adds up every piece on the board
+    pub fn refresh(&self, accumulator: &mut Accumulator, position: &Position) {
+        let (mut white, mut black) = (vec![], vec![]);
+        for x in 0..8 {
+            for y in 0..8 {
+                let piece = position.board[x][y];
+                if let Some((white_input, black_input)) = inputs(piece.piece_type, piece.team, x, y) {
+                    white.push(white_input);
+                    black.push(black_input);
+                }
+            }
+        }
+        accumulator.white.copy_from_slice(&self.hidden_biases);
+        accumulator.black.copy_from_slice(&self.hidden_biases);
+        self.apply(&mut accumulator.white, &white, &[]);
+        self.apply(&mut accumulator.black, &black, &[]);
+    }
+
+    // Brings `child` up to date from the accumulator of the position the move was made in,
+    // looking only at the squares that changed
+    pub fn update(&self, parent: &Accumulator, child: &mut Accumulator, before: &Position, after: &Position) {
+        let (mut white_added, mut black_added) = ([0; 4], [0; 4]);
+        let (mut white_removed, mut black_removed) = ([0; 4], [0; 4]);
+        let (mut added, mut removed) = (0, 0);
+        for x in 0..8 {
+            for y in 0..8 {
+                let (old, new) = (before.board[x][y], after.board[x][y]);
+                if old.piece_type == new.piece_type && old.team == new.team {
+                    continue;
+                }
+                if let Some((white_input, black_input)) = inputs(old.piece_type, old.team, x, y) {
+                    white_removed[removed] = white_input;
+                    black_removed[removed] = black_input;
+                    removed += 1;
+                }
+                if let Some((white_input, black_input)) = inputs(new.piece_type, new.team, x, y) {
+                    white_added[added] = white_input;
+                    black_added[added] = black_input;
+                    added += 1;
+                }
+            }
+        }
+
+        child.white.copy_from_slice(&parent.white);
+        child.black.copy_from_slice(&parent.black);
+        self.apply(&mut child.white, &white_added[..added], &white_removed[..removed]);
+        self.apply(&mut child.black, &black_added[..added], &black_removed[..removed]);
+    }
+
+    // Centipawns from the point of view of `team`, the side to move
+    pub fn evaluate(&self, accumulator: &Accumulator, team: Team) -> i32 {
+        let (us, them) = if team == Team::Black {
+            (&accumulator.black, &accumulator.white)
+        } else {
+            (&accumulator.white, &accumulator.black)
+        };
+        let (our_weights, their_weights) = self.output_weights.split_at(self.hidden);
+
+        #[cfg(target_arch = "x86_64")]
+        let sum = if self.avx2 {
+            // SAFETY: only taken when the CPU has AVX2
+            unsafe { clipped_dot_avx2(us, our_weights) + clipped_dot_avx2(them, their_weights) }
+        } else {
+            clipped_dot(us, our_weights) + clipped_dot(them, their_weights)
+        };
+        #[cfg(not(target_arch = "x86_64"))]
+        let sum = clipped_dot(us, our_weights) + clipped_dot(them, their_weights);
+
+        ((sum as i64 + self.output_bias as i64) * SCALE as i64 / (QA * QB) as i64) as i32
+    }
+
+    // A full evaluation without an accumulator to hand
+    pub fn evaluate_position(&self, position: &Position) -> i32 {
+        let mut accumulator = self.new_accumulator();
+        self.refresh(&mut accumulator, position);
+        self.evaluate(&accumulator, position.current_team)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn random_network(hidden: usize) -> Network {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut values = |count: usize, range: i16| (0..count).map(|_| rng.random_range(-range..=range)).collect::<Vec<i16>>();
+        let input_weights = values(INPUTS * hidden, 40);
+        let hidden_biases = values(hidden, 60);
+        let output_weights = values(2 * hidden, 64);
+        Network::new(hidden, input_weights, hidden_biases, output_weights, 1234)
+    }
+
+    fn save(network: &Network, path: &str) -> Result<(), String> {
+        let mut bytes = Vec::with_capacity(16 + (INPUTS + 3) * network.hidden * 2);
+        bytes.extend(MAGIC);
+        bytes.extend(VERSION.to_le_bytes());
+        bytes.extend((network.hidden as u32).to_le_bytes());
+        for value in network.input_weights.iter().chain(&network.hidden_biases).chain(&network.output_weights) {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.extend(network.output_bias.to_le_bytes());
+        std::fs::write(path, bytes).map_err(|error| format!("Can't write {}: {}", path, error))
+    }
+
+    #[test]
+    fn test_updates_match_refresh() {
+        let network = random_network(32);
+        let mut rng = StdRng::seed_from_u64(3);
+        // Castling both ways, en passant and promotions all turn up from these
+        let fens = [
+            "r3k2r/pppq1ppp/2n2n2/3pp3/3PP3/2N2N2/PPPQ1PPP/R3K2R w KQkq - 0 1",
+            "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1",
+            "1n2k3/P7/8/8/8/8/7p/4K1N1 w - - 0 1",
+        ];
+        for fen in fens {
+            let mut position = Position::from_fen(fen).unwrap();
+            let mut accumulator = network.new_accumulator();
+            network.refresh(&mut accumulator, &position);
+            for _ in 0..40 {
+                let moves = position.legal_moves();
+                if moves.is_empty() {
+                    break;
+                }
+                for chess_move in &moves {
+                    let child = position.make_move(*chess_move);
+                    let mut updated = network.new_accumulator();
+                    network.update(&accumulator, &mut updated, &position, &child);
+                    let mut refreshed = network.new_accumulator();
+                    network.refresh(&mut refreshed, &child);
+                    assert!(updated.white == refreshed.white && updated.black == refreshed.black, "{} after {}", fen, chess_move.to_uci());
+                }
+                let chess_move = moves[rng.random_range(0..moves.len())];
+                let child = position.make_move(chess_move);
+                let mut updated = network.new_accumulator();
+                network.update(&accumulator, &mut updated, &position, &child);
+                (position, accumulator) = (child, updated);
+            }
+        }
+    }
+
+    #[test]
+    fn test_search_with_network() {
+        use crate::search::{SearchLimits, SearchOptions, search};
+        use crate::tt::TranspositionTable;
+        use std::sync::Arc;
+        use std::sync::atomic::AtomicBool;
+
+        // Whatever the network thinks, mate is mate
+        let options = SearchOptions { nnue: Some(Arc::new(random_network(32))), ..Default::default() };
+        let position = Position::from_fen("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1").unwrap();
+        let limits = SearchLimits { depth: Some(4), ..Default::default() };
+        let result = search(&position, &limits, &options, &TranspositionTable::new(16), Arc::new(AtomicBool::new(false)), |_| {});
+        assert_eq!(result.best_move.unwrap().to_uci(), "a1a8");
+    }
+
+    #[test]
+    fn test_colour_symmetry() {
+        // The same position with the colours swapped scores the same for the side to move
+        let network = random_network(32);
+        let position = Position::from_fen("r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4").unwrap();
+        let mirrored = Position::from_fen("rnbqk2r/pppp1ppp/5n2/2b1p3/4P3/2N2N2/PPPP1PPP/R1BQKB1R b KQkq - 4 4").unwrap();
+        assert_eq!(network.evaluate_position(&position), network.evaluate_position(&mirrored));
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let network = random_network(16);
+        let path = std::env::temp_dir().join("chess_again_test.nnue");
+        let path = path.to_str().unwrap();
+        save(&network, path).unwrap();
+        let loaded = Network::load(path).unwrap();
+        let position = Position::default();
+        assert_eq!(network.evaluate_position(&position), loaded.evaluate_position(&position));
+        assert_eq!(std::fs::metadata(path).unwrap().len(), 16 + (INPUTS as u64 + 3) * 16 * 2);
+
+        // Cut short, it is refused
+        let bytes = std::fs::read(path).unwrap();
+        std::fs::write(path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(Network::load(path).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,236 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use eframe::egui;
+
+use crate::Team;
+use crate::position::{ChessMove, Position};
+use crate::san::move_to_san;
+use crate::search::{SearchLimits, SearchOptions, search};
+use crate::skill::{self, MAX_SKILL};
+use crate::tt::TranspositionTable;
+use crate::zobrist;
+
+const OPPONENT_HASH_MB: usize = 16;
+const THINKING_TIME_MS: u64 = 1000;
+
+struct Reply {
+    key: u64,
+    chess_move: ChessMove,
+    expected: Option<ChessMove>, // What it thinks you'll answer with
+}
+
+pub enum OpponentAction {
+    NewGame,
+    Play(ChessMove),
+}
+
+// Plays one side on a background thread, at a strength picked in the new game dialog
+pub struct Opponent {
+    dialog_open: bool,
+    playing: bool,
+    computer: Team,
+    skill: u32,
+    thinking: Option<u64>, // Zobrist key of the position being searched
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    reply: Arc<Mutex<Option<Reply>>>,
+    ponder_enabled: bool,
+    ponder: Arc<AtomicBool>, // Set while searching the reply we expect on your time
+    expected: Option<ChessMove>,
+    tt: Arc<TranspositionTable>,
+    last_move: String,
+}
+
+impl Default for Opponent {
+    fn default() -> Self {
+        Self {
+            dialog_open: false,
+            playing: false,
+            computer: Team::Black,
+            skill: 5,
+            thinking: None,
+            stop: Arc::new(AtomicBool::new(false)),
+            thread: None,
+            reply: Arc::new(Mutex::new(None)),
+            ponder_enabled: true,
+            ponder: Arc::new(AtomicBool::new(false)),
+            expected: None,
+            tt: Arc::new(TranspositionTable::new(OPPONENT_HASH_MB)),
+            last_move: String::new(),
+        }
+    }
+}
+
+impl Drop for Opponent {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl Opponent {
+    pub fn playing(&self) -> bool {
+        self.playing
+    }
+
+    // The board ignores clicks while the computer is to move
+    pub fn computers_turn(&self, team: Team) -> bool {
+        self.playing && team == self.computer
+    }
+
+    fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.thread.take() {
+            handle.join().unwrap();
+        }
+        // A search cut short may have settled on any move, so nothing it found gets played
+        *self.reply.lock().unwrap() = None;
+        self.thinking = None;
+    }
+
+    fn start(&mut self, ctx: &egui::Context, position: &Position, ponder: bool) {
+        self.stop();
+        let key = zobrist::hash(position);
+        self.thinking = Some(key);
+
+        self.stop = Arc::new(AtomicBool::new(false));
+        let stop = self.stop.clone();
+        let reply = self.reply.clone();
+        let tt = self.tt.clone();
+        let ctx = ctx.clone();
+        let position = *position;
+        let skill = self.skill;
+        self.ponder = Arc::new(AtomicBool::new(ponder));
+        let mut limits = SearchLimits {
+            movetime: Some(THINKING_TIME_MS),
+            ponder: Some(self.ponder.clone()),
+            ..Default::default()
+        };
+        let mut options = SearchOptions::default();
+        skill::limit_search(skill, &mut limits, &mut options);
+        self.thread = Some(std::thread::spawn(move || {
+            let result = search(&position, &limits, &options, &tt, stop, |_| {});
+            let chosen = skill::choose_move(skill, &result.lines, &mut rand::rng()).or(result.best_move);
+            if let Some(chess_move) = chosen {
+                let line = result.lines.iter().find(|line| line.pv.first() == Some(&chess_move));
+                let expected = line.and_then(|line| line.pv.get(1)).copied();
+                *reply.lock().unwrap() = Some(Reply { key, chess_move, expected });
+                ctx.request_repaint();
+            }
+        }));
+    }
+
+    // Searches the position after the reply it expects, so a correct guess saves the time
+    fn think_on_your_time(&mut self, ctx: &egui::Context, position: &Position) {
+        let expected = self.expected.filter(|chess_move| self.ponder_enabled && position.legal_moves().contains(chess_move));
+        let Some(expected) = expected else {
+            if self.thread.is_some() {
+                self.stop(); // The position got changed under it
+            }
+            return;
+        };
+        let predicted = position.make_move(expected);
+        if self.thinking != Some(zobrist::hash(&predicted)) {
+            self.start(ctx, &predicted, true);
+        }
+    }
+
+    fn show_dialog(&mut self, ctx: &egui::Context) -> Option<OpponentAction> {
+        let mut action = None;
+        let mut open = self.dialog_open;
+        egui::Window::new("Play against the computer")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("You play");
+                    ui.radio_value(&mut self.computer, Team::Black, "White");
+                    ui.radio_value(&mut self.computer, Team::White, "Black");
+                });
+                ui.add(egui::Slider::new(&mut self.skill, 0..=MAX_SKILL).text("Difficulty"));
+                let strength = if self.skill == MAX_SKILL {
+                    "Full strength".to_string()
+                } else {
+                    format!("About {} Elo", skill::elo_from_skill(self.skill))
+                };
+                ui.label(strength);
+                if ui.button("Start game").clicked() {
+                    action = Some(OpponentAction::NewGame);
+                }
+            });
+        self.dialog_open = open;
+
+        if action.is_some() {
+            self.stop();
+            self.tt.clear();
+            self.playing = true;
+            self.dialog_open = false;
+            self.last_move.clear();
+            self.expected = None;
+            *self.reply.lock().unwrap() = None;
+        }
+        action
+    }
+
+    // While you're looking back through the game it waits rather than playing over the later moves
+    pub fn show(&mut self, ui: &mut egui::Ui, position: &Position, browsing: bool) -> Option<OpponentAction> {
+        ui.heading("Computer opponent");
+        ui.horizontal(|ui| {
+            if ui.button("New game...").clicked() {
+                self.dialog_open = true;
+            }
+            if self.playing && ui.button("Stop playing").clicked() {
+                self.stop();
+                self.playing = false;
+            }
+            ui.checkbox(&mut self.ponder_enabled, "Think on your time");
+        });
+        if let Some(action) = self.show_dialog(ui.ctx()) {
+            return Some(action);
+        }
+        if !self.playing {
+            return None;
+        }
+
+        if !self.last_move.is_empty() {
+            ui.label(&self.last_move);
+        }
+        if position.legal_moves().is_empty() {
+            ui.label("Game over");
+            return None;
+        }
+        if browsing {
+            if self.thread.is_some() {
+                self.stop();
+            }
+            ui.label("Go to the latest move, or play a new one, to carry on");
+            return None;
+        }
+        if position.current_team != self.computer {
+            self.think_on_your_time(ui.ctx(), position);
+            ui.label("Your move");
+            return None;
+        }
+
+        let key = zobrist::hash(position);
+        let reply = self.reply.lock().unwrap().take();
+        if let Some(reply) = reply
+            && reply.key == key
+        {
+            self.stop();
+            self.expected = reply.expected;
+            self.last_move = format!("Computer plays {}", move_to_san(position, reply.chess_move));
+            return Some(OpponentAction::Play(reply.chess_move));
+        }
+        if self.thinking == Some(key) {
+            // You played the move it was pondering on, so the search carries on with the clock running
+            self.ponder.store(false, Ordering::Relaxed);
+        } else {
+            self.start(ui.ctx(), position, false);
+        }
+        ui.label("Thinking...");
+        None
+    }
+}
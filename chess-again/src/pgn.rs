@@ -0,0 +1,223 @@
+// The movetext in order, with the moves of variations between their brackets
+#[derive(Clone, PartialEq, Debug)]
+pub enum MoveText {
+    Move(String),
+    Comment(String),
+    StartVariation,
+    EndVariation,
+}
+
+pub struct PgnGame {
+    pub headers: Vec<(String, String)>,
+    pub moves: Vec<String>, // Main line in SAN, as written
+    pub movetext: Vec<MoveText>,
+    pub result: String,
+}
+
+impl PgnGame {
+    fn new() -> PgnGame {
+        PgnGame { headers: vec![], moves: vec![], movetext: vec![], result: "*".to_string() }
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+}
+
+fn is_result(token: &str) -> bool {
+    matches!(token, "1-0" | "0-1" | "1/2-1/2" | "*")
+}
+
+// Reads every game in a PGN file. `moves` gets the main line, and `movetext` everything
+// but the annotation glyphs.
+pub fn parse_pgn(text: &str) -> Vec<PgnGame> {
+    let mut games: Vec<PgnGame> = vec![];
+    let mut game = PgnGame::new();
+    let mut in_game = false;
+    let mut variation_depth = 0;
+    let mut in_comment = false;
+    let mut comment = String::new();
+
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if !in_comment && variation_depth == 0 && trimmed.starts_with('[') && trimmed.ends_with(']') {
+            if in_game {
+                games.push(std::mem::replace(&mut game, PgnGame::new()));
+                in_game = false;
+            }
+            let inner = &trimmed[1..trimmed.len() - 1];
+            if let Some((name, value)) = inner.split_once(' ') {
+                game.headers.push((name.to_string(), header_value(value.trim())));
+            }
+            continue;
+        }
+        if trimmed.starts_with('%') {
+            continue; // Escaped line
+        }
+
+        let mut token = String::new();
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            if in_comment {
+                if c == '}' {
+                    in_comment = false;
+                    push_comment(&mut game, &comment);
+                    comment.clear();
+                } else {
+                    comment.push(c);
+                }
+                continue;
+            }
+            let separator = c.is_whitespace() || matches!(c, '{' | '}' | '(' | ')' | ';');
+            if separator && !token.is_empty() {
+                in_game |= push_token(&mut game, &token, variation_depth);
+                token.clear();
+            }
+            match c {
+                '{' => in_comment = true,
+                ';' => {
+                    // Rest of the line is a comment
+                    push_comment(&mut game, &chars.by_ref().collect::<String>());
+                    break;
+                }
+                '(' => {
+                    variation_depth += 1;
+                    game.movetext.push(MoveText::StartVariation);
+                }
+                ')' if variation_depth > 0 => {
+                    variation_depth -= 1;
+                    game.movetext.push(MoveText::EndVariation);
+                }
+                _ if !separator => token.push(c),
+                _ => {}
+            }
+            if chars.peek().is_none() && !token.is_empty() {
+                in_game |= push_token(&mut game, &token, variation_depth);
+                token.clear();
+            }
+        }
+        if in_comment {
+            comment.push(' ');
+        }
+    }
+
+    if in_game || !game.headers.is_empty() {
+        games.push(game);
+    }
+    games
+}
+
+fn push_comment(game: &mut PgnGame, text: &str) {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if !text.is_empty() {
+        game.movetext.push(MoveText::Comment(text));
+    }
+}
+
+// Returns whether the token belonged to the movetext
+fn push_token(game: &mut PgnGame, token: &str, variation_depth: usize) -> bool {
+    if is_result(token) {
+        if variation_depth == 0 {
+            game.result = token.to_string();
+        }
+        return true;
+    }
+    if token.starts_with('$') {
+        return true; // Numeric annotation glyph
+    }
+
+    // Move numbers can be glued to the move, as in "12.Nf3" or "12...Nf6"
+    let token = match token.rfind('.') {
+        Some(index) => &token[index + 1..],
+        None => token,
+    };
+    if !token.is_empty() {
+        if variation_depth == 0 {
+            game.moves.push(token.to_string());
+        }
+        game.movetext.push(MoveText::Move(token.to_string()));
+    }
+    true
+}
+
+// The text between the quotes, where a backslash escapes the next quote or backslash
+fn header_value(quoted: &str) -> String {
+    let mut value = String::new();
+    let mut chars = quoted.strip_prefix('"').unwrap_or(quoted).chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => value.extend(chars.next()),
+            '"' => break,
+            _ => value.push(c),
+        }
+    }
+    value
+}
+
+// A tag pair line, e.g. [White "Morphy, Paul"]
+pub fn format_header(name: &str, value: &str) -> String {
+    format!("[{} \"{}\"]\n", name, value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// Joins movetext tokens into lines shorter than 80 characters
+pub fn wrap_movetext(tokens: &[String]) -> String {
+    let mut text = String::new();
+    let mut line = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + token.len() >= 80 {
+            text.push_str(&line);
+            text.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(token);
+    }
+    text.push_str(&line);
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pgn() {
+        let text = r#"[Event "Casual"]
+[White "A"]
+[Black "B"]
+[Result "1-0"]
+
+1. e4 {best by test} e5 2.Nf3 (2. f4 exf4) Nc6 $1 3. Bb5 a6; the Ruy Lopez
+4. Ba4 Nf6 5. 0-0 1-0
+
+[Event "Second"]
+[Result "1/2-1/2"]
+
+1. d4 d5 1/2-1/2
+"#;
+        let games = parse_pgn(text);
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].header("White"), Some("A"));
+        assert_eq!(games[0].moves, ["e4", "e5", "Nf3", "Nc6", "Bb5", "a6", "Ba4", "Nf6", "0-0"]);
+        assert_eq!(games[0].result, "1-0");
+        assert_eq!(games[1].moves, ["d4", "d5"]);
+        assert_eq!(games[1].result, "1/2-1/2");
+
+        use MoveText::*;
+        let movetext = &games[0].movetext;
+        assert_eq!(movetext[..3], [Move("e4".to_string()), Comment("best by test".to_string()), Move("e5".to_string())]);
+        assert_eq!(movetext[4..8], [StartVariation, Move("f4".to_string()), Move("exf4".to_string()), EndVariation]);
+        assert!(movetext.contains(&Comment("the Ruy Lopez".to_string())));
+    }
+
+    #[test]
+    fn test_header_escapes() {
+        let name = r#"Engine "fast" C:\bin"#;
+        let line = format_header("White", name);
+        assert_eq!(line, "[White \"Engine \\\"fast\\\" C:\\\\bin\"]\n");
+        let games = parse_pgn(&format!("{}\n1. e4 *\n", line));
+        assert_eq!(games[0].header("White"), Some(name));
+    }
+}
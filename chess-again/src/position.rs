@@ -0,0 +1,429 @@
+use crate::see::attackers_to;
+use crate::{Piece, PieceType, Team, create_board, create_piece, final_move_list, is_in_check, switch_teams};
+
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ChessMove {
+    pub from: [isize; 2],
+    pub to: [isize; 2],
+    pub promotion: PieceType,
+}
+
+impl ChessMove {
+    pub fn new(from: [isize; 2], to: [isize; 2]) -> ChessMove {
+        ChessMove {
+            from,
+            to,
+            promotion: PieceType::None,
+        }
+    }
+
+    pub fn to_uci(self) -> String {
+        let promotion = match self.promotion {
+            PieceType::Queen => "q",
+            PieceType::Rook => "r",
+            PieceType::Bishop => "b",
+            PieceType::Knight => "n",
+            _ => "",
+        };
+        format!("{}{}{}", square_name(self.from), square_name(self.to), promotion)
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Position {
+    pub board: [[Piece; 8]; 8],
+    pub current_team: Team,
+    pub en_passant: Option<[isize; 2]>, // Square a pawn can capture onto, if any
+    pub halfmove_clock: u32,
+    pub fullmove_number: u32,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self {
+            board: create_board(),
+            current_team: Team::White,
+            en_passant: None,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+        }
+    }
+}
+
+impl Position {
+    // Castling rights come from the `moved` flags, the same way the GUI decides them
+    pub fn from_fen(fen: &str) -> Result<Position, String> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        if fields.len() < 2 {
+            return Err(format!("Not enough fields in FEN \"{}\"", fen));
+        }
+
+        let mut board = [[create_piece(PieceType::None, Team::None); 8]; 8];
+        let ranks: Vec<&str> = fields[0].split('/').collect();
+        if ranks.len() != 8 {
+            return Err(format!("Expected 8 ranks in FEN \"{}\"", fen));
+        }
+        for (i, rank) in ranks.iter().enumerate() {
+            let y = 7 - i;
+            let mut x = 0;
+            for c in rank.chars() {
+                if let Some(skip) = c.to_digit(10) {
+                    x += skip as usize;
+                    continue;
+                }
+                if x > 7 {
+                    return Err(format!("Rank {} is too long in FEN \"{}\"", y + 1, fen));
+                }
+                let team = if c.is_ascii_uppercase() { Team::White } else { Team::Black };
+                let piece_type = match c.to_ascii_lowercase() {
+                    'p' => PieceType::Pawn,
+                    'n' => PieceType::Knight,
+                    'b' => PieceType::Bishop,
+                    'r' => PieceType::Rook,
+                    'q' => PieceType::Queen,
+                    'k' => PieceType::King,
+                    _ => return Err(format!("Unknown piece '{}' in FEN \"{}\"", c, fen)),
+                };
+                let mut piece = create_piece(piece_type, team);
+                piece.moved = match (team, piece_type) {
+                    (Team::White, PieceType::Pawn) => y != 1,
+                    (Team::Black, PieceType::Pawn) => y != 6,
+                    _ => true,
+                };
+                board[x][y] = piece;
+                x += 1;
+            }
+        }
+
+        let current_team = match fields[1] {
+            "w" => Team::White,
+            "b" => Team::Black,
+            other => return Err(format!("Unknown side to move \"{}\"", other)),
+        };
+
+        let castling = fields.get(2).copied().unwrap_or("-");
+        for (c, king, rook) in [('K', [4, 0], [7, 0]), ('Q', [4, 0], [0, 0]), ('k', [4, 7], [7, 7]), ('q', [4, 7], [0, 7])] {
+            if castling.contains(c) {
+                let team = if c.is_ascii_uppercase() { Team::White } else { Team::Black };
+                let king_piece = &mut board[king[0]][king[1]];
+                if king_piece.piece_type == PieceType::King && king_piece.team == team {
+                    king_piece.moved = false;
+                }
+                let rook_piece = &mut board[rook[0]][rook[1]];
+                if rook_piece.piece_type == PieceType::Rook && rook_piece.team == team {
+                    rook_piece.moved = false;
+                }
+            }
+        }
+
+        let en_passant = match fields.get(3).copied().unwrap_or("-") {
+            "-" => None,
+            square => Some(parse_square(square).ok_or(format!("Bad en passant square \"{}\"", square))?),
+        };
+
+        Ok(Position {
+            board,
+            current_team,
+            en_passant,
+            halfmove_clock: fields.get(4).and_then(|s| s.parse().ok()).unwrap_or(0),
+            fullmove_number: fields.get(5).and_then(|s| s.parse().ok()).unwrap_or(1),
+        })
+    }
+
+    pub fn to_fen(self) -> String {
+        let mut fen = String::new();
+        for y in (0..=7).rev() {
+            let mut empty = 0;
+            for x in 0..=7 {
+                let piece = self.board[x][y];
+                if piece.piece_type == PieceType::None {
+                    empty += 1;
+                    continue;
+                }
+                if empty > 0 {
+                    fen.push_str(&empty.to_string());
+                    empty = 0;
+                }
+                let c = match piece.piece_type {
+                    PieceType::Pawn => 'p',
+                    PieceType::Knight => 'n',
+                    PieceType::Bishop => 'b',
+                    PieceType::Rook => 'r',
+                    PieceType::Queen => 'q',
+                    _ => 'k',
+                };
+                fen.push(if piece.team == Team::White { c.to_ascii_uppercase() } else { c });
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if y > 0 {
+                fen.push('/');
+            }
+        }
+
+        fen.push_str(if self.current_team == Team::White { " w " } else { " b " });
+
+        let rights = self.castling_rights();
+        let mut castling: String = ['K', 'Q', 'k', 'q']
+            .iter()
+            .zip(rights)
+            .filter(|(_, allowed)| *allowed)
+            .map(|(c, _)| *c)
+            .collect();
+        if castling.is_empty() {
+            castling.push('-');
+        }
+        fen.push_str(&castling);
+
+        match self.en_passant {
+            Some(square) => fen.push_str(&format!(" {}", square_name(square))),
+            None => fen.push_str(" -"),
+        }
+        fen.push_str(&format!(" {} {}", self.halfmove_clock, self.fullmove_number));
+        fen
+    }
+
+    pub fn piece_at(&self, pos: [isize; 2]) -> Piece {
+        self.board[pos[0] as usize][pos[1] as usize]
+    }
+
+    // White kingside, white queenside, black kingside, black queenside
+    pub fn castling_rights(&self) -> [bool; 4] {
+        let unmoved = |pos: [usize; 2], piece_type: PieceType, team: Team| {
+            let piece = self.board[pos[0]][pos[1]];
+            piece.piece_type == piece_type && piece.team == team && !piece.moved
+        };
+        let white_king = unmoved([4, 0], PieceType::King, Team::White);
+        let black_king = unmoved([4, 7], PieceType::King, Team::Black);
+        [
+            white_king && unmoved([7, 0], PieceType::Rook, Team::White),
+            white_king && unmoved([0, 0], PieceType::Rook, Team::White),
+            black_king && unmoved([7, 7], PieceType::Rook, Team::Black),
+            black_king && unmoved([0, 7], PieceType::Rook, Team::Black),
+        ]
+    }
+
+    // Neither side can mate: bare kings, or kings and a single knight or bishop
+    pub fn insufficient_material(&self) -> bool {
+        let mut minors = 0;
+        for piece in self.board.iter().flatten() {
+            match piece.piece_type {
+                PieceType::Knight | PieceType::Bishop => minors += 1,
+                PieceType::King | PieceType::None => {}
+                _ => return false,
+            }
+        }
+        minors <= 1
+    }
+
+    // Whether the side could still mate if the other one helped. A lone knight or bishop
+    // can, but only with the other king hemmed in by its own pieces
+    pub fn can_mate(&self, team: Team) -> bool {
+        let (mut minors, mut blockers) = (0, 0);
+        for piece in self.board.iter().flatten() {
+            match (piece.team == team, piece.piece_type) {
+                (_, PieceType::King | PieceType::None) => {}
+                (true, PieceType::Knight | PieceType::Bishop) => minors += 1,
+                (true, _) => return true,
+                (false, _) => blockers += 1,
+            }
+        }
+        minors >= 2 || (minors == 1 && blockers > 0)
+    }
+
+    pub fn king_square(&self, team: Team) -> Option<[isize; 2]> {
+        (0..8).flat_map(|x| (0..8).map(move |y| [x, y])).find(|square| {
+            let piece = self.piece_at(*square);
+            piece.piece_type == PieceType::King && piece.team == team
+        })
+    }
+
+    pub fn is_capture(&self, chess_move: ChessMove) -> bool {
+        self.piece_at(chess_move.to).team != Team::None || self.is_en_passant(chess_move)
+    }
+
+    pub fn is_en_passant(&self, chess_move: ChessMove) -> bool {
+        self.piece_at(chess_move.from).piece_type == PieceType::Pawn
+            && self.en_passant == Some(chess_move.to)
+            && self.piece_at(chess_move.to).team == Team::None
+    }
+
+    pub fn is_castling(&self, chess_move: ChessMove) -> bool {
+        self.piece_at(chess_move.from).piece_type == PieceType::King && (chess_move.to[0] - chess_move.from[0]).abs() == 2
+    }
+
+    pub fn in_check(&self) -> bool {
+        is_in_check(self.current_team, &self.board)
+    }
+
+    pub fn legal_moves(&self) -> Vec<ChessMove> {
+        let mut temp_vector: Vec<ChessMove> = vec![];
+        let team = self.current_team;
+
+        for y in 0..=7 {
+            for x in 0..=7 {
+                let piece = self.board[x as usize][y as usize];
+                if piece.team != team {
+                    continue;
+                }
+                for to in final_move_list(piece, [x, y], &self.board, true) {
+                    if piece.piece_type == PieceType::Pawn && (to[1] == 0 || to[1] == 7) {
+                        for promotion in [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight] {
+                            temp_vector.push(ChessMove { from: [x, y], to, promotion });
+                        }
+                    } else {
+                        temp_vector.push(ChessMove::new([x, y], to));
+                    }
+                }
+            }
+        }
+
+        if let Some(target) = self.en_passant {
+            let forward = if team == Team::White { 1 } else { -1 };
+            for side in [-1, 1] {
+                let from = [target[0] + side, target[1] - forward];
+                if !crate::check_in_board(from) {
+                    continue;
+                }
+                let piece = self.piece_at(from);
+                if piece.piece_type == PieceType::Pawn && piece.team == team {
+                    let chess_move = ChessMove::new(from, target);
+                    if !is_in_check(team, &self.make_move(chess_move).board) {
+                        temp_vector.push(chess_move);
+                    }
+                }
+            }
+        }
+
+        let rights = self.castling_rights();
+        let (home, kingside, queenside) = match team {
+            Team::White => (0, rights[0], rights[1]),
+            _ => (7, rights[2], rights[3]),
+        };
+        if (kingside || queenside) && !self.in_check() {
+            let enemy = if team == Team::White { Team::Black } else { Team::White };
+            let empty = |x: isize| self.board[x as usize][home as usize].piece_type == PieceType::None;
+            let safe = |x: isize| attackers_to(&self.board, [x, home], enemy).is_empty();
+            if kingside && empty(5) && empty(6) && safe(5) && safe(6) {
+                temp_vector.push(ChessMove::new([4, home], [6, home]));
+            }
+            if queenside && empty(1) && empty(2) && empty(3) && safe(3) && safe(2) {
+                temp_vector.push(ChessMove::new([4, home], [2, home]));
+            }
+        }
+
+        temp_vector
+    }
+
+    pub fn make_move(&self, chess_move: ChessMove) -> Position {
+        let mut position = *self;
+        let empty_piece = create_piece(PieceType::None, Team::None);
+        let [from_x, from_y] = [chess_move.from[0] as usize, chess_move.from[1] as usize];
+        let [to_x, to_y] = [chess_move.to[0] as usize, chess_move.to[1] as usize];
+        let mut piece = self.board[from_x][from_y];
+
+        if self.is_en_passant(chess_move) {
+            position.board[to_x][from_y] = empty_piece;
+        }
+        if self.is_castling(chess_move) {
+            let (rook_from, rook_to) = if to_x == 6 { (7, 5) } else { (0, 3) };
+            position.board[rook_to][to_y] = position.board[rook_from][to_y];
+            position.board[rook_to][to_y].moved = true;
+            position.board[rook_from][to_y] = empty_piece;
+        }
+
+        if piece.piece_type == PieceType::Pawn || self.is_capture(chess_move) {
+            position.halfmove_clock = 0;
+        } else {
+            position.halfmove_clock += 1;
+        }
+
+        position.en_passant = None;
+        if piece.piece_type == PieceType::Pawn && (to_y as isize - from_y as isize).abs() == 2 {
+            position.en_passant = Some([to_x as isize, (to_y + from_y) as isize / 2]);
+        }
+
+        piece.moved = true;
+        if chess_move.promotion != PieceType::None {
+            piece.piece_type = chess_move.promotion;
+        }
+        position.board[to_x][to_y] = piece;
+        position.board[from_x][from_y] = empty_piece;
+
+        if self.current_team == Team::Black {
+            position.fullmove_number += 1;
+        }
+        switch_teams(&mut position.current_team);
+        position
+    }
+
+    pub fn parse_uci_move(&self, text: &str) -> Option<ChessMove> {
+        self.legal_moves().into_iter().find(|chess_move| chess_move.to_uci() == text)
+    }
+}
+
+pub fn square_name(pos: [isize; 2]) -> String {
+    let file = (b'a' + pos[0] as u8) as char;
+    let rank = (b'1' + pos[1] as u8) as char;
+    format!("{}{}", file, rank)
+}
+
+pub fn parse_square(text: &str) -> Option<[isize; 2]> {
+    let bytes = text.as_bytes();
+    if bytes.len() != 2 || !(b'a'..=b'h').contains(&bytes[0]) || !(b'1'..=b'8').contains(&bytes[1]) {
+        return None;
+    }
+    Some([(bytes[0] - b'a') as isize, (bytes[1] - b'1') as isize])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn perft(position: &Position, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        position
+            .legal_moves()
+            .into_iter()
+            .map(|chess_move| perft(&position.make_move(chess_move), depth - 1))
+            .sum()
+    }
+
+    #[test]
+    fn test_fen_round_trip() {
+        let fens = [
+            START_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR b Kq d6 0 3",
+        ];
+        for fen in fens {
+            assert_eq!(Position::from_fen(fen).unwrap().to_fen(), fen);
+        }
+    }
+
+    #[test]
+    fn test_perft_start_position() {
+        let position = Position::default();
+        assert_eq!(perft(&position, 1), 20);
+        assert_eq!(perft(&position, 3), 8902);
+    }
+
+    #[test]
+    fn test_perft_tricky_positions() {
+        // Castling, en passant, promotions and pins all show up in these
+        let kiwipete = Position::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        assert_eq!(perft(&kiwipete, 2), 2039);
+
+        let endgame = Position::from_fen("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1").unwrap();
+        assert_eq!(perft(&endgame, 3), 2812);
+
+        let promotions = Position::from_fen("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1").unwrap();
+        assert_eq!(perft(&promotions, 2), 264);
+    }
+}
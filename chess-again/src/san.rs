@@ -0,0 +1,202 @@
+use crate::position::{ChessMove, Position, parse_square, square_name};
+use crate::{PieceType, Team};
+
+pub fn piece_letter(piece_type: PieceType) -> &'static str {
+    match piece_type {
+        PieceType::Knight => "N",
+        PieceType::Bishop => "B",
+        PieceType::Rook => "R",
+        PieceType::Queen => "Q",
+        PieceType::King => "K",
+        _ => "",
+    }
+}
+
+// Standard algebraic notation for a legal move, e.g. "Nbd7", "exd6", "e8=Q+", "O-O-O#"
+pub fn move_to_san(position: &Position, chess_move: ChessMove) -> String {
+    let piece = position.piece_at(chess_move.from);
+    let mut san = String::new();
+
+    if position.is_castling(chess_move) {
+        san.push_str(if chess_move.to[0] == 6 { "O-O" } else { "O-O-O" });
+    } else {
+        let capture = position.is_capture(chess_move);
+        if piece.piece_type == PieceType::Pawn {
+            if capture {
+                san.push((b'a' + chess_move.from[0] as u8) as char);
+            }
+        } else {
+            san.push_str(piece_letter(piece.piece_type));
+
+            // Only say where it came from if another piece of the same kind could go there too
+            let rivals: Vec<ChessMove> = position
+                .legal_moves()
+                .into_iter()
+                .filter(|other| {
+                    other.to == chess_move.to
+                        && other.from != chess_move.from
+                        && position.piece_at(other.from).piece_type == piece.piece_type
+                })
+                .collect();
+            if !rivals.is_empty() {
+                let square = square_name(chess_move.from);
+                if rivals.iter().all(|other| other.from[0] != chess_move.from[0]) {
+                    san.push_str(&square[..1]);
+                } else if rivals.iter().all(|other| other.from[1] != chess_move.from[1]) {
+                    san.push_str(&square[1..]);
+                } else {
+                    san.push_str(&square);
+                }
+            }
+        }
+        if capture {
+            san.push('x');
+        }
+        san.push_str(&square_name(chess_move.to));
+        if chess_move.promotion != PieceType::None {
+            san.push('=');
+            san.push_str(piece_letter(chess_move.promotion));
+        }
+    }
+
+    let after = position.make_move(chess_move);
+    if after.in_check() {
+        san.push(if after.legal_moves().is_empty() { '#' } else { '+' });
+    }
+    san
+}
+
+// Accepts the usual sloppiness found in PGN files: missing or extra disambiguation, "0-0"
+// for castling, "e8Q" without the '=', and check or annotation marks
+pub fn parse_san(position: &Position, text: &str) -> Option<ChessMove> {
+    let text = text.trim_end_matches(['+', '#', '!', '?']).replace('0', "O");
+    let legal_moves = position.legal_moves();
+
+    if text == "O-O" || text == "O-O-O" {
+        let file = if text == "O-O" { 6 } else { 2 };
+        return legal_moves
+            .into_iter()
+            .find(|chess_move| position.is_castling(*chess_move) && chess_move.to[0] == file);
+    }
+
+    let mut body: Vec<char> = text.chars().filter(|c| *c != 'x' && *c != '-' && *c != '=').collect();
+    let promotion = match body.last() {
+        Some('Q') => PieceType::Queen,
+        Some('R') => PieceType::Rook,
+        Some('B') if body.len() > 2 => PieceType::Bishop,
+        Some('N') => PieceType::Knight,
+        _ => PieceType::None,
+    };
+    if promotion != PieceType::None {
+        body.pop();
+    }
+
+    let piece_type = match body.first() {
+        Some('N') => PieceType::Knight,
+        Some('B') => PieceType::Bishop,
+        Some('R') => PieceType::Rook,
+        Some('Q') => PieceType::Queen,
+        Some('K') => PieceType::King,
+        _ => PieceType::Pawn,
+    };
+    if piece_type != PieceType::Pawn {
+        body.remove(0);
+    }
+    if body.len() < 2 {
+        return None;
+    }
+    let to = parse_square(&body[body.len() - 2..].iter().collect::<String>())?;
+    let hints = &body[..body.len() - 2];
+
+    let mut candidates = legal_moves.into_iter().filter(|chess_move| {
+        chess_move.to == to
+            && chess_move.promotion == promotion
+            && position.piece_at(chess_move.from).piece_type == piece_type
+            && hints.iter().all(|hint| match hint {
+                'a'..='h' => chess_move.from[0] == (*hint as u8 - b'a') as isize,
+                '1'..='8' => chess_move.from[1] == (*hint as u8 - b'1') as isize,
+                _ => false,
+            })
+    });
+    let chess_move = candidates.next()?;
+    if candidates.next().is_some() {
+        return None; // Ambiguous
+    }
+    Some(chess_move)
+}
+
+// A sequence of moves with move numbers, e.g. "12... Nf6 13. e5 Nd5"
+pub fn format_line(position: &Position, moves: &[ChessMove]) -> String {
+    let mut text = String::new();
+    let mut position = *position;
+    for (i, chess_move) in moves.iter().enumerate() {
+        if position.current_team == Team::White {
+            text.push_str(&format!("{}. ", position.fullmove_number));
+        } else if i == 0 {
+            text.push_str(&format!("{}... ", position.fullmove_number));
+        }
+        text.push_str(&move_to_san(&position, *chess_move));
+        text.push(' ');
+        position = position.make_move(*chess_move);
+    }
+    text.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn san(fen: &str, uci: &str) -> String {
+        let position = Position::from_fen(fen).unwrap();
+        move_to_san(&position, position.parse_uci_move(uci).unwrap())
+    }
+
+    #[test]
+    fn test_move_to_san() {
+        let start = crate::position::START_FEN;
+        assert_eq!(san(start, "e2e4"), "e4");
+        assert_eq!(san(start, "g1f3"), "Nf3");
+        assert_eq!(san("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1c1"), "O-O-O");
+        assert_eq!(san("rnbqkbnr/ppp1pppp/8/3pP3/8/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 3", "e5d6"), "exd6");
+        assert_eq!(san("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b7b8q"), "b8=Q+");
+        assert_eq!(san("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "a1a8"), "Ra8#");
+    }
+
+    #[test]
+    fn test_disambiguation() {
+        assert_eq!(san("4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1", "b1d2"), "Nbd2");
+        assert_eq!(san("4k3/8/8/R7/8/8/8/R3K3 w - - 0 1", "a1a3"), "R1a3");
+        assert_eq!(san("k7/8/8/8/8/2Q1Q3/8/2Q1K3 w - - 0 1", "c3d2"), "Qc3d2");
+    }
+
+    #[test]
+    fn test_parse_san() {
+        let parse = |fen: &str, text: &str| {
+            let position = Position::from_fen(fen).unwrap();
+            parse_san(&position, text).map(|chess_move| chess_move.to_uci())
+        };
+        let start = crate::position::START_FEN;
+        assert_eq!(parse(start, "e4").as_deref(), Some("e2e4"));
+        assert_eq!(parse(start, "Ng1f3").as_deref(), Some("g1f3"));
+        assert_eq!(parse(start, "e5"), None);
+        assert_eq!(parse("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", "0-0-0+").as_deref(), Some("e8c8"));
+        assert_eq!(parse("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1", "b8N").as_deref(), Some("b7b8n"));
+        assert_eq!(parse("4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1", "Nd2"), None);
+        assert_eq!(parse("4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1", "Nfd2").as_deref(), Some("f1d2"));
+    }
+
+    #[test]
+    fn test_format_line() {
+        let position = Position::default();
+        let moves: Vec<ChessMove> = ["e2e4", "e7e5", "g1f3"]
+            .iter()
+            .scan(position, |position, text| {
+                let chess_move = position.parse_uci_move(text).unwrap();
+                *position = position.make_move(chess_move);
+                Some(chess_move)
+            })
+            .collect();
+        assert_eq!(format_line(&position, &moves), "1. e4 e5 2. Nf3");
+        assert_eq!(format_line(&position.make_move(moves[0]), &moves[1..]), "1... e5 2. Nf3");
+    }
+}
@@ -0,0 +1,772 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::PieceType;
+use crate::endgame::{Dtm, EndgameTables};
+use crate::evaluate::evaluate;
+use crate::nnue::{Accumulator, Network};
+use crate::position::{ChessMove, Position};
+use crate::see::{piece_value, see};
+use crate::syzygy::{TB_WIN, Tablebases, result_score};
+use crate::timeman::TimeManager;
+use crate::tt::{Bound, TableEntry, TranspositionTable};
+use crate::zobrist;
+use shakmaty_syzygy::Wdl;
+
+pub const MATE: i32 = 30000;
+pub const INFINITY: i32 = 32000;
+const MAX_PLY: usize = 128;
+// Anything this close to MATE is a forced mate, however far into the search it was found
+pub const MATE_THRESHOLD: i32 = MATE - MAX_PLY as i32;
+
+// How many nodes go by between looks at the clock
+const TIME_CHECK_INTERVAL: u64 = 16;
+
+#[derive(Clone)]
+pub struct SearchLimits {
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+    pub movetime: Option<u64>, // Milliseconds
+    pub time_left: Option<u64>,
+    pub increment: u64,
+    pub moves_to_go: Option<u64>,
+    pub move_overhead: u64,
+    // Set while searching on the opponent's time; the clock limits only count once it's cleared
+    pub ponder: Option<Arc<AtomicBool>>,
+    pub infinite: bool, // Analysis that only ends when `stop` is set, so no shortcuts
+    pub history: Vec<u64>, // Zobrist keys of the positions played before this one, oldest first
+}
+
+impl Default for SearchLimits {
+    fn default() -> Self {
+        Self {
+            depth: None,
+            nodes: None,
+            movetime: None,
+            time_left: None,
+            increment: 0,
+            moves_to_go: None,
+            move_overhead: 30,
+            ponder: None,
+            infinite: false,
+            history: vec![],
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PvLine {
+    pub score: i32,
+    pub pv: Vec<ChessMove>,
+}
+
+// Sent after every completed depth, with the best line first
+#[derive(Clone)]
+pub struct SearchInfo {
+    pub depth: u32,
+    pub nodes: u64,
+    pub time_ms: u64,
+    pub lines: Vec<PvLine>,
+}
+
+pub struct SearchResult {
+    pub best_move: Option<ChessMove>,
+    pub score: i32,
+    pub depth: u32,
+    pub nodes: u64,
+    pub pv: Vec<ChessMove>,
+    pub lines: Vec<PvLine>,
+}
+
+#[derive(Clone)]
+pub struct SearchOptions {
+    pub threads: usize,
+    pub multi_pv: usize, // How many of the best root moves get their own score and line
+    pub tablebases: Option<Arc<Tablebases>>,
+    pub endgame_tables: Option<Arc<EndgameTables>>,
+    pub nnue: Option<Arc<Network>>, // Evaluates with the network instead of the hand-written terms
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self { threads: 1, multi_pv: 1, tablebases: None, endgame_tables: None, nnue: None }
+    }
+}
+
+struct Searcher<'a> {
+    stop: &'a AtomicBool,         // Ends this search, once a limit is hit or the main thread is done
+    outside_stop: &'a AtomicBool, // The caller's, which the search never sets itself
+    tt: &'a TranspositionTable,
+    shared_nodes: &'a AtomicU64, // Nodes searched by every thread, for reporting and node limits
+    time_manager: TimeManager,
+    node_limit: Option<u64>,
+    nodes: u64,
+    multi_pv: usize,
+    infinite: bool,
+    // Lets helper threads run a ply ahead of the main thread, so they fill the table with
+    // different work instead of repeating it
+    depth_offset: u32,
+    killers: [[Option<ChessMove>; 2]; MAX_PLY],
+    // The game's positions and then the ones on the way down to this node, to spot repetitions
+    path: Vec<u64>,
+    root_index: usize,
+    tablebases: Option<&'a Tablebases>,
+    endgame_tables: Option<&'a EndgameTables>,
+    nnue: Option<&'a Network>,
+    accumulators: Vec<Accumulator>, // One per ply, each updated from the one before
+}
+
+// Mate and tablebase scores are stored relative to the node rather than the root, so they
+// stay right when the same position turns up at a different ply
+const TT_ADJUSTED: i32 = TB_WIN - MAX_PLY as i32;
+
+fn score_to_tt(score: i32, ply: usize) -> i32 {
+    if score >= TT_ADJUSTED {
+        score + ply as i32
+    } else if score <= -TT_ADJUSTED {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_tt(score: i32, ply: usize) -> i32 {
+    if score >= TT_ADJUSTED {
+        score - ply as i32
+    } else if score <= -TT_ADJUSTED {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+impl<'a> Searcher<'a> {
+    fn new(stop: &'a AtomicBool, outside_stop: &'a AtomicBool, tt: &'a TranspositionTable, shared_nodes: &'a AtomicU64, limits: &SearchLimits) -> Searcher<'a> {
+        Searcher {
+            stop,
+            outside_stop,
+            tt,
+            shared_nodes,
+            time_manager: TimeManager::new(limits),
+            node_limit: limits.nodes,
+            nodes: 0,
+            multi_pv: 1,
+            infinite: limits.infinite,
+            depth_offset: 0,
+            killers: [[None; 2]; MAX_PLY],
+            path: limits.history.clone(),
+            root_index: limits.history.len(),
+            tablebases: None,
+            endgame_tables: None,
+            nnue: None,
+            accumulators: vec![],
+        }
+    }
+
+    fn set_options(&mut self, options: &'a SearchOptions) {
+        self.tablebases = options.tablebases.as_deref();
+        self.endgame_tables = options.endgame_tables.as_deref();
+        self.nnue = options.nnue.as_deref();
+        if let Some(network) = self.nnue {
+            self.accumulators = vec![network.new_accumulator(); MAX_PLY + 1];
+        }
+    }
+
+    // Makes the move, keeping the network's accumulator for the new ply up to date
+    fn make_move(&mut self, position: &Position, chess_move: ChessMove, ply: usize) -> Position {
+        let child = position.make_move(chess_move);
+        if let Some(network) = self.nnue {
+            let (parents, children) = self.accumulators.split_at_mut(ply + 1);
+            network.update(&parents[ply], &mut children[0], position, &child);
+        }
+        child
+    }
+
+    fn evaluate(&self, position: &Position, ply: usize) -> i32 {
+        match self.nnue {
+            Some(network) => network.evaluate(&self.accumulators[ply], position.current_team),
+            None => evaluate(position),
+        }
+    }
+
+    // Counts the node and says whether the search has to unwind
+    fn enter_node(&mut self) -> bool {
+        self.nodes += 1;
+        if self.nodes.is_multiple_of(TIME_CHECK_INTERVAL) {
+            let total = self.shared_nodes.fetch_add(TIME_CHECK_INTERVAL, Ordering::Relaxed) + TIME_CHECK_INTERVAL;
+            if self.time_manager.hard_limit_reached() || self.node_limit.is_some_and(|limit| total >= limit) {
+                self.stop.store(true, Ordering::Relaxed);
+            }
+        }
+        if self.outside_stop.load(Ordering::Relaxed) {
+            self.stop.store(true, Ordering::Relaxed);
+        }
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    // Whether the position turned up before with the same side to move, which scores as a
+    // draw. Nothing from before the last capture or pawn move can come round again
+    fn is_repetition(&self, key: u64, halfmove_clock: u32) -> bool {
+        (2..=halfmove_clock as usize).step_by(2).any(|back| self.path.len() >= back && self.path[self.path.len() - back] == key)
+    }
+
+    fn order_moves(&self, position: &Position, moves: Vec<ChessMove>, best_move: Option<ChessMove>, ply: usize) -> Vec<ChessMove> {
+        let mut scored: Vec<(i32, ChessMove)> = moves
+            .into_iter()
+            .map(|chess_move| {
+                let score = if Some(chess_move) == best_move {
+                    1_000_000
+                } else if position.is_capture(chess_move) || chess_move.promotion != PieceType::None {
+                    // Winning and even captures before quiet moves, losing ones after
+                    let exchange = see(position, chess_move);
+                    if exchange >= 0 { 100_000 + exchange } else { -100_000 + exchange }
+                } else if ply < MAX_PLY && self.killers[ply].contains(&Some(chess_move)) {
+                    50_000
+                } else {
+                    0
+                };
+                (score, chess_move)
+            })
+            .collect();
+        scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        scored.into_iter().map(|(_, chess_move)| chess_move).collect()
+    }
+
+    fn negamax(&mut self, position: &Position, depth: i32, mut alpha: i32, beta: i32, ply: usize, pv: &mut Vec<ChessMove>) -> i32 {
+        pv.clear();
+        if self.enter_node() {
+            return 0;
+        }
+
+        let in_check = position.in_check();
+        let depth = if in_check { depth + 1 } else { depth };
+        if depth <= 0 || ply >= MAX_PLY {
+            return self.quiescence(position, alpha, beta, ply);
+        }
+        if position.halfmove_clock >= 100 {
+            return 0;
+        }
+
+        let is_pv = beta - alpha > 1;
+        let key = zobrist::hash(position);
+        self.path.truncate(self.root_index + ply);
+        if ply > 0 && self.is_repetition(key, position.halfmove_clock) {
+            return 0;
+        }
+        self.path.push(key);
+        let entry = self.tt.probe(key);
+        if let Some(entry) = entry
+            && !is_pv
+            && entry.depth >= depth
+        {
+            let score = score_from_tt(entry.score, ply);
+            match entry.bound {
+                Bound::Exact => return score,
+                Bound::Lower if score >= beta => return score,
+                Bound::Upper if score <= alpha => return score,
+                _ => {}
+            }
+        }
+
+        // Our own tables know exactly how far away mate is
+        if ply > 0
+            && let Some(endgame_tables) = self.endgame_tables
+            && let Some(dtm) = endgame_tables.probe(position)
+        {
+            return dtm_score(dtm, ply);
+        }
+
+        // Straight after a capture or pawn move the tables know the answer
+        if ply > 0
+            && let Some(tablebases) = self.tablebases
+            && let Some(wdl) = tablebases.probe_wdl(position)
+        {
+            let score = match wdl {
+                Wdl::Win => TB_WIN - ply as i32,
+                Wdl::Loss => -TB_WIN + ply as i32,
+                _ => 0,
+            };
+            self.tt.store(key, TableEntry {
+                best_move: None,
+                score: score_to_tt(score, ply),
+                depth: MAX_PLY as i32,
+                bound: Bound::Exact,
+            });
+            return score;
+        }
+
+        let moves = position.legal_moves();
+        if moves.is_empty() {
+            return if in_check { -MATE + ply as i32 } else { 0 };
+        }
+
+        let original_alpha = alpha;
+        let mut child_pv: Vec<ChessMove> = vec![];
+        let mut best_score = -INFINITY;
+        let mut best_move = None;
+        for (i, chess_move) in self.order_moves(position, moves, entry.and_then(|entry| entry.best_move), ply).into_iter().enumerate() {
+            let capture = position.is_capture(chess_move);
+
+            // Near the leaves, captures that lose material are not worth a look
+            if depth <= 2 && i > 0 && capture && !in_check && see(position, chess_move) < -100 * depth {
+                continue;
+            }
+
+            // Principal variation search: prove the rest are worse with a null window first
+            let child = self.make_move(position, chess_move, ply);
+            let mut score = if i == 0 {
+                -self.negamax(&child, depth - 1, -beta, -alpha, ply + 1, &mut child_pv)
+            } else {
+                -self.negamax(&child, depth - 1, -alpha - 1, -alpha, ply + 1, &mut child_pv)
+            };
+            if i > 0 && score > alpha && score < beta {
+                score = -self.negamax(&child, depth - 1, -beta, -alpha, ply + 1, &mut child_pv);
+            }
+            if self.stop.load(Ordering::Relaxed) {
+                return 0;
+            }
+
+            if score > best_score {
+                best_score = score;
+                best_move = Some(chess_move);
+                if score > alpha {
+                    alpha = score;
+                    pv.clear();
+                    pv.push(chess_move);
+                    pv.extend(&child_pv);
+                }
+                if score >= beta {
+                    if !capture {
+                        self.killers[ply][1] = self.killers[ply][0];
+                        self.killers[ply][0] = Some(chess_move);
+                    }
+                    break;
+                }
+            }
+        }
+
+        let bound = if best_score >= beta {
+            Bound::Lower
+        } else if best_score > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        self.tt.store(key, TableEntry {
+            best_move,
+            score: score_to_tt(best_score, ply),
+            depth,
+            bound,
+        });
+
+        best_score
+    }
+
+    fn quiescence(&mut self, position: &Position, mut alpha: i32, beta: i32, ply: usize) -> i32 {
+        if self.enter_node() {
+            return 0;
+        }
+
+        let stand_pat = self.evaluate(position, ply);
+        if stand_pat >= beta || ply >= MAX_PLY {
+            return stand_pat;
+        }
+        alpha = alpha.max(stand_pat);
+
+        let captures: Vec<ChessMove> = position
+            .legal_moves()
+            .into_iter()
+            .filter(|chess_move| position.is_capture(*chess_move) || chess_move.promotion == PieceType::Queen)
+            .collect();
+
+        let mut best_score = stand_pat;
+        for chess_move in self.order_moves(position, captures, None, ply) {
+            // Ordered by exchange value, so once one loses material the rest do too
+            if see(position, chess_move) < 0 {
+                break;
+            }
+            // Even winning the piece outright wouldn't bring us back up to alpha
+            let gain = piece_value(position.piece_at(chess_move.to).piece_type) + piece_value(chess_move.promotion);
+            if stand_pat + gain + 200 < alpha {
+                continue;
+            }
+
+            let child = self.make_move(position, chess_move, ply);
+            let score = -self.quiescence(&child, -beta, -alpha, ply + 1);
+            if self.stop.load(Ordering::Relaxed) {
+                return 0;
+            }
+            if score > best_score {
+                best_score = score;
+                if score > alpha {
+                    alpha = score;
+                }
+                if score >= beta {
+                    break;
+                }
+            }
+        }
+
+        best_score
+    }
+
+    fn search_root(&mut self, position: &Position, depth: i32, root_moves: &[ChessMove], previous_best: Option<ChessMove>) -> Option<(ChessMove, i32, Vec<ChessMove>)> {
+        let mut alpha = -INFINITY;
+        let mut best: Option<(ChessMove, i32, Vec<ChessMove>)> = None;
+        let mut child_pv: Vec<ChessMove> = vec![];
+
+        for (i, chess_move) in self.order_moves(position, root_moves.to_vec(), previous_best, 0).into_iter().enumerate() {
+            let child = self.make_move(position, chess_move, 0);
+            let mut score = if i == 0 {
+                -self.negamax(&child, depth - 1, -INFINITY, -alpha, 1, &mut child_pv)
+            } else {
+                -self.negamax(&child, depth - 1, -alpha - 1, -alpha, 1, &mut child_pv)
+            };
+            if i > 0 && score > alpha {
+                score = -self.negamax(&child, depth - 1, -INFINITY, -alpha, 1, &mut child_pv);
+            }
+            if self.stop.load(Ordering::Relaxed) {
+                break;
+            }
+            if score > alpha {
+                alpha = score;
+                let mut pv = vec![chess_move];
+                pv.extend(&child_pv);
+                best = Some((chess_move, score, pv));
+            }
+        }
+
+        best
+    }
+
+    fn iterative_deepening(&mut self, position: &Position, root_moves: &[ChessMove], max_depth: u32, report: &mut impl FnMut(&SearchInfo)) -> SearchResult {
+        let mut result = SearchResult {
+            best_move: root_moves.first().copied(),
+            score: 0,
+            depth: 0,
+            nodes: 0,
+            pv: vec![],
+            lines: vec![],
+        };
+        if let Some(network) = self.nnue {
+            network.refresh(&mut self.accumulators[0], position);
+        }
+        self.path.truncate(self.root_index);
+        self.path.push(zobrist::hash(position));
+
+        for depth in 1..=max_depth {
+            // Each extra line searches the root again without the moves already picked
+            let mut lines: Vec<PvLine> = vec![];
+            let mut completed = true;
+            for pv_index in 0..self.multi_pv.min(root_moves.len()) {
+                let candidates: Vec<ChessMove> = root_moves
+                    .iter()
+                    .filter(|chess_move| !lines.iter().any(|line| line.pv[0] == **chess_move))
+                    .copied()
+                    .collect();
+                let previous_best = result.lines.get(pv_index).map(|line| line.pv[0]).or(result.best_move);
+                let found = self.search_root(position, (depth + self.depth_offset) as i32, &candidates, previous_best);
+                completed = !self.stop.load(Ordering::Relaxed);
+
+                // A partial iteration still searched the old best move first, so anything
+                // that beat it is trustworthy
+                match found {
+                    Some((_, score, pv)) if completed => lines.push(PvLine { score, pv }),
+                    Some((_, score, pv)) if pv_index == 0 && (result.depth == 0 || score > result.score) => lines.push(PvLine { score, pv }),
+                    _ => {}
+                }
+                if !completed {
+                    // Fill up with the lines from the last completed depth
+                    let older: Vec<PvLine> = result
+                        .lines
+                        .iter()
+                        .filter(|line| !lines.iter().any(|new| new.pv[0] == line.pv[0]))
+                        .cloned()
+                        .collect();
+                    lines.extend(older);
+                    break;
+                }
+            }
+
+            if !lines.is_empty() {
+                if completed {
+                    lines.sort_by_key(|line| std::cmp::Reverse(line.score));
+                }
+                lines.truncate(self.multi_pv);
+                result.best_move = Some(lines[0].pv[0]);
+                result.score = lines[0].score;
+                result.pv = lines[0].pv.clone();
+                result.lines = lines;
+            }
+            if !completed {
+                break;
+            }
+            result.depth = depth;
+            self.tt.store(zobrist::hash(position), TableEntry {
+                best_move: result.best_move,
+                score: result.score,
+                depth: depth as i32,
+                bound: Bound::Exact,
+            });
+
+            report(&SearchInfo {
+                depth,
+                nodes: self.shared_nodes.load(Ordering::Relaxed),
+                time_ms: self.time_manager.elapsed().as_millis() as u64,
+                lines: result.lines.clone(),
+            });
+
+            if let Some(best_move) = result.best_move
+                && self.time_manager.should_stop(best_move, result.score)
+            {
+                break;
+            }
+            if self.multi_pv == 1 && !self.infinite && result.score.abs() >= MATE - depth as i32 {
+                break; // Found the quickest mate there is
+            }
+        }
+
+        result
+    }
+}
+
+fn dtm_score(dtm: Dtm, ply: usize) -> i32 {
+    match dtm {
+        Dtm::Win(moves) => MATE - ply as i32 - (2 * moves as i32 - 1),
+        Dtm::Draw => 0,
+        Dtm::Loss(moves) => -MATE + ply as i32 + 2 * moves as i32,
+    }
+}
+
+pub fn is_mate_score(score: i32) -> bool {
+    score.abs() >= MATE_THRESHOLD
+}
+
+pub fn score_to_uci(score: i32) -> String {
+    if is_mate_score(score) {
+        let moves = (MATE - score.abs() + 1) / 2;
+        format!("mate {}", if score > 0 { moves } else { -moves })
+    } else {
+        format!("cp {}", score)
+    }
+}
+
+// Iterative deepening until a limit is hit or `stop` is set from outside. With more than one
+// thread, helpers search the same position and share what they find through `tt` (Lazy SMP);
+// only the main thread's result is reported. `report` is called after every completed depth.
+// An infinite search can still return early, at the deepest depth or when `stop` is already
+// set, so the caller waits for `stop` itself before answering.
+pub fn search(position: &Position, limits: &SearchLimits, options: &SearchOptions, tt: &TranspositionTable, stop: Arc<AtomicBool>, mut report: impl FnMut(&SearchInfo)) -> SearchResult {
+    let root_moves = position.legal_moves();
+    if root_moves.len() <= 1 && limits.depth.is_none() && limits.nodes.is_none() && !limits.infinite {
+        // Nothing to think about, but whoever is listening still hears what the move is
+        let lines: Vec<PvLine> = root_moves
+            .first()
+            .map(|only_move| PvLine { score: -evaluate(&position.make_move(*only_move)), pv: vec![*only_move] })
+            .into_iter()
+            .collect();
+        if !lines.is_empty() {
+            report(&SearchInfo { depth: 0, nodes: 0, time_ms: 0, lines: lines.clone() });
+        }
+        return SearchResult {
+            best_move: root_moves.first().copied(),
+            score: lines.first().map_or(0, |line| line.score),
+            depth: 0,
+            nodes: 0,
+            pv: lines.first().map_or(vec![], |line| line.pv.clone()),
+            lines,
+        };
+    }
+
+    // In a tablebase ending just play the move the tables give. That's only one line, so
+    // MultiPV searches as usual and probes the tables below the root
+    let table_move = match (&options.endgame_tables, &options.tablebases) {
+        _ if options.multi_pv > 1 || limits.infinite => None,
+        (Some(endgame_tables), _) if let Some((best_move, dtm)) = endgame_tables.best_move(position) => Some((best_move, dtm_score(dtm, 0))),
+        (_, Some(tablebases)) => tablebases.best_move(position).map(|(best_move, result)| (best_move, result_score(result, 0))),
+        _ => None,
+    };
+    if let Some((best_move, score)) = table_move {
+        let line = PvLine { score, pv: vec![best_move] };
+        report(&SearchInfo { depth: 1, nodes: 0, time_ms: 0, lines: vec![line.clone()] });
+        return SearchResult {
+            best_move: Some(best_move),
+            score: line.score,
+            depth: 1,
+            nodes: 0,
+            pv: line.pv.clone(),
+            lines: vec![line],
+        };
+    }
+
+    let max_depth = limits.depth.unwrap_or(MAX_PLY as u32 - 1);
+    let shared_nodes = AtomicU64::new(0);
+    let finished = AtomicBool::new(false);
+    let mut result = std::thread::scope(|scope| {
+        for thread in 1..options.threads.max(1) {
+            let (finished, stop, shared_nodes, root_moves) = (&finished, &*stop, &shared_nodes, &root_moves);
+            scope.spawn(move || {
+                let helper_limits = SearchLimits { history: limits.history.clone(), ..Default::default() };
+                let mut helper = Searcher::new(finished, stop, tt, shared_nodes, &helper_limits);
+                helper.depth_offset = thread as u32 % 2;
+                helper.set_options(options);
+                helper.iterative_deepening(position, root_moves, max_depth, &mut |_| {});
+            });
+        }
+
+        let mut searcher = Searcher::new(&finished, &stop, tt, &shared_nodes, limits);
+        searcher.multi_pv = options.multi_pv.max(1);
+        searcher.set_options(options);
+        let result = searcher.iterative_deepening(position, &root_moves, max_depth, &mut report);
+        finished.store(true, Ordering::Relaxed);
+        result
+    });
+
+    result.nodes = shared_nodes.load(Ordering::Relaxed);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn search_fen(fen: &str, limits: SearchLimits) -> SearchResult {
+        let position = Position::from_fen(fen).unwrap();
+        let tt = TranspositionTable::new(16);
+        search(&position, &limits, &SearchOptions::default(), &tt, Arc::new(AtomicBool::new(false)), |_| {})
+    }
+
+    #[test]
+    fn test_finds_mate_in_one() {
+        let limits = SearchLimits { depth: Some(2), ..Default::default() };
+        let result = search_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", limits);
+        assert_eq!(result.best_move.unwrap().to_uci(), "a1a8");
+        assert_eq!(score_to_uci(result.score), "mate 1");
+    }
+
+    #[test]
+    fn test_wins_hanging_queen() {
+        let limits = SearchLimits { depth: Some(3), ..Default::default() };
+        let result = search_fen("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1", limits);
+        assert_eq!(result.best_move.unwrap().to_uci(), "d2d5");
+    }
+
+    #[test]
+    fn test_movetime_is_respected() {
+        let limits = SearchLimits { movetime: Some(200), move_overhead: 0, ..Default::default() };
+        let start = Instant::now();
+        let result = search_fen("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3", limits);
+        assert!(result.best_move.is_some());
+        assert!(start.elapsed().as_millis() < 250);
+    }
+
+    #[test]
+    fn test_helper_threads_agree() {
+        let position = Position::from_fen("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1").unwrap();
+        let limits = SearchLimits { depth: Some(4), ..Default::default() };
+        let options = SearchOptions { threads: 4, ..Default::default() };
+        let tt = TranspositionTable::new(16);
+        let result = search(&position, &limits, &options, &tt, Arc::new(AtomicBool::new(false)), |_| {});
+        assert_eq!(result.best_move.unwrap().to_uci(), "d2d5");
+        assert!(tt.probe(zobrist::hash(&position)).is_some());
+    }
+
+    #[test]
+    fn test_forced_replies_are_still_reported() {
+        // Taking the rook is the king's only move
+        let position = Position::from_fen("k7/8/8/8/8/8/1r6/K7 w - - 0 1").unwrap();
+        let tt = TranspositionTable::new(16);
+        let mut reported = vec![];
+        let result = search(&position, &SearchLimits::default(), &SearchOptions::default(), &tt, Arc::new(AtomicBool::new(false)), |info| {
+            reported.push(info.clone())
+        });
+        assert_eq!(result.best_move.unwrap().to_uci(), "a1b2");
+        assert_eq!(reported.len(), 1);
+        assert_eq!(reported[0].lines[0].pv, result.pv);
+    }
+
+    #[test]
+    fn test_perpetual_check_saves_the_game() {
+        // A rook down and about to be mated, but the queen can check forever from e8 and h5
+        let fen = "7k/6p1/8/8/4Q3/8/qr3PPP/6K1 w - - 0 1";
+        let result = search_fen(fen, SearchLimits { depth: Some(5), ..Default::default() });
+        assert_eq!(result.score, 0);
+        assert!(Position::from_fen(fen).unwrap().make_move(result.best_move.unwrap()).in_check());
+
+        // Once the checks have been given, the game's own history counts too
+        let mut position = Position::from_fen(fen).unwrap();
+        let mut history = vec![];
+        for text in ["e4e8", "h8h7", "e8h5", "h7g8"] {
+            history.push(zobrist::hash(&position));
+            position = position.make_move(position.parse_uci_move(text).unwrap());
+        }
+        let limits = SearchLimits { depth: Some(3), history, ..Default::default() };
+        let result = search(&position, &limits, &SearchOptions::default(), &TranspositionTable::new(16), Arc::new(AtomicBool::new(false)), |_| {});
+        assert_eq!((result.score, result.best_move.unwrap().to_uci()), (0, "h5e8".to_string()));
+    }
+
+    #[test]
+    fn test_infinite_searches_take_no_shortcuts() {
+        // Finding the mate doesn't end it, and the caller's stop is left for the caller
+        let position = Position::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let limits = SearchLimits { depth: Some(4), infinite: true, ..Default::default() };
+        let result = search(&position, &limits, &SearchOptions::default(), &TranspositionTable::new(16), stop.clone(), |_| {});
+        assert_eq!((result.depth, score_to_uci(result.score)), (4, "mate 1".to_string()));
+        assert!(!stop.load(Ordering::Relaxed));
+
+        // A single legal move still gets searched until the caller stops it
+        let position = Position::from_fen("k7/8/8/8/8/8/1r6/K7 w - - 0 1").unwrap();
+        let limits = SearchLimits { infinite: true, ..Default::default() };
+        let stopper = stop.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            stopper.store(true, Ordering::Relaxed);
+        });
+        let result = search(&position, &limits, &SearchOptions::default(), &TranspositionTable::new(16), stop, |_| {});
+        assert_eq!(result.best_move.unwrap().to_uci(), "a1b2");
+        assert!(result.depth > 0);
+    }
+
+    #[test]
+    fn test_table_scores_keep_their_distance_in_the_tt() {
+        for score in [MATE - 7, TB_WIN - 7, -TB_WIN + 7] {
+            let stored = score_to_tt(score, 5);
+            assert_eq!(score_from_tt(stored, 3), score + if score > 0 { 2 } else { -2 });
+        }
+        assert_eq!(score_from_tt(score_to_tt(250, 5), 3), 250);
+    }
+
+    #[test]
+    fn test_endgame_tables_give_mate_scores() {
+        let directory = std::env::temp_dir().join("chess_again_test_search_dtm");
+        crate::endgame::run_generate(&[directory.to_str().unwrap().to_string(), "KRvK".to_string()]);
+        let endgame_tables = Arc::new(EndgameTables::load(directory.to_str().unwrap()).unwrap());
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        // Rook and king against king is a mate in 16 from here; no search finds that on its own
+        let position = Position::from_fen("8/8/8/3k4/8/8/8/R3K3 w - - 0 1").unwrap();
+        let options = SearchOptions { endgame_tables: Some(endgame_tables), ..Default::default() };
+        let tt = TranspositionTable::new(16);
+        let limits = SearchLimits { depth: Some(4), ..Default::default() };
+        let result = search(&position, &limits, &options, &tt, Arc::new(AtomicBool::new(false)), |_| {});
+        assert!(score_to_uci(result.score).starts_with("mate "));
+        let after = position.make_move(result.best_move.unwrap());
+        assert!(matches!(options.endgame_tables.unwrap().probe(&after), Some(Dtm::Loss(_))));
+    }
+
+    #[test]
+    fn test_multi_pv_lines() {
+        let position = Position::from_fen("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1").unwrap();
+        let limits = SearchLimits { depth: Some(3), ..Default::default() };
+        let options = SearchOptions { multi_pv: 3, ..Default::default() };
+        let tt = TranspositionTable::new(16);
+        let result = search(&position, &limits, &options, &tt, Arc::new(AtomicBool::new(false)), |_| {});
+
+        assert_eq!(result.lines.len(), 3);
+        assert_eq!(result.lines[0].pv[0].to_uci(), "d2d5");
+        assert!(result.lines.windows(2).all(|pair| pair[0].score >= pair[1].score));
+        assert_ne!(result.lines[1].pv[0], result.lines[2].pv[0]);
+    }
+}
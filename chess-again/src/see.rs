@@ -0,0 +1,216 @@
+use crate::position::{ChessMove, Position};
+use crate::{Piece, PieceType, Team, check_in_board, create_piece};
+
+pub fn piece_value(piece_type: PieceType) -> i32 {
+    match piece_type {
+        PieceType::Pawn => 100,
+        PieceType::Knight => 300,
+        PieceType::Bishop => 300,
+        PieceType::Rook => 500,
+        PieceType::Queen => 900,
+        PieceType::King => 20000,
+        PieceType::None => 0,
+    }
+}
+
+// Every piece of `team` that attacks `pos`. Sliders are found by walking outwards from the
+// square, so a piece hiding behind another slider shows up once the front one is removed.
+pub fn attackers_to(board: &[[Piece; 8]; 8], pos: [isize; 2], team: Team) -> Vec<[isize; 2]> {
+    let mut temp_vector: Vec<[isize; 2]> = vec![];
+
+    let pawn_rank = if team == Team::White { -1 } else { 1 };
+    let jumps = [
+        (PieceType::Pawn, vec![[-1, pawn_rank], [1, pawn_rank]]),
+        (PieceType::Knight, vec![[1, 2], [2, 1], [2, -1], [1, -2], [-1, 2], [-2, 1], [-2, -1], [-1, -2]]),
+        (PieceType::King, vec![[0, 1], [1, 1], [1, 0], [1, -1], [0, -1], [-1, -1], [-1, 0], [-1, 1]]),
+    ];
+    for (piece_type, offsets) in jumps {
+        for offset in offsets {
+            let new_pos = [pos[0] + offset[0], pos[1] + offset[1]];
+            if check_in_board(new_pos) {
+                let piece = board[new_pos[0] as usize][new_pos[1] as usize];
+                if piece.team == team && piece.piece_type == piece_type {
+                    temp_vector.push(new_pos);
+                }
+            }
+        }
+    }
+
+    let rays = [
+        (PieceType::Rook, [[0, 1], [1, 0], [0, -1], [-1, 0]]),
+        (PieceType::Bishop, [[1, 1], [1, -1], [-1, -1], [-1, 1]]),
+    ];
+    for (piece_type, offsets) in rays {
+        for offset in offsets {
+            let mut new_pos = [pos[0] + offset[0], pos[1] + offset[1]];
+            while check_in_board(new_pos) {
+                let piece = board[new_pos[0] as usize][new_pos[1] as usize];
+                if piece.piece_type != PieceType::None {
+                    if piece.team == team && (piece.piece_type == piece_type || piece.piece_type == PieceType::Queen) {
+                        temp_vector.push(new_pos);
+                    }
+                    break;
+                }
+                new_pos = [new_pos[0] + offset[0], new_pos[1] + offset[1]];
+            }
+        }
+    }
+
+    temp_vector
+}
+
+fn least_valuable_attacker(board: &[[Piece; 8]; 8], pos: [isize; 2], team: Team) -> Option<[isize; 2]> {
+    attackers_to(board, pos, team)
+        .into_iter()
+        .min_by_key(|attacker| piece_value(board[attacker[0] as usize][attacker[1] as usize].piece_type))
+}
+
+// Static exchange evaluation: the material the side making `chess_move` wins (or loses, if
+// negative) once both sides have made every profitable recapture on the target square.
+// Pins are ignored, which is the usual trade-off for speed.
+pub fn see(position: &Position, chess_move: ChessMove) -> i32 {
+    let mut board = position.board;
+    let target = chess_move.to;
+    let mut mover = board[chess_move.from[0] as usize][chess_move.from[1] as usize];
+    let mut gain: Vec<i32> = vec![];
+
+    if position.is_en_passant(chess_move) {
+        gain.push(piece_value(PieceType::Pawn));
+        board[target[0] as usize][chess_move.from[1] as usize] = create_piece(PieceType::None, Team::None);
+    } else {
+        gain.push(piece_value(board[target[0] as usize][target[1] as usize].piece_type));
+    }
+    if chess_move.promotion != PieceType::None {
+        gain[0] += piece_value(chess_move.promotion) - piece_value(PieceType::Pawn);
+        mover.piece_type = chess_move.promotion;
+    }
+
+    let mut from = chess_move.from;
+    let mut side = mover.team;
+    loop {
+        // Make the capture, then see if the other side can take back
+        board[from[0] as usize][from[1] as usize] = create_piece(PieceType::None, Team::None);
+        board[target[0] as usize][target[1] as usize] = mover;
+        let on_square = piece_value(mover.piece_type);
+
+        side = if side == Team::White { Team::Black } else { Team::White };
+        let Some(attacker) = least_valuable_attacker(&board, target, side) else {
+            break;
+        };
+        let mut next = board[attacker[0] as usize][attacker[1] as usize];
+
+        // A king can only recapture if nothing would be able to take it back
+        if next.piece_type == PieceType::King {
+            let mut after = board;
+            after[attacker[0] as usize][attacker[1] as usize] = create_piece(PieceType::None, Team::None);
+            if least_valuable_attacker(&after, target, mover.team).is_some() {
+                break;
+            }
+        }
+
+        let mut value = on_square - gain[gain.len() - 1];
+        if next.piece_type == PieceType::Pawn && (target[1] == 0 || target[1] == 7) {
+            value += piece_value(PieceType::Queen) - piece_value(PieceType::Pawn);
+            next.piece_type = PieceType::Queen;
+        }
+        gain.push(value);
+
+        mover = next;
+        from = attacker;
+    }
+
+    while gain.len() > 1 {
+        let last = gain.pop().unwrap();
+        let previous = gain.len() - 1;
+        gain[previous] = -(-gain[previous]).max(last);
+    }
+
+    gain[0]
+}
+
+// Pieces of `team` the other side could win material by capturing.
+pub fn hanging_pieces(position: &Position, team: Team) -> Vec<[isize; 2]> {
+    let enemy = if team == Team::White { Team::Black } else { Team::White };
+    let mut enemy_to_move = *position;
+    enemy_to_move.current_team = enemy;
+
+    let mut temp_vector: Vec<[isize; 2]> = vec![];
+    for y in (0..=7).rev() {
+        for x in 0..=7 {
+            let piece = position.board[x as usize][y as usize];
+            if piece.team != team || piece.piece_type == PieceType::King {
+                continue;
+            }
+            let hanging = attackers_to(&position.board, [x, y], enemy)
+                .into_iter()
+                .any(|attacker| see(&enemy_to_move, ChessMove::new(attacker, [x, y])) > 0);
+            if hanging {
+                temp_vector.push([x, y]);
+            }
+        }
+    }
+
+    temp_vector
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_position(current_team: Team) -> Position {
+        let empty_piece = create_piece(PieceType::None, Team::None);
+        Position {
+            board: [[empty_piece; 8]; 8],
+            current_team,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_see_undefended_capture() {
+        let mut position = empty_position(Team::White);
+        position.board[3][3] = create_piece(PieceType::Rook, Team::White);
+        position.board[3][6] = create_piece(PieceType::Knight, Team::Black);
+
+        assert_eq!(see(&position, ChessMove::new([3, 3], [3, 6])), 300);
+    }
+
+    #[test]
+    fn test_see_losing_capture() {
+        // Rook takes a pawn that is defended by another pawn
+        let mut position = empty_position(Team::White);
+        position.board[3][0] = create_piece(PieceType::Rook, Team::White);
+        position.board[3][4] = create_piece(PieceType::Pawn, Team::Black);
+        position.board[4][5] = create_piece(PieceType::Pawn, Team::Black);
+
+        assert_eq!(see(&position, ChessMove::new([3, 0], [3, 4])), -400);
+    }
+
+    #[test]
+    fn test_see_x_ray() {
+        // Rook takes a rook-defended knight, backed up by a queen behind it
+        let mut position = empty_position(Team::White);
+        position.board[4][0] = create_piece(PieceType::Queen, Team::White);
+        position.board[4][1] = create_piece(PieceType::Rook, Team::White);
+        position.board[4][5] = create_piece(PieceType::Knight, Team::Black);
+        position.board[4][7] = create_piece(PieceType::Rook, Team::Black);
+
+        assert_eq!(see(&position, ChessMove::new([4, 1], [4, 5])), 300);
+
+        position.board[4][0] = create_piece(PieceType::None, Team::None);
+        assert_eq!(see(&position, ChessMove::new([4, 1], [4, 5])), -200);
+    }
+
+    #[test]
+    fn test_hanging_pieces() {
+        let mut position = empty_position(Team::White);
+        position.board[2][2] = create_piece(PieceType::Knight, Team::White);
+        position.board[6][6] = create_piece(PieceType::Bishop, Team::Black);
+        position.board[5][5] = create_piece(PieceType::Bishop, Team::White);
+
+        // The white bishop is attacked and undefended, and it shields the knight behind it
+        let hanging = hanging_pieces(&position, Team::White);
+        assert!(hanging.contains(&[5, 5]));
+        assert!(!hanging.contains(&[2, 2]));
+    }
+}
@@ -0,0 +1,288 @@
+use std::time::Duration;
+
+use eframe::egui;
+use egui::Color32;
+
+use crate::theme::{PieceSet, THEMES};
+
+// Kept next to game.pgn, in the directory the program is run from
+pub const SETTINGS_FILE: &str = "chess-again.cfg";
+
+// Laid over the square colours, so they should be see-through
+#[derive(Clone, Copy)]
+pub struct Highlights {
+    pub last_move: Color32,
+    pub selected: Color32,
+    pub check: Color32,
+    pub moves: Color32, // Dots on empty squares, rings round captures
+}
+
+impl Default for Highlights {
+    fn default() -> Self {
+        Self {
+            last_move: Color32::from_rgba_unmultiplied(255, 235, 60, 110),
+            selected: Color32::from_rgba_unmultiplied(60, 120, 255, 110),
+            check: Color32::from_rgba_unmultiplied(255, 0, 0, 90),
+            moves: Color32::from_black_alpha(70),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Coordinates {
+    Inside, // In the corners of the edge squares
+    Outside,
+    Hidden,
+}
+
+// How the board looks and behaves
+pub struct Settings {
+    animation_ms: u64,
+    highlights: Highlights,
+    coordinates: Coordinates,
+    tools_window: bool, // The panels on the right go in a window of their own
+    light: Color32,
+    dark: Color32,
+    pieces: PieceSet,
+    pieces_path: String, // What's typed in the box, which may not have loaded
+    error: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            animation_ms: 200,
+            highlights: Highlights::default(),
+            coordinates: Coordinates::Inside,
+            tools_window: false,
+            light: THEMES[0].light,
+            dark: THEMES[0].dark,
+            pieces: PieceSet::default(),
+            pieces_path: String::new(),
+            error: None,
+        }
+    }
+}
+
+fn colour_text(colour: Color32) -> String {
+    let [r, g, b, a] = colour.to_array();
+    format!("{} {} {} {}", r, g, b, a)
+}
+
+fn parse_colour(text: &str) -> Option<Color32> {
+    let values: Vec<u8> = text.split_whitespace().map(|value| value.parse().ok()).collect::<Option<_>>()?;
+    match values[..] {
+        [r, g, b, a] => Some(Color32::from_rgba_premultiplied(r, g, b, a)),
+        _ => None,
+    }
+}
+
+impl Settings {
+    // Whatever was saved last time, with anything missing or unreadable left at its default
+    pub fn load(path: &str) -> Settings {
+        let mut settings = match std::fs::read_to_string(path) {
+            Ok(text) => Settings::from_text(&text),
+            Err(_) => Settings::default(),
+        };
+        if !settings.pieces_path.is_empty() {
+            match PieceSet::load(&settings.pieces_path) {
+                Ok(pieces) => settings.pieces = pieces,
+                Err(error) => settings.error = Some(error),
+            }
+        }
+        settings
+    }
+
+    // One "name value" line per setting
+    fn from_text(text: &str) -> Settings {
+        let mut settings = Settings::default();
+        for line in text.lines() {
+            let (name, value) = line.split_once(' ').unwrap_or((line, ""));
+            let value = value.trim();
+            let colour = parse_colour(value);
+            match name {
+                "animation" => settings.animation_ms = value.parse().unwrap_or(settings.animation_ms),
+                "coordinates" => {
+                    settings.coordinates = match value {
+                        "outside" => Coordinates::Outside,
+                        "hidden" => Coordinates::Hidden,
+                        _ => Coordinates::Inside,
+                    }
+                }
+                "tools_window" => settings.tools_window = value == "true",
+                "light" => settings.light = colour.unwrap_or(settings.light),
+                "dark" => settings.dark = colour.unwrap_or(settings.dark),
+                "last_move" => settings.highlights.last_move = colour.unwrap_or(settings.highlights.last_move),
+                "selected" => settings.highlights.selected = colour.unwrap_or(settings.highlights.selected),
+                "check" => settings.highlights.check = colour.unwrap_or(settings.highlights.check),
+                "moves" => settings.highlights.moves = colour.unwrap_or(settings.highlights.moves),
+                "pieces" => settings.pieces_path = value.to_string(),
+                _ => {}
+            }
+        }
+        settings
+    }
+
+    fn to_text(&self) -> String {
+        let coordinates = match self.coordinates {
+            Coordinates::Inside => "inside",
+            Coordinates::Outside => "outside",
+            Coordinates::Hidden => "hidden",
+        };
+        let lines = [
+            format!("animation {}", self.animation_ms),
+            format!("coordinates {}", coordinates),
+            format!("tools_window {}", self.tools_window),
+            format!("light {}", colour_text(self.light)),
+            format!("dark {}", colour_text(self.dark)),
+            format!("last_move {}", colour_text(self.highlights.last_move)),
+            format!("selected {}", colour_text(self.highlights.selected)),
+            format!("check {}", colour_text(self.highlights.check)),
+            format!("moves {}", colour_text(self.highlights.moves)),
+            format!("pieces {}", self.pieces.directory().unwrap_or("")),
+        ];
+        lines.join("\n") + "\n"
+    }
+
+    fn save(&mut self) {
+        if let Err(error) = std::fs::write(SETTINGS_FILE, self.to_text()) {
+            self.error = Some(format!("Can't write {}: {}", SETTINGS_FILE, error));
+        }
+    }
+
+    // How long a piece takes to slide to its square, or zero to jump straight there
+    pub fn animation(&self) -> Duration {
+        Duration::from_millis(self.animation_ms)
+    }
+
+    pub fn highlights(&self) -> Highlights {
+        self.highlights
+    }
+
+    pub fn coordinates(&self) -> Coordinates {
+        self.coordinates
+    }
+
+    pub fn pieces(&self) -> &PieceSet {
+        &self.pieces
+    }
+
+    pub fn square_colour(&self, square: [isize; 2]) -> Color32 {
+        if (square[0] + square[1]) % 2 == 0 { self.dark } else { self.light }
+    }
+
+    pub fn tools_window(&self) -> bool {
+        self.tools_window
+    }
+
+    // For when the separate window gets closed
+    pub fn dock_tools(&mut self) {
+        self.tools_window = false;
+        self.save();
+    }
+
+    fn show_theme(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        let current = THEMES.iter().find(|theme| theme.light == self.light && theme.dark == self.dark);
+        egui::ComboBox::from_label("Board theme").selected_text(current.map_or("Custom", |theme| theme.name)).show_ui(ui, |ui| {
+            for theme in &THEMES {
+                if ui.selectable_label(current.is_some_and(|current| current.name == theme.name), theme.name).clicked() {
+                    (self.light, self.dark) = (theme.light, theme.dark);
+                    changed = true;
+                }
+            }
+        });
+        ui.horizontal(|ui| {
+            changed |= egui::color_picker::color_edit_button_srgba(ui, &mut self.light, egui::color_picker::Alpha::Opaque).changed();
+            ui.label("Light squares");
+            changed |= egui::color_picker::color_edit_button_srgba(ui, &mut self.dark, egui::color_picker::Alpha::Opaque).changed();
+            ui.label("Dark squares");
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Pieces");
+            ui.add(egui::TextEdit::singleline(&mut self.pieces_path).hint_text("directory of .svg or .png").desired_width(150.0));
+            if ui.button("Load").clicked() {
+                match PieceSet::load(self.pieces_path.trim()) {
+                    Ok(pieces) => {
+                        self.pieces = pieces;
+                        self.error = None;
+                        changed = true;
+                    }
+                    Err(error) => self.error = Some(error),
+                }
+            }
+            if self.pieces.directory().is_some() && ui.button("Built-in").clicked() {
+                self.pieces = PieceSet::default();
+                self.pieces_path.clear();
+                changed = true;
+            }
+        });
+        if let Some(error) = &self.error {
+            ui.colored_label(Color32::RED, error);
+        }
+        changed
+    }
+
+    // Saved as soon as anything changes
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        let mut changed = false;
+        egui::CollapsingHeader::new("Board settings").show(ui, |ui| {
+            changed |= self.show_theme(ui);
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.label("Coordinates");
+                changed |= ui.radio_value(&mut self.coordinates, Coordinates::Inside, "Inside").changed();
+                changed |= ui.radio_value(&mut self.coordinates, Coordinates::Outside, "Outside").changed();
+                changed |= ui.radio_value(&mut self.coordinates, Coordinates::Hidden, "Off").changed();
+            });
+            changed |= ui.checkbox(&mut self.tools_window, "Tools in a separate window").changed();
+            changed |= ui.add(egui::Slider::new(&mut self.animation_ms, 0..=1000).text("Animation (ms)")).changed();
+            let highlights = &mut self.highlights;
+            egui::Grid::new("highlight colours").show(ui, |ui| {
+                for (name, colour) in [
+                    ("Last move", &mut highlights.last_move),
+                    ("Selected piece", &mut highlights.selected),
+                    ("King in check", &mut highlights.check),
+                    ("Legal moves", &mut highlights.moves),
+                ] {
+                    ui.label(name);
+                    changed |= egui::color_picker::color_edit_button_srgba(ui, colour, egui::color_picker::Alpha::OnlyBlend).changed();
+                    ui.end_row();
+                }
+            });
+            if ui.button("Default colours").clicked() {
+                self.highlights = Highlights::default();
+                changed = true;
+            }
+        });
+        if changed {
+            self.save();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_text_round_trip() {
+        let mut settings = Settings { animation_ms: 350, coordinates: Coordinates::Outside, tools_window: true, ..Default::default() };
+        (settings.light, settings.dark) = (THEMES[1].light, THEMES[1].dark);
+        settings.highlights.check = Color32::from_rgba_unmultiplied(200, 0, 0, 128);
+
+        let text = settings.to_text();
+        assert!(text.contains("pieces \n"));
+        let loaded = Settings::from_text(&text);
+        assert_eq!((loaded.animation_ms, loaded.coordinates, loaded.tools_window), (350, Coordinates::Outside, true));
+        assert_eq!((loaded.light, loaded.dark), (THEMES[1].light, THEMES[1].dark));
+        assert_eq!(loaded.highlights.check, settings.highlights.check);
+        assert_eq!(loaded.to_text(), text);
+
+        // Anything it can't make sense of stays as it was
+        let odd = Settings::from_text("animation fast\nlight 1 2\nunknown 3\ndark 10 20 30 255");
+        assert_eq!((odd.animation_ms, odd.light), (200, THEMES[0].light));
+        assert_eq!(odd.dark, Color32::from_rgb(10, 20, 30));
+    }
+}
@@ -0,0 +1,114 @@
+use rand::Rng;
+use rand::seq::IndexedRandom;
+
+use crate::position::ChessMove;
+use crate::search::{MATE_THRESHOLD, PvLine, SearchLimits, SearchOptions};
+
+pub const MAX_SKILL: u32 = 20;
+pub const MIN_ELO: u32 = 800;
+pub const MAX_ELO: u32 = 2800;
+
+// How many root moves a weakened search keeps scores for, to pick between
+const CANDIDATES: usize = 4;
+const ELO_PER_LEVEL: u32 = 100;
+
+// A rough scale, each level being worth about a hundred points
+pub fn skill_from_elo(elo: u32) -> u32 {
+    (elo.clamp(MIN_ELO, MAX_ELO) - MIN_ELO) / ELO_PER_LEVEL
+}
+
+pub fn elo_from_skill(skill: u32) -> u32 {
+    MIN_ELO + skill.min(MAX_SKILL) * ELO_PER_LEVEL
+}
+
+// Below the top level the search stops early and keeps a few candidate moves around;
+// the limits it was given still apply when they're tighter
+pub fn limit_search(skill: u32, limits: &mut SearchLimits, options: &mut SearchOptions) {
+    if skill >= MAX_SKILL {
+        return;
+    }
+    let depth = 1 + skill / 2;
+    let nodes = 500 << (skill / 2);
+    limits.depth = Some(limits.depth.map_or(depth, |limit| limit.min(depth)));
+    limits.nodes = Some(limits.nodes.map_or(nodes, |limit| limit.min(nodes)));
+    options.multi_pv = options.multi_pv.max(CANDIDATES);
+    // One thread keeps the node limit meaning the same thing on every machine
+    options.threads = 1;
+}
+
+// Picks one of the candidate lines, usually a good one: the lower the level the more the
+// scores get blurred, and now and then it overlooks something and plays any of them
+pub fn choose_move(skill: u32, lines: &[PvLine], rng: &mut impl Rng) -> Option<ChessMove> {
+    let best = lines.first()?;
+    if skill >= MAX_SKILL || best.score >= MATE_THRESHOLD {
+        return best.pv.first().copied();
+    }
+    // Walking into a mate isn't believable when there's a way out
+    let candidates: Vec<&PvLine> = lines
+        .iter()
+        .filter(|line| !line.pv.is_empty() && (line.score > -MATE_THRESHOLD || line.score == best.score))
+        .collect();
+
+    let weakness = (MAX_SKILL - skill) as f64;
+    let chosen = if rng.random_bool(weakness * 0.015) {
+        candidates.choose(rng)
+    } else {
+        let temperature = 10.0 + 15.0 * weakness;
+        candidates.choose_weighted(rng, |line| ((line.score - best.score) as f64 / temperature).exp()).ok()
+    };
+    chosen.and_then(|line| line.pv.first().copied())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::Position;
+    use crate::search::MATE;
+    use rand::SeedableRng;
+
+    fn lines(position: &Position, moves: &[(&str, i32)]) -> Vec<PvLine> {
+        moves.iter().map(|(text, score)| PvLine { score: *score, pv: vec![position.parse_uci_move(text).unwrap()] }).collect()
+    }
+
+    #[test]
+    fn test_elo_maps_onto_levels() {
+        assert_eq!(skill_from_elo(0), 0);
+        assert_eq!(skill_from_elo(1550), 7);
+        assert_eq!(skill_from_elo(5000), MAX_SKILL);
+        assert_eq!(skill_from_elo(elo_from_skill(13)), 13);
+
+        let mut limits = SearchLimits { depth: Some(2), ..Default::default() };
+        let mut options = SearchOptions::default();
+        limit_search(10, &mut limits, &mut options);
+        assert_eq!((limits.depth, limits.nodes, options.multi_pv), (Some(2), Some(16000), CANDIDATES));
+    }
+
+    #[test]
+    fn test_weak_levels_vary_their_moves() {
+        let position = Position::default();
+        let candidates = lines(&position, &[("e2e4", 40), ("d2d4", 35), ("g1f3", 30), ("a2a3", -20)]);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+
+        let best = candidates[0].pv[0];
+        assert!((0..100).all(|_| choose_move(MAX_SKILL, &candidates, &mut rng) == Some(best)));
+        let picks: Vec<ChessMove> = (0..200).filter_map(|_| choose_move(0, &candidates, &mut rng)).collect();
+        assert_eq!(picks.len(), 200);
+        assert!(picks.iter().any(|chess_move| *chess_move != best));
+        // Near-equal moves get mixed up at any level, but the weak move is much rarer at the top
+        let blunder = candidates[3].pv[0];
+        let weak = picks.iter().filter(|chess_move| **chess_move == blunder).count();
+        let strong = (0..200).filter(|_| choose_move(18, &candidates, &mut rng) == Some(blunder)).count();
+        assert!(strong * 2 < weak);
+    }
+
+    #[test]
+    fn test_mates_are_not_thrown_away() {
+        let position = Position::default();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(2);
+
+        let mating = lines(&position, &[("e2e4", MATE - 3), ("d2d4", 50)]);
+        assert!((0..100).all(|_| choose_move(0, &mating, &mut rng) == Some(mating[0].pv[0])));
+        let mated = lines(&position, &[("e2e4", 10), ("f2f3", -MATE + 4)]);
+        assert!((0..100).all(|_| choose_move(0, &mated, &mut rng) == Some(mated[0].pv[0])));
+    }
+}
@@ -0,0 +1,125 @@
+use shakmaty::fen::Fen;
+use shakmaty::{CastlingMode, Chess};
+use shakmaty_syzygy::{AmbiguousWdl, Tablebase, Wdl};
+
+use crate::Team;
+use crate::position::{ChessMove, Position};
+use crate::search::MATE;
+
+// Tablebase wins score below every real mate so a mate the search can see is still preferred,
+// and the ply is taken off so the quickest way into a won ending wins out
+pub const TB_WIN: i32 = MATE - 1000;
+
+// What the tables say about the side to move
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum TablebaseResult {
+    Win(u32), // Half-moves until the next capture or pawn move that keeps the win (DTZ)
+    Draw,
+    Loss(u32),
+}
+
+pub struct Tablebases {
+    tables: Tablebase<Chess>,
+}
+
+pub fn piece_count(position: &Position) -> usize {
+    position.board.iter().flatten().filter(|piece| piece.team != Team::None).count()
+}
+
+// The Syzygy tables don't know about castling, so those positions are never probed
+fn to_shakmaty(position: &Position) -> Option<Chess> {
+    if position.castling_rights().contains(&true) {
+        return None;
+    }
+    let fen: Fen = position.to_fen().parse().ok()?;
+    fen.into_position(CastlingMode::Standard).ok()
+}
+
+impl Tablebases {
+    // Every table in the directory; subdirectories are not searched
+    pub fn open(directory: &str) -> Result<Tablebases, String> {
+        let mut tables = Tablebase::new();
+        let count = tables
+            .add_directory(directory)
+            .map_err(|error| format!("Can't read tablebases in {}: {}", directory, error))?;
+        if count == 0 {
+            return Err(format!("No Syzygy tables in {}", directory));
+        }
+        Ok(Tablebases { tables })
+    }
+
+    pub fn max_pieces(&self) -> usize {
+        self.tables.max_pieces()
+    }
+
+    fn covers(&self, position: &Position) -> bool {
+        piece_count(position) <= self.max_pieces()
+    }
+
+    // Only needs the WDL tables, but is only right straight after a capture or pawn move
+    // because it ignores the fifty move rule. Cursed wins and blessed losses count as draws.
+    pub fn probe_wdl(&self, position: &Position) -> Option<Wdl> {
+        if position.halfmove_clock != 0 || !self.covers(position) {
+            return None;
+        }
+        self.tables.probe_wdl_after_zeroing(&to_shakmaty(position)?).ok()
+    }
+
+    pub fn probe(&self, position: &Position) -> Option<TablebaseResult> {
+        if !self.covers(position) {
+            return None;
+        }
+        let chess = to_shakmaty(position)?;
+        let dtz = self.tables.probe_dtz(&chess).ok()?;
+        let plies = dtz.ignore_rounding().0.unsigned_abs();
+        Some(match AmbiguousWdl::from_dtz_and_halfmoves(dtz, position.halfmove_clock).after_zeroing() {
+            Wdl::Win => TablebaseResult::Win(plies),
+            Wdl::Loss => TablebaseResult::Loss(plies),
+            _ => TablebaseResult::Draw,
+        })
+    }
+
+    // The move that keeps the best result and makes progress towards it
+    pub fn best_move(&self, position: &Position) -> Option<(ChessMove, TablebaseResult)> {
+        if !self.covers(position) {
+            return None;
+        }
+        let (best_move, _) = self.tables.best_move(&to_shakmaty(position)?).ok()??;
+        let chess_move = position.parse_uci_move(&best_move.to_uci(CastlingMode::Standard).to_string())?;
+        Some((chess_move, self.probe(position)?))
+    }
+}
+
+// A search score for the result, `ply` moves from the root
+pub fn result_score(result: TablebaseResult, ply: usize) -> i32 {
+    match result {
+        TablebaseResult::Win(plies) => TB_WIN - ply as i32 - plies as i32,
+        TablebaseResult::Draw => 0,
+        TablebaseResult::Loss(plies) => -TB_WIN + ply as i32 + plies as i32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_positions_convert() {
+        let position = Position::from_fen("8/8/8/4k3/8/8/4P3/4K3 w - - 0 1").unwrap();
+        assert_eq!(piece_count(&position), 3);
+        assert!(to_shakmaty(&position).is_some());
+
+        // Castling rights make a position unprobeable
+        let position = Position::from_fen("4k3/8/8/8/8/8/8/4K2R w K - 0 1").unwrap();
+        assert!(to_shakmaty(&position).is_none());
+    }
+
+    #[test]
+    fn test_missing_tables() {
+        assert!(Tablebases::open("/nonexistent/syzygy").is_err());
+        let empty = std::env::temp_dir().join("chess_again_empty_syzygy");
+        std::fs::create_dir_all(&empty).unwrap();
+        assert!(Tablebases::open(empty.to_str().unwrap()).is_err());
+        std::fs::remove_dir_all(&empty).unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+use std::path::Path;
+
+use eframe::egui;
+use egui::Color32;
+
+use crate::{Piece, PieceType, Team};
+
+// Mipmaps keep the pieces smooth when they're drawn smaller than the images
+const PIECE_TEXTURE: egui::TextureOptions = egui::TextureOptions::LINEAR.with_mipmap_mode(Some(egui::TextureFilter::Linear));
+
+const TEAMS: [(Team, &str, char); 2] = [(Team::White, "white", 'w'), (Team::Black, "black", 'b')];
+const PIECE_TYPES: [(PieceType, &str, char); 6] = [
+    (PieceType::King, "king", 'K'),
+    (PieceType::Queen, "queen", 'Q'),
+    (PieceType::Rook, "rook", 'R'),
+    (PieceType::Bishop, "bishop", 'B'),
+    (PieceType::Knight, "knight", 'N'),
+    (PieceType::Pawn, "pawn", 'P'),
+];
+
+pub struct BoardTheme {
+    pub name: &'static str,
+    pub light: Color32,
+    pub dark: Color32,
+}
+
+pub const THEMES: [BoardTheme; 5] = [
+    BoardTheme { name: "Green", light: Color32::from_rgb(237, 237, 209), dark: Color32::from_rgb(117, 149, 85) },
+    BoardTheme { name: "Brown", light: Color32::from_rgb(240, 217, 181), dark: Color32::from_rgb(181, 136, 99) },
+    BoardTheme { name: "Blue", light: Color32::from_rgb(222, 227, 230), dark: Color32::from_rgb(140, 162, 173) },
+    BoardTheme { name: "Grey", light: Color32::from_rgb(232, 232, 232), dark: Color32::from_rgb(150, 150, 150) },
+    BoardTheme { name: "Purple", light: Color32::from_rgb(236, 232, 245), dark: Color32::from_rgb(136, 119, 183) },
+];
+
+// The pieces compiled into the program, or image files from a directory
+#[derive(Default)]
+pub struct PieceSet {
+    directory: Option<String>,
+    uris: Vec<String>, // One for each team and piece type, in the order of TEAMS and PIECE_TYPES
+}
+
+fn index(piece: Piece) -> Option<usize> {
+    let team = TEAMS.iter().position(|(team, _, _)| *team == piece.team)?;
+    let piece_type = PIECE_TYPES.iter().position(|(piece_type, _, _)| *piece_type == piece.piece_type)?;
+    Some(team * PIECE_TYPES.len() + piece_type)
+}
+
+impl PieceSet {
+    // Each piece is looked for as white_king.svg or wK.svg, and the same again as .png,
+    // which covers both this program's naming and the usual one
+    pub fn load(directory: &str) -> Result<PieceSet, String> {
+        let root = Path::new(directory);
+        if !root.is_dir() {
+            return Err(format!("Can't find the directory {}", directory));
+        }
+        let mut uris = vec![];
+        for (_, team_name, team_letter) in TEAMS {
+            for (_, type_name, type_letter) in PIECE_TYPES {
+                let names = [format!("{}_{}", team_name, type_name), format!("{}{}", team_letter, type_letter)];
+                let found = ["svg", "png"]
+                    .iter()
+                    .flat_map(|extension| names.iter().map(move |name| root.join(format!("{}.{}", name, extension))))
+                    .find(|path| path.is_file());
+                let Some(path) = found else {
+                    return Err(format!("No {}_{} or {}{} image (.svg or .png) in {}", team_name, type_name, team_letter, type_letter, directory));
+                };
+                let path = path.canonicalize().map_err(|error| format!("Can't read {}: {}", path.display(), error))?;
+                uris.push(format!("file://{}", path.display()));
+            }
+        }
+        Ok(PieceSet { directory: Some(directory.to_string()), uris })
+    }
+
+    // None for the built-in pieces
+    pub fn directory(&self) -> Option<&str> {
+        self.directory.as_deref()
+    }
+
+    fn image(&self, piece: Piece) -> egui::ImageSource<'static> {
+        if let Some(uri) = index(piece).and_then(|index| self.uris.get(index)) {
+            return egui::ImageSource::Uri(uri.clone().into());
+        }
+        match (piece.team, piece.piece_type) {
+            (Team::Black, PieceType::Rook) => egui::include_image!("../assets/black_rook.png"),
+            (Team::Black, PieceType::Knight) => egui::include_image!("../assets/black_knight.png"),
+            (Team::Black, PieceType::Bishop) => egui::include_image!("../assets/black_bishop.png"),
+            (Team::Black, PieceType::Queen) => egui::include_image!("../assets/black_queen.png"),
+            (Team::Black, PieceType::King) => egui::include_image!("../assets/black_king.png"),
+            (Team::Black, PieceType::Pawn) => egui::include_image!("../assets/black_pawn.png"),
+            (Team::White, PieceType::Rook) => egui::include_image!("../assets/white_rook.png"),
+            (Team::White, PieceType::Knight) => egui::include_image!("../assets/white_knight.png"),
+            (Team::White, PieceType::Bishop) => egui::include_image!("../assets/white_bishop.png"),
+            (Team::White, PieceType::Queen) => egui::include_image!("../assets/white_queen.png"),
+            (Team::White, PieceType::King) => egui::include_image!("../assets/white_king.png"),
+            (Team::White, PieceType::Pawn) => egui::include_image!("../assets/white_pawn.png"),
+            _ => egui::include_image!("../assets/blank.png"),
+        }
+    }
+
+    // SVG pieces are drawn at the size asked for, so they stay sharp at any scale
+    pub fn picture(&self, piece: Piece, size: egui::Vec2) -> egui::Image<'static> {
+        egui::Image::new(self.image(piece)).fit_to_exact_size(size).texture_options(PIECE_TEXTURE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create_piece;
+
+    #[test]
+    fn test_piece_sets_need_every_piece() {
+        let directory = std::env::temp_dir().join("chess_again_test_pieces");
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        for (_, _, team) in TEAMS {
+            for (_, _, piece_type) in PIECE_TYPES {
+                std::fs::write(directory.join(format!("{}{}.svg", team, piece_type)), "").unwrap();
+            }
+        }
+        std::fs::write(directory.join("white_king.png"), "").unwrap();
+        let path = directory.to_str().unwrap();
+
+        let set = PieceSet::load(path).unwrap();
+        assert_eq!(set.directory(), Some(path));
+        // SVGs win over PNGs
+        let king = create_piece(PieceType::King, Team::White);
+        assert!(matches!(set.image(king), egui::ImageSource::Uri(uri) if uri.ends_with("wK.svg")));
+
+        std::fs::remove_file(directory.join("bN.svg")).unwrap();
+        let error = PieceSet::load(path).err().unwrap();
+        assert!(error.starts_with("No black_knight or bN image"));
+        assert!(PieceSet::load(directory.join("missing").to_str().unwrap()).is_err());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
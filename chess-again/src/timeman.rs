@@ -0,0 +1,155 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::position::ChessMove;
+use crate::search::SearchLimits;
+
+// Moves we assume are still to be played when the time control doesn't say
+const DEFAULT_MOVES_TO_GO: u64 = 30;
+
+pub struct TimeManager {
+    start: Instant,
+    soft_limit: Option<Duration>, // Don't start another iteration after this
+    hard_limit: Option<Duration>, // Abort the search mid-iteration after this
+    previous_best: Option<ChessMove>,
+    previous_score: Option<i32>,
+    stable_iterations: u32,
+    ponder: Option<Arc<AtomicBool>>,
+}
+
+impl TimeManager {
+    pub fn new(limits: &SearchLimits) -> TimeManager {
+        let (soft_limit, hard_limit) = if let Some(movetime) = limits.movetime {
+            let limit = Duration::from_millis(movetime.saturating_sub(limits.move_overhead).max(1));
+            (Some(limit), Some(limit))
+        } else if let Some(time_left) = limits.time_left {
+            // Whatever happens we never touch the last `move_overhead` milliseconds of the clock
+            let usable = time_left.saturating_sub(limits.move_overhead).max(1);
+            let moves_to_go = limits.moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).clamp(1, 50);
+
+            let optimum = usable / moves_to_go + limits.increment * 3 / 4;
+            let hard = (optimum * 4).min(usable * 4 / 5).max(1);
+            let soft = (optimum * 3 / 5).min(hard);
+            (Some(Duration::from_millis(soft)), Some(Duration::from_millis(hard)))
+        } else {
+            (None, None)
+        };
+
+        TimeManager {
+            start: Instant::now(),
+            soft_limit,
+            hard_limit,
+            previous_best: None,
+            previous_score: None,
+            stable_iterations: 0,
+            ponder: limits.ponder.clone(),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    // Time spent pondering still counts once the expected move comes, so a ponder hit
+    // usually means the move is ready straight away
+    fn pondering(&self) -> bool {
+        self.ponder.as_ref().is_some_and(|ponder| ponder.load(Ordering::Relaxed))
+    }
+
+    pub fn hard_limit_reached(&self) -> bool {
+        !self.pondering() && self.hard_limit.is_some_and(|limit| self.elapsed() >= limit)
+    }
+
+    // Called after every completed iteration. A best move that keeps coming back lets us
+    // stop early, while a falling score buys more time to find something better.
+    pub fn should_stop(&mut self, best_move: ChessMove, score: i32) -> bool {
+        if self.previous_best == Some(best_move) {
+            self.stable_iterations += 1;
+        } else {
+            self.stable_iterations = 0;
+        }
+        let score_drop = self.previous_score.map_or(0, |previous| (previous - score).clamp(0, 200));
+        self.previous_best = Some(best_move);
+        self.previous_score = Some(score);
+
+        let Some(soft_limit) = self.soft_limit else {
+            return false;
+        };
+        if self.pondering() {
+            return false;
+        }
+
+        let stability = 1.4 - 0.1 * self.stable_iterations.min(6) as f64;
+        let falling = 1.0 + score_drop as f64 / 200.0;
+        let mut limit = soft_limit.mul_f64(stability * falling);
+        if let Some(hard_limit) = self.hard_limit {
+            limit = limit.min(hard_limit);
+        }
+
+        self.elapsed() >= limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits_never_exceed_the_clock() {
+        for (time_left, increment, moves_to_go) in [(100, 0, None), (5000, 100, Some(1)), (60000, 0, Some(40)), (20, 1000, None)] {
+            let limits = SearchLimits {
+                time_left: Some(time_left),
+                increment,
+                moves_to_go,
+                ..Default::default()
+            };
+            let time_manager = TimeManager::new(&limits);
+            let hard = time_manager.hard_limit.unwrap().as_millis() as u64;
+            assert!(hard <= time_left.saturating_sub(limits.move_overhead).max(1));
+            assert!(time_manager.soft_limit.unwrap() <= time_manager.hard_limit.unwrap());
+        }
+    }
+
+    #[test]
+    fn test_stable_best_move_stops_earlier() {
+        let limits = SearchLimits {
+            movetime: Some(10000),
+            ..Default::default()
+        };
+        let mut time_manager = TimeManager::new(&limits);
+        let chess_move = ChessMove::new([4, 1], [4, 3]);
+        time_manager.soft_limit = Some(Duration::from_millis(100));
+        time_manager.start = Instant::now() - Duration::from_millis(120);
+
+        // Just changed its mind, so it gets up to 140ms
+        assert!(!time_manager.should_stop(chess_move, 20));
+        for _ in 0..5 {
+            time_manager.should_stop(chess_move, 20);
+        }
+        assert!(time_manager.should_stop(chess_move, 20));
+
+        // A big drop in score pushes the limit back out
+        assert!(!time_manager.should_stop(chess_move, -180));
+    }
+
+    #[test]
+    fn test_pondering_holds_the_clock() {
+        let ponder = Arc::new(AtomicBool::new(true));
+        let limits = SearchLimits {
+            movetime: Some(50),
+            move_overhead: 0,
+            ponder: Some(ponder.clone()),
+            ..Default::default()
+        };
+        let mut time_manager = TimeManager::new(&limits);
+        time_manager.start = Instant::now() - Duration::from_millis(100);
+        let chess_move = ChessMove::new([4, 1], [4, 3]);
+        assert!(!time_manager.hard_limit_reached());
+        assert!(!time_manager.should_stop(chess_move, 0));
+
+        ponder.store(false, Ordering::Relaxed);
+        assert!(time_manager.hard_limit_reached());
+        assert!(time_manager.should_stop(chess_move, 0));
+    }
+}
@@ -0,0 +1,156 @@
+use std::sync::Arc;
+
+use eframe::egui;
+
+use crate::Team;
+use crate::endgame::{Dtm, EndgameTables};
+use crate::position::{ChessMove, Position};
+use crate::san::move_to_san;
+
+pub enum TrainerAction {
+    Setup(Position),
+    Play(ChessMove),
+}
+
+// You play White from a random won position out of one of the generated tables, and the
+// computer defends with whatever holds out longest
+pub struct Trainer {
+    directory: String,
+    tables: Option<Arc<EndgameTables>>,
+    error: Option<String>,
+    ending: String,
+    min_moves: u32,
+    active: bool,
+    expected: Option<Dtm>, // What the tables said before your last move
+    moves_played: u32,
+    feedback: String,
+}
+
+impl Default for Trainer {
+    fn default() -> Self {
+        Self {
+            directory: "tables".to_string(),
+            tables: None,
+            error: None,
+            ending: String::new(),
+            min_moves: 10,
+            active: false,
+            expected: None,
+            moves_played: 0,
+            feedback: String::new(),
+        }
+    }
+}
+
+fn describe(dtm: Dtm) -> String {
+    match dtm {
+        Dtm::Win(moves) => format!("Mate in {}", moves),
+        Dtm::Draw => "Draw".to_string(),
+        Dtm::Loss(moves) => format!("Mated in {}", moves),
+    }
+}
+
+impl Trainer {
+    pub fn tables(&self) -> Option<Arc<EndgameTables>> {
+        self.tables.clone()
+    }
+
+    fn load(&mut self) {
+        match EndgameTables::load(self.directory.trim()) {
+            Ok(tables) => {
+                if !tables.names().contains(&self.ending) {
+                    self.ending = tables.names().first().cloned().unwrap_or_default();
+                }
+                self.tables = Some(Arc::new(tables));
+                self.error = None;
+            }
+            Err(error) => {
+                self.tables = None;
+                self.error = Some(error);
+            }
+        }
+    }
+
+    // Judges the move you just made against the best one
+    fn judge(&mut self, position: &Position, tables: &EndgameTables) {
+        let now = tables.probe(position);
+        self.feedback = match (self.expected, now) {
+            (Some(Dtm::Win(before)), Some(Dtm::Loss(after))) if after + 1 == before => "Best move".to_string(),
+            (Some(Dtm::Win(before)), Some(Dtm::Loss(after))) => {
+                format!("Still winning, but mate is now {} moves away instead of {}", after, before - 1)
+            }
+            (Some(Dtm::Win(_)), Some(_)) => "That lets the win slip away".to_string(),
+            _ => String::new(),
+        };
+        self.moves_played += 1;
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, position: &Position) -> Option<TrainerAction> {
+        ui.heading("Endgame trainer");
+        ui.horizontal(|ui| {
+            ui.label("Tables:");
+            ui.text_edit_singleline(&mut self.directory);
+            if ui.button("Load").clicked() {
+                self.load();
+            }
+        });
+        if let Some(error) = &self.error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+        let tables = self.tables.clone()?;
+
+        let mut action = None;
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_salt("ending").selected_text(self.ending.as_str()).show_ui(ui, |ui| {
+                for name in tables.names() {
+                    ui.selectable_value(&mut self.ending, name.clone(), name);
+                }
+            });
+            ui.label("Mate in at least");
+            ui.add(egui::DragValue::new(&mut self.min_moves).range(1..=40));
+            if ui.button("New position").clicked()
+                && let Some(start) = tables.random_position(&self.ending, self.min_moves)
+            {
+                self.active = true;
+                self.expected = None;
+                self.moves_played = 0;
+                self.feedback.clear();
+                action = Some(TrainerAction::Setup(start));
+            }
+        });
+        if action.is_some() {
+            return action;
+        }
+
+        if self.active && position.legal_moves().is_empty() {
+            self.active = false;
+            self.feedback = if position.in_check() && position.current_team == Team::Black {
+                format!("Checkmate! Solved in {} moves", self.moves_played + 1)
+            } else {
+                "Stalemate, the win got away".to_string()
+            };
+        }
+        if !self.active {
+            ui.label(&self.feedback);
+            return None;
+        }
+
+        if position.current_team == Team::Black {
+            // Your move just went in, so answer it
+            self.judge(position, &tables);
+            let (defence, _) = tables.best_move(position)?;
+            self.feedback.push_str(&format!("\nComputer plays {}", move_to_san(position, defence)));
+            return Some(TrainerAction::Play(defence));
+        }
+
+        let dtm = tables.probe(position);
+        self.expected = dtm;
+        if let Some(dtm) = dtm {
+            ui.label(egui::RichText::new(describe(dtm)).strong());
+        }
+        if !self.feedback.is_empty() {
+            ui.label(&self.feedback);
+        }
+        None
+    }
+}
@@ -0,0 +1,151 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::PieceType;
+use crate::position::ChessMove;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Bound {
+    Exact,
+    Lower, // Score is at least this (failed high)
+    Upper, // Score is at most this (failed low)
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct TableEntry {
+    pub best_move: Option<ChessMove>,
+    pub score: i32,
+    pub depth: i32,
+    pub bound: Bound,
+}
+
+// Every slot is a pair of words: the key xor'd with the data, and the data. Threads read and
+// write without locking, and a slot torn by two writers simply fails the key check.
+pub struct TranspositionTable {
+    slots: Vec<[AtomicU64; 2]>,
+}
+
+fn encode_move(chess_move: Option<ChessMove>) -> u64 {
+    let Some(chess_move) = chess_move else {
+        return 0;
+    };
+    let promotion = match chess_move.promotion {
+        PieceType::Knight => 1,
+        PieceType::Bishop => 2,
+        PieceType::Rook => 3,
+        PieceType::Queen => 4,
+        _ => 0,
+    };
+    let from = chess_move.from[1] * 8 + chess_move.from[0];
+    let to = chess_move.to[1] * 8 + chess_move.to[0];
+    (from as u64) | (to as u64) << 6 | promotion << 12
+}
+
+fn decode_move(bits: u64) -> Option<ChessMove> {
+    if bits == 0 {
+        return None;
+    }
+    let from = (bits & 63) as isize;
+    let to = (bits >> 6 & 63) as isize;
+    let promotion = match bits >> 12 & 7 {
+        1 => PieceType::Knight,
+        2 => PieceType::Bishop,
+        3 => PieceType::Rook,
+        4 => PieceType::Queen,
+        _ => PieceType::None,
+    };
+    Some(ChessMove {
+        from: [from % 8, from / 8],
+        to: [to % 8, to / 8],
+        promotion,
+    })
+}
+
+impl TranspositionTable {
+    pub fn new(megabytes: usize) -> TranspositionTable {
+        let count = (megabytes.max(1) * 1024 * 1024 / std::mem::size_of::<[AtomicU64; 2]>()).max(1);
+        TranspositionTable {
+            slots: (0..count).map(|_| [AtomicU64::new(0), AtomicU64::new(0)]).collect(),
+        }
+    }
+
+    pub fn clear(&self) {
+        for slot in &self.slots {
+            slot[0].store(0, Ordering::Relaxed);
+            slot[1].store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn slot(&self, key: u64) -> &[AtomicU64; 2] {
+        &self.slots[((key as u128 * self.slots.len() as u128) >> 64) as usize]
+    }
+
+    pub fn probe(&self, key: u64) -> Option<TableEntry> {
+        let slot = self.slot(key);
+        let check = slot[0].load(Ordering::Relaxed);
+        let data = slot[1].load(Ordering::Relaxed);
+        if data == 0 || check ^ data != key {
+            return None;
+        }
+
+        Some(TableEntry {
+            best_move: decode_move(data & 0xFFFF),
+            score: (data >> 16 & 0xFFFF) as u16 as i16 as i32,
+            depth: (data >> 32 & 0xFF) as i32,
+            bound: match data >> 40 & 3 {
+                1 => Bound::Exact,
+                2 => Bound::Lower,
+                _ => Bound::Upper,
+            },
+        })
+    }
+
+    pub fn store(&self, key: u64, entry: TableEntry) {
+        let slot = self.slot(key);
+
+        // Keep a deeper result for the same position unless this one is exact
+        if let Some(existing) = self.probe(key)
+            && existing.depth > entry.depth
+            && entry.bound != Bound::Exact
+        {
+            return;
+        }
+
+        let bound = match entry.bound {
+            Bound::Exact => 1,
+            Bound::Lower => 2,
+            Bound::Upper => 3,
+        };
+        let data = encode_move(entry.best_move)
+            | (entry.score as i16 as u16 as u64) << 16
+            | (entry.depth.clamp(0, 255) as u64) << 32
+            | bound << 40;
+        slot[0].store(key ^ data, Ordering::Relaxed);
+        slot[1].store(data, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_and_probe() {
+        let table = TranspositionTable::new(1);
+        let chess_move = ChessMove {
+            from: [4, 6],
+            to: [4, 7],
+            promotion: PieceType::Knight,
+        };
+        table.store(42, TableEntry { best_move: Some(chess_move), score: -29990, depth: 7, bound: Bound::Lower });
+
+        let entry = table.probe(42).unwrap();
+        assert_eq!(entry.best_move, Some(chess_move));
+        assert_eq!(entry.score, -29990);
+        assert_eq!(entry.depth, 7);
+        assert_eq!(entry.bound, Bound::Lower);
+        assert!(table.probe(43).is_none());
+
+        table.clear();
+        assert!(table.probe(42).is_none());
+    }
+}
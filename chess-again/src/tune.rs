@@ -0,0 +1,206 @@
+use std::time::Instant;
+
+use crate::evaluate::{EvalParams, PARAM_COUNT, features};
+use crate::position::Position;
+
+const DEFAULT_EPOCHS: usize = 500;
+const DEFAULT_LEARNING_RATE: f64 = 1.0;
+const REPORT_INTERVAL: usize = 25;
+
+// A position with its parameter counts and how the game ended for White (1, 0.5 or 0)
+struct Sample {
+    features: Vec<(u16, i8)>,
+    result: f64,
+}
+
+// The result can be written as 1-0, 0-1 or 1/2-1/2, or as [1.0], [0.5] or [0.0], anywhere
+// after the board; quotes and semicolons around it (as in EPD c9 fields) are ignored
+fn parse_result(text: &str) -> Option<f64> {
+    text.split_whitespace().skip(4).find_map(|token| {
+        match token.trim_matches(|c: char| c == '"' || c == ';' || c == '[' || c == ']') {
+            "1-0" | "1.0" => Some(1.0),
+            "0-1" | "0.0" => Some(0.0),
+            "1/2-1/2" | "0.5" => Some(0.5),
+            _ => None,
+        }
+    })
+}
+
+fn parse_sample(line: &str) -> Option<Sample> {
+    let result = parse_result(line)?;
+    let fen: Vec<&str> = line.split_whitespace().take(4).collect();
+    let position = Position::from_fen(&fen.join(" ")).ok()?;
+    Some(Sample { features: features(&position), result })
+}
+
+fn sigmoid(score: f64, k: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * score / 400.0))
+}
+
+fn score(sample: &Sample, params: &[f64]) -> f64 {
+    sample.features.iter().map(|(index, count)| params[*index as usize] * *count as f64).sum()
+}
+
+// Mean squared difference between the results and the predicted ones
+fn loss(samples: &[Sample], params: &[f64], k: f64) -> f64 {
+    let total: f64 = in_parallel(samples, |chunk| {
+        chunk.iter().map(|sample| (sample.result - sigmoid(score(sample, params), k)).powi(2)).sum::<f64>()
+    })
+    .into_iter()
+    .sum();
+    total / samples.len() as f64
+}
+
+fn gradient(samples: &[Sample], params: &[f64], k: f64) -> Vec<f64> {
+    let partials = in_parallel(samples, |chunk| {
+        let mut gradient = vec![0.0; PARAM_COUNT];
+        for sample in chunk {
+            let predicted = sigmoid(score(sample, params), k);
+            let slope = (predicted - sample.result) * predicted * (1.0 - predicted);
+            for (index, count) in &sample.features {
+                gradient[*index as usize] += slope * *count as f64;
+            }
+        }
+        gradient
+    });
+    // The constant factors only scale the step, which the optimiser takes out anyway
+    let mut total = vec![0.0; PARAM_COUNT];
+    for partial in partials {
+        for (sum, value) in total.iter_mut().zip(partial) {
+            *sum += value;
+        }
+    }
+    total
+}
+
+fn in_parallel<T: Send>(samples: &[Sample], work: impl Fn(&[Sample]) -> T + Sync) -> Vec<T> {
+    let threads = std::thread::available_parallelism().map_or(1, |count| count.get());
+    let chunk_size = samples.len().div_ceil(threads).max(1);
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = samples.chunks(chunk_size).map(|chunk| scope.spawn(|| work(chunk))).collect();
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+    })
+}
+
+// The scaling between centipawns and winning chances that suits the starting values best,
+// found by narrowing a scan
+fn fit_k(samples: &[Sample], params: &[f64]) -> f64 {
+    let (mut best, mut step) = (1.0, 0.5);
+    for _ in 0..6 {
+        let candidates = (-5..=5).map(|i| best + i as f64 * step).filter(|k| *k > 0.0);
+        best = candidates.min_by(|a, b| loss(samples, params, *a).total_cmp(&loss(samples, params, *b))).unwrap_or(best);
+        step /= 5.0;
+    }
+    best
+}
+
+fn round(params: &[f64]) -> EvalParams {
+    let rounded: Vec<i32> = params.iter().map(|value| value.round() as i32).collect();
+    EvalParams::from_vec(&rounded)
+}
+
+// Gradient descent with Adam, starting from `start`; `report` gets the epoch and loss now and then
+fn tune(samples: &[Sample], start: &EvalParams, epochs: usize, learning_rate: f64, mut report: impl FnMut(usize, f64, &[f64])) -> EvalParams {
+    let mut params: Vec<f64> = start.to_vec().into_iter().map(f64::from).collect();
+    let k = fit_k(samples, &params);
+    println!("K = {:.3}, starting loss {:.6}", k, loss(samples, &params, k));
+
+    let (beta1, beta2) = (0.9, 0.999);
+    let mut momentum = vec![0.0; PARAM_COUNT];
+    let mut velocity = vec![0.0; PARAM_COUNT];
+    for epoch in 1..=epochs {
+        let gradient = gradient(samples, &params, k);
+        for i in 0..PARAM_COUNT {
+            momentum[i] = beta1 * momentum[i] + (1.0 - beta1) * gradient[i];
+            velocity[i] = beta2 * velocity[i] + (1.0 - beta2) * gradient[i] * gradient[i];
+            let corrected_momentum = momentum[i] / (1.0 - beta1.powi(epoch as i32));
+            let corrected_velocity = velocity[i] / (1.0 - beta2.powi(epoch as i32));
+            params[i] -= learning_rate * corrected_momentum / (corrected_velocity.sqrt() + 1e-8);
+        }
+        if epoch % REPORT_INTERVAL == 0 || epoch == epochs {
+            report(epoch, loss(samples, &params, k), &params);
+        }
+    }
+    round(&params)
+}
+
+// tune <positions> <output> [epochs] [learning rate]
+pub fn run(args: &[String]) {
+    let (Some(input), Some(output)) = (args.first(), args.get(1)) else {
+        println!("usage: tune <positions.epd> <params.txt> [epochs] [learning rate]");
+        return;
+    };
+    let epochs = args.get(2).and_then(|arg| arg.parse().ok()).unwrap_or(DEFAULT_EPOCHS);
+    let learning_rate = args.get(3).and_then(|arg| arg.parse().ok()).unwrap_or(DEFAULT_LEARNING_RATE);
+
+    let text = match std::fs::read_to_string(input) {
+        Ok(text) => text,
+        Err(error) => {
+            println!("Can't read {}: {}", input, error);
+            return;
+        }
+    };
+    let samples: Vec<Sample> = text.lines().filter_map(parse_sample).collect();
+    let skipped = text.lines().filter(|line| !line.trim().is_empty()).count() - samples.len();
+    println!("{} positions, {} lines skipped", samples.len(), skipped);
+    if samples.is_empty() {
+        return;
+    }
+
+    // Starts from whatever the engine uses now, so an earlier run can be carried on
+    let start = crate::evaluate::params().clone();
+    let started = Instant::now();
+    let tuned = tune(&samples, &start, epochs, learning_rate, |epoch, loss, params| {
+        println!("epoch {:5}  loss {:.6}  {:.0}s", epoch, loss, started.elapsed().as_secs_f64());
+        // Saved as it goes so a long run can be stopped early
+        if let Err(error) = round(params).save(output) {
+            println!("{}", error);
+        }
+    });
+    match tuned.save(output) {
+        Ok(()) => println!("Wrote {} (material {:?})", output, tuned.material),
+        Err(error) => println!("{}", error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::position::parse_square;
+    use crate::{PieceType, Team, create_piece};
+
+    #[test]
+    fn test_parse_results() {
+        assert_eq!(parse_result("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - c9 \"1/2-1/2\";"), Some(0.5));
+        assert_eq!(parse_result("8/8/8/8/8/8/8/K1k5 w - - 0 1 [0.0]"), Some(0.0));
+        assert_eq!(parse_result("8/8/8/8/8/8/8/K1k5 w - - 1-0"), Some(1.0));
+        assert_eq!(parse_result("8/8/8/8/8/8/8/K1k5 w - - 0 1"), None);
+        assert!(parse_sample("not a fen at all 1-0").is_none());
+    }
+
+    #[test]
+    fn test_tuning_fits_the_data() {
+        // White wins whenever it has the extra knight and loses whenever Black has it, so a
+        // knight ought to end up worth more than it started at
+        let mut lines = vec![];
+        for square in ["b3", "c4", "d5", "e4", "f3", "g5"] {
+            for (team, result) in [(Team::White, "1-0"), (Team::Black, "0-1")] {
+                let mut position = Position::from_fen("4k3/pppp4/8/8/8/8/PPPP4/4K3 w - - 0 1").unwrap();
+                let [x, y] = parse_square(square).unwrap();
+                let y = if team == Team::White { y } else { 7 - y };
+                position.board[x as usize][y as usize] = create_piece(PieceType::Knight, team);
+                lines.push(format!("{} {}", position.to_fen(), result));
+            }
+        }
+        let samples: Vec<Sample> = lines.iter().filter_map(|line| parse_sample(line)).collect();
+        assert_eq!(samples.len(), 12);
+
+        let start = EvalParams::default();
+        let start_values: Vec<f64> = start.to_vec().into_iter().map(f64::from).collect();
+        let k = fit_k(&samples, &start_values);
+        let tuned = tune(&samples, &start, 50, 2.0, |_, _, _| {});
+        let tuned_values: Vec<f64> = tuned.to_vec().into_iter().map(f64::from).collect();
+        assert!(loss(&samples, &tuned_values, k) < loss(&samples, &start_values, k));
+        assert!(tuned.material[1] > start.material[1]);
+    }
+}
@@ -0,0 +1,415 @@
+use std::io::BufRead;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+use crate::Team;
+use crate::evaluate::evaluate;
+use crate::book::{Book, BookSelection};
+use crate::endgame::EndgameTables;
+use crate::mate::{self, MateResult};
+use crate::nnue::Network;
+use crate::position::{ChessMove, Position, START_FEN};
+use crate::san::format_line;
+use crate::search::{SearchLimits, SearchOptions, SearchResult, score_to_uci, search};
+use crate::skill::{self, MAX_ELO, MAX_SKILL, MIN_ELO};
+use crate::syzygy::Tablebases;
+use crate::tt::TranspositionTable;
+use crate::zobrist;
+
+const DEFAULT_HASH_MB: usize = 16;
+const MAX_THREADS: usize = 256;
+const MAX_MULTI_PV: usize = 64;
+
+fn print_bestmove(best_move: Option<ChessMove>, expected_reply: Option<ChessMove>) {
+    match (best_move, expected_reply) {
+        (Some(best_move), Some(reply)) => println!("bestmove {} ponder {}", best_move.to_uci(), reply.to_uci()),
+        (Some(best_move), None) => println!("bestmove {}", best_move.to_uci()),
+        (None, _) => println!("bestmove 0000"),
+    }
+}
+
+fn print_info(result: &SearchResult) {
+    let pv: Vec<String> = result.pv.iter().map(|chess_move| chess_move.to_uci()).collect();
+    println!("info depth {} score {} nodes {} pv {}", result.depth, score_to_uci(result.score), result.nodes, pv.join(" "));
+}
+
+// go mate <moves>: proves a mate with the mate solver, or plays the best move a normal
+// search finds if there isn't one
+fn mate_search(position: &Position, moves: u32, options: &SearchOptions, tt: &TranspositionTable, stop: Arc<AtomicBool>) {
+    let started = Instant::now();
+    let (result, nodes) = mate::solve(position, moves, &stop, |moves, nodes| {
+        println!("info depth {} nodes {} time {}", 2 * moves, nodes, started.elapsed().as_millis());
+    });
+    match result {
+        MateResult::Mate(line) => {
+            let pv: Vec<String> = line.iter().map(|chess_move| chess_move.to_uci()).collect();
+            println!(
+                "info depth {} score mate {} nodes {} time {} pv {}",
+                line.len(),
+                line.len().div_ceil(2),
+                nodes,
+                started.elapsed().as_millis(),
+                pv.join(" ")
+            );
+            println!("info string {}", format_line(position, &line));
+            print_bestmove(line.first().copied(), line.get(1).copied());
+        }
+        MateResult::NoMate | MateResult::Stopped => {
+            if matches!(result, MateResult::NoMate) {
+                println!("info string no forced mate in {}", moves);
+            }
+            let limits = SearchLimits { depth: Some(2 * moves), ..Default::default() };
+            let result = search(position, &limits, options, tt, stop, |_| {});
+            print_info(&result);
+            print_bestmove(result.best_move, result.pv.get(1).copied());
+        }
+    }
+}
+
+struct Uci {
+    position: Position,
+    history: Vec<u64>, // Keys of the positions before it, so the search knows about repetitions
+    move_overhead: u64,
+    options: SearchOptions,
+    tt: Arc<TranspositionTable>,
+    stop: Arc<AtomicBool>,
+    ponder: Arc<AtomicBool>, // Cleared by ponderhit
+    search_thread: Option<JoinHandle<()>>,
+    own_book: bool,
+    book: Option<Book>,
+    book_selection: BookSelection,
+    use_nnue: bool,
+    network: Option<Arc<Network>>,
+    skill_level: u32,
+    limit_strength: bool,
+    elo: u32,
+}
+
+impl Uci {
+    // UCI_LimitStrength takes over from Skill Level while it's on
+    fn skill(&self) -> u32 {
+        if self.limit_strength { skill::skill_from_elo(self.elo) } else { self.skill_level }
+    }
+
+    fn stop_search(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.search_thread.take() {
+            handle.join().unwrap();
+        }
+    }
+
+    fn set_option(&mut self, args: &[&str]) {
+        // setoption name <name...> value <value>
+        let value_index = args.iter().position(|arg| *arg == "value").unwrap_or(args.len());
+        let name = args.get(1..value_index).unwrap_or(&[]).join(" ");
+        let value = args.get(value_index + 1..).unwrap_or(&[]).join(" ");
+
+        match name.to_lowercase().as_str() {
+            "move overhead" => {
+                if let Ok(overhead) = value.parse() {
+                    self.move_overhead = overhead;
+                }
+            }
+            "threads" => {
+                if let Ok(threads) = value.parse::<usize>() {
+                    self.options.threads = threads.clamp(1, MAX_THREADS);
+                }
+            }
+            "multipv" => {
+                if let Ok(multi_pv) = value.parse::<usize>() {
+                    self.options.multi_pv = multi_pv.clamp(1, MAX_MULTI_PV);
+                }
+            }
+            "hash" => {
+                if let Ok(megabytes) = value.parse() {
+                    self.tt = Arc::new(TranspositionTable::new(megabytes));
+                }
+            }
+            "syzygypath" => {
+                self.options.tablebases = None;
+                if value.is_empty() || value == "<empty>" {
+                    return;
+                }
+                match Tablebases::open(&value) {
+                    Ok(tablebases) => {
+                        println!("info string found tablebases for up to {} pieces", tablebases.max_pieces());
+                        self.options.tablebases = Some(Arc::new(tablebases));
+                    }
+                    Err(error) => println!("info string {}", error),
+                }
+            }
+            "endgamepath" => {
+                self.options.endgame_tables = None;
+                if value.is_empty() || value == "<empty>" {
+                    return;
+                }
+                match EndgameTables::load(&value) {
+                    Ok(endgame_tables) => {
+                        println!("info string loaded endgame tables {}", endgame_tables.names().join(" "));
+                        self.options.endgame_tables = Some(Arc::new(endgame_tables));
+                    }
+                    Err(error) => println!("info string {}", error),
+                }
+            }
+            "usennue" => {
+                self.use_nnue = value == "true";
+                if self.use_nnue && self.network.is_none() {
+                    println!("info string no network loaded, set EvalFile first");
+                }
+            }
+            "evalfile" => {
+                self.network = None;
+                if value.is_empty() || value == "<empty>" {
+                    return;
+                }
+                match Network::load(&value) {
+                    Ok(network) => self.network = Some(Arc::new(network)),
+                    Err(error) => println!("info string {}", error),
+                }
+            }
+            "skill level" => {
+                if let Ok(level) = value.parse::<u32>() {
+                    self.skill_level = level.min(MAX_SKILL);
+                }
+            }
+            "uci_limitstrength" => self.limit_strength = value == "true",
+            "uci_elo" => {
+                if let Ok(elo) = value.parse::<u32>() {
+                    self.elo = elo.clamp(MIN_ELO, MAX_ELO);
+                }
+            }
+            "ponder" => {} // Only tells us the GUI may send go ponder
+            "ownbook" => self.own_book = value == "true",
+            "bookfile" => {
+                if value.is_empty() || value == "<empty>" {
+                    self.book = None;
+                    return;
+                }
+                self.book = match Book::load(&value) {
+                    Ok(book) => Some(book),
+                    Err(error) => {
+                        println!("info string {}", error);
+                        None
+                    }
+                };
+            }
+            "bookselection" => {
+                self.book_selection = match value.to_lowercase().as_str() {
+                    "best" => BookSelection::BestWeight,
+                    _ => BookSelection::WeightedRandom,
+                };
+            }
+            _ => println!("info string unknown option {}", name),
+        }
+    }
+
+    fn set_position(&mut self, args: &[&str]) {
+        let moves_index = args.iter().position(|arg| *arg == "moves").unwrap_or(args.len());
+        let fen = match args.first() {
+            Some(&"startpos") => START_FEN.to_string(),
+            Some(&"fen") => args[1..moves_index].join(" "),
+            _ => return,
+        };
+
+        let mut position = match Position::from_fen(&fen) {
+            Ok(position) => position,
+            Err(error) => {
+                println!("info string {}", error);
+                return;
+            }
+        };
+        let mut history = vec![];
+        for text in args.iter().skip(moves_index + 1) {
+            match position.parse_uci_move(text) {
+                Some(chess_move) => {
+                    history.push(zobrist::hash(&position));
+                    position = position.make_move(chess_move);
+                }
+                None => {
+                    println!("info string illegal move {}", text);
+                    break;
+                }
+            }
+        }
+        self.position = position;
+        self.history = history;
+    }
+
+    fn go(&mut self, args: &[&str]) {
+        self.stop_search();
+        let pondering = args.contains(&"ponder");
+        let infinite = args.contains(&"infinite");
+
+        // A book move would have to be sent before the opponent has moved, or before the
+        // GUI says stop
+        if !pondering
+            && !infinite
+            && self.own_book
+            && let Some(book) = &self.book
+            && let Some(book_move) = book.pick(&self.position, self.book_selection)
+        {
+            println!("info string book move");
+            println!("bestmove {}", book_move.to_uci());
+            return;
+        }
+
+        let mut limits = SearchLimits {
+            move_overhead: self.move_overhead,
+            infinite,
+            history: self.history.clone(),
+            ..Default::default()
+        };
+        if pondering {
+            self.ponder = Arc::new(AtomicBool::new(true));
+            limits.ponder = Some(self.ponder.clone());
+        }
+        let white = self.position.current_team == Team::White;
+        let mut mate_moves = None;
+        let mut i = 0;
+        while i < args.len() {
+            let value = args.get(i + 1).and_then(|value| value.parse::<u64>().ok());
+            match args[i] {
+                "wtime" if white => limits.time_left = value,
+                "btime" if !white => limits.time_left = value,
+                "winc" if white => limits.increment = value.unwrap_or(0),
+                "binc" if !white => limits.increment = value.unwrap_or(0),
+                "movestogo" => limits.moves_to_go = value,
+                "movetime" => limits.movetime = value,
+                "depth" => limits.depth = value.map(|depth| depth as u32),
+                "nodes" => limits.nodes = value,
+                "mate" => mate_moves = value.map(|moves| moves.max(1) as u32),
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            }
+            i += 2;
+        }
+
+        self.stop = Arc::new(AtomicBool::new(false));
+        let stop = self.stop.clone();
+        let ponder = self.ponder.clone();
+        let position = self.position;
+        let mut options = self.options.clone();
+        options.nnue = if self.use_nnue { self.network.clone() } else { None };
+        let skill = self.skill();
+        skill::limit_search(skill, &mut limits, &mut options);
+        let tt = self.tt.clone();
+        if let Some(moves) = mate_moves {
+            self.search_thread = Some(std::thread::spawn(move || mate_search(&position, moves, &options, &tt, stop)));
+            return;
+        }
+        self.search_thread = Some(std::thread::spawn(move || {
+            let result = search(&position, &limits, &options, &tt, stop.clone(), |info| {
+                let nps = info.nodes * 1000 / info.time_ms.max(1);
+                for (i, line) in info.lines.iter().enumerate() {
+                    let pv: Vec<String> = line.pv.iter().map(|chess_move| chess_move.to_uci()).collect();
+                    println!(
+                        "info depth {} multipv {} score {} nodes {} nps {} time {} pv {}",
+                        info.depth,
+                        i + 1,
+                        score_to_uci(line.score),
+                        info.nodes,
+                        nps,
+                        info.time_ms,
+                        pv.join(" ")
+                    );
+                }
+            });
+            let best_move = if skill < MAX_SKILL {
+                skill::choose_move(skill, &result.lines, &mut rand::rng()).or(result.best_move)
+            } else {
+                result.best_move
+            };
+            // The best move can't be sent while pondering or analysing, even if the search has finished
+            while (infinite || (pondering && ponder.load(Ordering::Relaxed))) && !stop.load(Ordering::Relaxed) {
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+            let expected_reply = result
+                .lines
+                .iter()
+                .find(|line| line.pv.first() == best_move.as_ref())
+                .and_then(|line| line.pv.get(1));
+            print_bestmove(best_move, expected_reply.copied());
+        }));
+    }
+}
+
+pub fn run() {
+    let mut uci = Uci {
+        position: Position::default(),
+        history: vec![],
+        move_overhead: SearchLimits::default().move_overhead,
+        options: SearchOptions::default(),
+        tt: Arc::new(TranspositionTable::new(DEFAULT_HASH_MB)),
+        stop: Arc::new(AtomicBool::new(false)),
+        ponder: Arc::new(AtomicBool::new(false)),
+        search_thread: None,
+        own_book: false,
+        book: None,
+        book_selection: BookSelection::WeightedRandom,
+        use_nnue: false,
+        network: None,
+        skill_level: MAX_SKILL,
+        limit_strength: false,
+        elo: MAX_ELO,
+    };
+
+    for line in std::io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let args: Vec<&str> = line.split_whitespace().collect();
+        let Some(command) = args.first() else {
+            continue;
+        };
+
+        match *command {
+            "uci" => {
+                println!("id name Chess (but better)");
+                println!("id author VeryCoolMike");
+                println!("option name Move Overhead type spin default {} min 0 max 5000", SearchLimits::default().move_overhead);
+                println!("option name Threads type spin default 1 min 1 max {}", MAX_THREADS);
+                println!("option name Hash type spin default {} min 1 max 65536", DEFAULT_HASH_MB);
+                println!("option name Ponder type check default false");
+                println!("option name MultiPV type spin default 1 min 1 max {}", MAX_MULTI_PV);
+                println!("option name SyzygyPath type string default <empty>");
+                println!("option name EndgamePath type string default <empty>");
+                println!("option name UseNNUE type check default false");
+                println!("option name EvalFile type string default <empty>");
+                println!("option name Skill Level type spin default {} min 0 max {}", MAX_SKILL, MAX_SKILL);
+                println!("option name UCI_LimitStrength type check default false");
+                println!("option name UCI_Elo type spin default {} min {} max {}", MAX_ELO, MIN_ELO, MAX_ELO);
+                println!("option name OwnBook type check default false");
+                println!("option name BookFile type string default <empty>");
+                println!("option name BookSelection type combo default Random var Random var Best");
+                println!("uciok");
+            }
+            "isready" => println!("readyok"),
+            "setoption" => uci.set_option(&args[1..]),
+            "ucinewgame" => {
+                uci.stop_search();
+                uci.position = Position::default();
+                uci.history.clear();
+                uci.tt.clear();
+            }
+            "position" => uci.set_position(&args[1..]),
+            "go" => uci.go(&args[1..]),
+            "d" => println!("info string fen {}", uci.position.to_fen()),
+            "eval" => {
+                println!("info string classical eval {}", evaluate(&uci.position));
+                if let Some(network) = &uci.network {
+                    println!("info string nnue eval {}", network.evaluate_position(&uci.position));
+                }
+            }
+            "ponderhit" => uci.ponder.store(false, Ordering::Relaxed),
+            "stop" => uci.stop_search(),
+            "quit" => break,
+            _ => println!("info string unknown command {}", line),
+        }
+    }
+
+    uci.stop_search();
+}
@@ -0,0 +1,93 @@
+use crate::position::Position;
+use crate::{PieceType, Team};
+
+const fn splitmix64(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    (state, z ^ (z >> 31))
+}
+
+// 12 pieces on 64 squares, then side to move, 4 castling rights and 8 en passant files
+const KEY_COUNT: usize = 12 * 64 + 1 + 4 + 8;
+const SIDE_KEY: usize = 12 * 64;
+const CASTLING_KEYS: usize = SIDE_KEY + 1;
+const EN_PASSANT_KEYS: usize = CASTLING_KEYS + 4;
+
+const KEYS: [u64; KEY_COUNT] = {
+    let mut keys = [0; KEY_COUNT];
+    let mut state = 0x1234_5678_9ABC_DEF0;
+    let mut i = 0;
+    while i < KEY_COUNT {
+        let (next_state, key) = splitmix64(state);
+        state = next_state;
+        keys[i] = key;
+        i += 1;
+    }
+    keys
+};
+
+fn piece_index(piece_type: PieceType, team: Team) -> usize {
+    let kind = match piece_type {
+        PieceType::Pawn => 0,
+        PieceType::Knight => 1,
+        PieceType::Bishop => 2,
+        PieceType::Rook => 3,
+        PieceType::Queen => 4,
+        _ => 5,
+    };
+    if team == Team::White { kind } else { kind + 6 }
+}
+
+pub fn hash(position: &Position) -> u64 {
+    let mut key = 0;
+    for x in 0..8 {
+        for y in 0..8 {
+            let piece = position.board[x][y];
+            if piece.team != Team::None {
+                key ^= KEYS[piece_index(piece.piece_type, piece.team) * 64 + y * 8 + x];
+            }
+        }
+    }
+
+    if position.current_team == Team::Black {
+        key ^= KEYS[SIDE_KEY];
+    }
+    for (i, allowed) in position.castling_rights().into_iter().enumerate() {
+        if allowed {
+            key ^= KEYS[CASTLING_KEYS + i];
+        }
+    }
+    if let Some(square) = position.en_passant {
+        key ^= KEYS[EN_PASSANT_KEYS + square[0] as usize];
+    }
+
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transpositions_hash_the_same() {
+        let start = Position::default();
+        let play = |moves: &[&str]| {
+            let mut position = start;
+            for text in moves {
+                position = position.make_move(position.parse_uci_move(text).unwrap());
+            }
+            position
+        };
+
+        assert_eq!(hash(&play(&["g1f3", "g8f6", "b1c3"])), hash(&play(&["b1c3", "g8f6", "g1f3"])));
+        assert_ne!(hash(&play(&["g1f3", "g8f6"])), hash(&play(&["g1f3"])));
+        assert_eq!(hash(&play(&["g1f3", "g8f6", "f3g1", "f6g8"])), hash(&start));
+        // Same squares but the kings have lost their castling rights
+        assert_ne!(
+            hash(&play(&["e2e4", "e7e5", "e1e2", "e8e7", "e2e1", "e7e8"])),
+            hash(&play(&["e2e4", "e7e5", "g1f3", "g8f6", "f3g1", "f6g8"]))
+        );
+    }
+}